@@ -0,0 +1,290 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::fs::File;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use crate::models::cnn_model::Embeddable;
+use crate::models::embedding_cache_model::{parse_cache, CacheEntry, EmbeddingCacheModel, CACHE_MAGIC};
+use crate::models::traversal_model::TraversalModel;
+use crate::controllers::io_controller::{bytes_checksum, collect_image_paths, file_checksum};
+use crate::controllers::journal_controller::STATE_DIR_NAME;
+use crate::controllers::index_controller::remove_index;
+
+const CACHE_FILE_NAME: &str = "embeddings.cache";
+
+/// Returns the file the embedding cache of `dir` is stored in.
+pub fn cache_path(dir: &Path) -> PathBuf {
+    dir.join(STATE_DIR_NAME).join(CACHE_FILE_NAME)
+}
+
+/// Reads the entries of a cache file, or no entries if there is no cache file yet.
+///
+/// # Returns
+///
+/// Returns the entries along with the length of the file up to the end of the last complete entry,
+/// otherwise returns an `io::Error` of kind `InvalidData` if the file is not a cache file.
+fn read_cache(path: &Path) -> io::Result<(Vec<CacheEntry>, usize)> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((vec![], 0)),
+        Err(err) => return Err(err)
+    };
+
+    parse_cache(&bytes)
+        .ok_or_else(|| { io::Error::new(io::ErrorKind::InvalidData, format!("{} is not an embedding cache, remove it with the cache clear command", path.display())) })
+}
+
+/// Combines the identity of a model with the checksum of the file it was loaded from.
+///
+/// # Arguments
+///
+/// * `model` - The model embeddings are computed with.
+/// * `model_file` - The weights or TorchScript file the model was loaded from, or `None` if the weights are embedded in the binary.
+///
+/// # Returns
+///
+/// Returns the identity of the model, otherwise returns an `io::Error` if `model_file` cannot be read.
+pub fn gen_model_identity<T: Embeddable + ?Sized>(model: &T, model_file: Option<&Path>) -> io::Result<u64> {
+    let file_checksum = model_file
+        .map(file_checksum)
+        .transpose()?;
+
+    Ok(bytes_checksum(format!("{} {:?}", model.identity(), file_checksum).as_bytes()))
+}
+
+/// Opens the embedding cache of `dir` for a model.
+///
+/// When new embeddings are stored, the cache file is created if there is none, and a trailing entry that an interrupted
+/// run only partly wrote is removed so new entries can be appended after the last complete one. Otherwise the cache file
+/// is only read, and nothing in `dir` is created or changed.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the cache is kept in.
+/// * `model` - The model embeddings are computed with.
+/// * `model_file` - The weights or TorchScript file the model was loaded from, or `None` if the weights are embedded in the binary.
+/// * `store` - Whether embeddings computed during the run are written to the cache file.
+///
+/// # Returns
+///
+/// Returns the cached embeddings of `model`, otherwise returns an `io::Error` if the cache file cannot be read or written.
+pub fn open_cache<T: Embeddable + ?Sized>(dir: &Path, model: &T, model_file: Option<&Path>, store: bool) -> io::Result<EmbeddingCacheModel> {
+    let identity = gen_model_identity(model, model_file)?;
+    let path = cache_path(dir);
+    let (entries, len) = read_cache(&path)?;
+
+    if !store {
+        return Ok(EmbeddingCacheModel::new(identity, entries, None));
+    }
+
+    if len == 0 {
+        fs::create_dir_all(dir.join(STATE_DIR_NAME))?;
+        fs::write(&path, CACHE_MAGIC)?;
+    }
+    else if fs::metadata(&path)?.len() > len as u64 {
+        File::options().write(true).open(&path)?.set_len(len as u64)?;
+    }
+
+    Ok(EmbeddingCacheModel::new(identity, entries, Some(path)))
+}
+
+/// Appends entries to a cache file, as opened by `open_cache`.
+pub fn append_cache_entries(path: &Path, entries: &[CacheEntry]) -> io::Result<()> {
+    let bytes: Vec<u8> = entries
+        .iter()
+        .flat_map(|entry| { entry.serialize() })
+        .collect();
+
+    File::options()
+        .append(true)
+        .open(path)?
+        .write_all(&bytes)
+}
+
+/// Removes the cached embeddings of images that are no longer anywhere in `dir`, along with any duplicate entries.
+///
+/// Every subdirectory is searched, including hidden ones, so images that were sorted into class directories keep their embeddings.
+/// Embeddings computed by other models are kept as long as their image is.
+///
+/// # Returns
+///
+/// Returns the number of entries that were kept and the number that were removed, otherwise returns an `io::Error`.
+pub fn prune_cache(dir: &Path) -> io::Result<(usize, usize)> {
+    let path = cache_path(dir);
+    let (entries, _) = read_cache(&path)?;
+
+    if entries.is_empty() {
+        return Ok((0, 0));
+    }
+
+    let checksums: HashSet<u64> = collect_image_paths(dir, &TraversalModel::new(usize::MAX, false, true))?
+        .iter()
+        .filter_map(|image_path| { file_checksum(image_path).ok() })
+        .collect();
+
+    let entry_count = entries.len();
+    let mut seen = HashSet::new();
+    let mut bytes = CACHE_MAGIC.to_vec();
+    let mut kept = 0;
+
+    for entry in entries {
+        if checksums.contains(&entry.checksum) && seen.insert((entry.identity, entry.checksum)) {
+            bytes.extend(entry.serialize());
+            kept += 1;
+        }
+    }
+
+    // Replace the cache file in one step so an interruption cannot lose the entries being kept
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, bytes)?;
+    fs::rename(&temp_path, &path)?;
+
+    Ok((kept, entry_count - kept))
+}
+
+/// Removes every cached embedding of `dir`, along with the nearest neighbour index built over them.
+///
+/// # Returns
+///
+/// Returns the number of entries that were removed, otherwise returns an `io::Error`.
+pub fn clear_cache(dir: &Path) -> io::Result<usize> {
+    let path = cache_path(dir);
+
+    // A file that is not a cache is removed all the same
+    let entry_count = read_cache(&path)
+        .map(|(entries, _)| { entries.len() })
+        .unwrap_or_default();
+
+    remove_index(dir)?;
+
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(entry_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tch::{Device, Kind, Tensor};
+    use tch::vision::image;
+    use tempfile::tempdir;
+    use crate::models::pipeline_model::PipelineModel;
+    use crate::models::preprocessing_model::{Normalization, PreprocessingModel, ResizeStrategy};
+    use crate::controllers::io_controller::gen_image_embeddings;
+    use super::*;
+
+    struct MockModel(&'static str);
+
+    impl Embeddable for MockModel {
+        fn preprocessing(&self) -> PreprocessingModel {
+            PreprocessingModel::new(ResizeStrategy::Stretch, 2, Normalization::Raw)
+        }
+
+        fn identity(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn embed_images(&self, images: &Tensor) -> Result<Tensor, tch::TchError> {
+            Ok(images.flatten(1, -1))
+        }
+    }
+
+    fn write_image(path: &Path, value: u8) {
+        image::save(&Tensor::full([3, 4, 4], value as i64, (Kind::Uint8, Device::Cpu)), path).unwrap();
+    }
+
+    #[test]
+    fn cached_embeddings_are_reused() {
+        let dir = tempdir().unwrap();
+        let model = MockModel("a");
+
+        write_image(&dir.path().join("a.png"), 1);
+        write_image(&dir.path().join("b.png"), 2);
+
+        let mut cache = open_cache(dir.path(), &model, None, true).unwrap();
+        let (embeddings, _, _) = gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::default(), Some(&mut cache)).unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+
+        // Renamed images are still found, and a new image is embedded
+        fs::rename(dir.path().join("b.png"), dir.path().join("c.png")).unwrap();
+        write_image(&dir.path().join("d.png"), 3);
+
+        let mut cache = open_cache(dir.path(), &model, None, true).unwrap();
+        let (cached_embeddings, _, _) = gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::default(), Some(&mut cache)).unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+        assert!(cached_embeddings[0].equal(&embeddings[0]));
+        assert!(cached_embeddings[1].equal(&embeddings[1]));
+
+        // Another model does not reuse the embeddings
+        let cache = open_cache(dir.path(), &MockModel("b"), None, true).unwrap();
+        assert_eq!(cache.entry_count(), 0);
+    }
+
+    #[test]
+    fn interrupted_entries_are_dropped_and_pruned() {
+        let dir = tempdir().unwrap();
+        let model = MockModel("a");
+
+        write_image(&dir.path().join("a.png"), 1);
+        write_image(&dir.path().join("b.png"), 2);
+
+        let mut cache = open_cache(dir.path(), &model, None, true).unwrap();
+        gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::new(1, 0, 1), Some(&mut cache)).unwrap();
+
+        // Half an entry left behind by an interrupted run is cut off
+        let mut file = File::options().append(true).open(cache_path(dir.path())).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+
+        assert_eq!(open_cache(dir.path(), &model, None, true).unwrap().entry_count(), 2);
+
+        fs::remove_file(dir.path().join("b.png")).unwrap();
+
+        assert_eq!(prune_cache(dir.path()).unwrap(), (1, 1));
+        assert_eq!(open_cache(dir.path(), &model, None, true).unwrap().entry_count(), 1);
+        assert_eq!(clear_cache(dir.path()).unwrap(), 1);
+        assert_eq!(clear_cache(dir.path()).unwrap(), 0);
+    }
+
+    #[test]
+    fn invalid_cache_file_returns_error() {
+        let dir = tempdir().unwrap();
+
+        fs::create_dir_all(dir.path().join(STATE_DIR_NAME)).unwrap();
+        fs::write(cache_path(dir.path()), "not a cache").unwrap();
+
+        assert!(open_cache(dir.path(), &MockModel("a"), None, true).is_err());
+        assert!(prune_cache(dir.path()).is_err());
+        assert_eq!(clear_cache(dir.path()).unwrap(), 0);
+        assert!(!cache_path(dir.path()).exists());
+    }
+
+    #[test]
+    fn unstored_cache_leaves_dir_unchanged() {
+        let dir = tempdir().unwrap();
+        let model = MockModel("a");
+
+        write_image(&dir.path().join("a.png"), 1);
+
+        // A dry run embeds every image without creating the cache
+        let mut cache = open_cache(dir.path(), &model, None, false).unwrap();
+        gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::default(), Some(&mut cache)).unwrap();
+
+        assert_eq!(cache.misses(), 1);
+        assert!(!dir.path().join(STATE_DIR_NAME).exists());
+
+        // Embeddings stored by an earlier run are still reused, and the cache file is left as it was
+        let mut cache = open_cache(dir.path(), &model, None, true).unwrap();
+        gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::default(), Some(&mut cache)).unwrap();
+        write_image(&dir.path().join("b.png"), 2);
+
+        let cache_bytes = fs::read(cache_path(dir.path())).unwrap();
+        let mut cache = open_cache(dir.path(), &model, None, false).unwrap();
+        gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::default(), Some(&mut cache)).unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(fs::read(cache_path(dir.path())).unwrap(), cache_bytes);
+    }
+}
\ No newline at end of file
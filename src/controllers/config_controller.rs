@@ -0,0 +1,107 @@
+use std::io;
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf};
+use crate::models::config_model::ConfigModel;
+
+/// Name of the project-local config file, looked up in the working directory.
+pub const PROJECT_CONFIG_NAME: &str = ".tensort.toml";
+
+/// Returns where the user-level config file is looked up.
+///
+/// Follows `$XDG_CONFIG_HOME`, falling back to `~/.config` (or `%APPDATA%` on Windows).
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| { !dir.is_empty() })
+        .map(PathBuf::from)
+        .or_else(|| { env::var_os("HOME").map(|home| { PathBuf::from(home).join(".config") }) })
+        .or_else(|| { env::var_os("APPDATA").map(PathBuf::from) })?;
+
+    Some(config_dir.join("tensort").join("config.toml"))
+}
+
+/// Merges a config file on top of `config`. A missing file is skipped.
+///
+/// # Arguments
+///
+/// * `config` - The configuration to merge into.
+/// * `path` - The config file to read.
+///
+/// # Returns
+///
+/// Returns an `io::Error` if the file exists but cannot be read or is malformed.
+pub fn merge_config_file(config: &mut ConfigModel, path: &Path) -> io::Result<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(());
+        },
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    config
+        .merge_toml(&text, path.to_path_buf())
+        .map_err(|message| { io::Error::new(io::ErrorKind::InvalidData, format!("Invalid config file {}: {}", path.display(), message)) })
+}
+
+/// Loads every configuration layer, from lowest to highest precedence:
+/// the user-level config file, the project-local `.tensort.toml`, then `TENSORT_*` environment variables.
+///
+/// Command line options take precedence over all of these and are applied when the arguments are parsed.
+///
+/// # Returns
+///
+/// Returns the merged `ConfigModel` along with the names of `TENSORT_*` environment variables that were ignored because they are not settings,
+/// otherwise returns an `io::Error` if a config file is invalid.
+pub fn load_config() -> io::Result<(ConfigModel, Vec<String>)> {
+    let mut config = ConfigModel::new();
+
+    if let Some(user_config_path) = user_config_path() {
+        merge_config_file(&mut config, &user_config_path)?;
+    }
+
+    merge_config_file(&mut config, Path::new(PROJECT_CONFIG_NAME))?;
+
+    let ignored_vars = config.merge_env(env::vars());
+
+    Ok((config, ignored_vars))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn merge_config_file_layers_files() {
+        let dir = tempdir().unwrap();
+        let user_config = dir.path().join("config.toml");
+        let project_config = dir.path().join(PROJECT_CONFIG_NAME);
+
+        fs::write(&user_config, "class_count = 4\nplacement = \"copy\"\n").unwrap();
+        fs::write(&project_config, "class_count = 6\n").unwrap();
+
+        let mut config = ConfigModel::new();
+        merge_config_file(&mut config, &user_config).unwrap();
+        merge_config_file(&mut config, &project_config).unwrap();
+        merge_config_file(&mut config, &dir.path().join("missing.toml")).unwrap();
+
+        assert_eq!(config.get("class-count").unwrap().value, "6");
+        assert_eq!(config.get("placement").unwrap().value, "copy");
+    }
+
+    #[test]
+    fn merge_config_file_reports_invalid_files() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join(PROJECT_CONFIG_NAME);
+
+        fs::write(&config_path, "class_count = five\n").unwrap();
+
+        let err = merge_config_file(&mut ConfigModel::new(), &config_path).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 1"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,30 @@
+use tch::{Cuda, Device};
+use crate::errors::DeviceUnavailableError;
+use crate::models::device_model::DeviceSelection;
+
+/// Works out the device the neural network runs on.
+///
+/// # Arguments
+///
+/// * `selection` - The device given on the command line or in a config file.
+///
+/// # Returns
+///
+/// Returns the device, otherwise returns a `DeviceUnavailableError` if the selected CUDA device does not exist.
+pub fn resolve_device(selection: DeviceSelection) -> Result<Device, DeviceUnavailableError> {
+    let cuda_device_count = usize::try_from(Cuda::device_count()).unwrap_or_default();
+
+    selection
+        .resolve(cuda_device_count)
+        .ok_or(DeviceUnavailableError { selection, cuda_device_count })
+}
+
+/// Limits the number of CPU threads the neural network uses, both within and across operations.
+///
+/// Must be called before the network runs, since the thread pool used across operations can only be sized once.
+pub fn set_thread_count(thread_count: usize) {
+    let thread_count = i32::try_from(thread_count).unwrap_or(i32::MAX);
+
+    tch::set_num_threads(thread_count);
+    tch::set_num_interop_threads(thread_count);
+}
\ No newline at end of file
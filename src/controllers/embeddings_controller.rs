@@ -1,8 +1,20 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::{mem, thread};
 use tch::vision::imagenet;
 use tch::Tensor;
+use crate::models::ann_index_model::HnswIndexModel;
+use crate::models::class_estimate_model::ClassEstimateModel;
+use crate::models::pipeline_model::default_worker_count;
 
 pub type Table<T> = Vec<Vec<T>>;
 
+/// Largest number of embeddings the silhouette score is computed over when estimating the class count from a neighbour graph.
+const SILHOUETTE_SAMPLE_SIZE: usize = 1000;
+
+/// Number of nearest neighbours searched for a part of a neighbour graph that falls apart, to link it to another part.
+const JOIN_SEARCH_COUNT: usize = 64;
+
 /// Calculates the cosine similarity between two tensors.
 pub fn cosine_similarity(t1: &Tensor, t2: &Tensor) -> f64 {
     let dot_product = t1.dot(t2);
@@ -135,6 +147,475 @@ pub fn cluster_embeddings(similarities: &[f64], similarity_threshold: f64, embed
     clusters
 }
 
+/// Calculates the mean silhouette coefficient of a clustering, using `1 - cosine similarity` as the distance.
+///
+/// For each embedding, `a` is its mean distance to the rest of its own class and `b` is its lowest mean
+/// distance to any other class. Its coefficient is `(b - a) / max(a, b)`, or `0.0` if it is alone in its class.
+///
+/// # Arguments
+///
+/// * `similarities` - The pairwise similarities between embeddings in row major order, as from `calc_pairwise_cosine_similarities`.
+/// * `embedding_count` - The total number of embeddings that were clustered.
+/// * `table` - The clustering to score, as from `cluster_embeddings`.
+///
+/// # Returns
+///
+/// Returns the mean coefficient over every embedding, or `-1.0` if fewer than two classes are populated.
+pub fn calc_silhouette_score(similarities: &[f64], embedding_count: usize, table: &[Vec<usize>]) -> f64 {
+    let classes: Vec<&Vec<usize>> = table
+        .iter()
+        .filter(|row| { !row.is_empty() })
+        .collect();
+
+    if classes.len() < 2 || embedding_count == 0 {
+        return -1.0;
+    }
+
+    let mean_distance = |embedding_index: usize, row: &[usize]| {
+        let total = row
+            .iter()
+            .filter(|other_index| { **other_index != embedding_index })
+            .map(|other_index| { 1.0 - similarities[embedding_index * embedding_count + other_index] })
+            .sum::<f64>();
+
+        let others = row
+            .iter()
+            .filter(|other_index| { **other_index != embedding_index })
+            .count();
+
+        total / others as f64
+    };
+
+    let mut score_sum = 0.0;
+
+    for (class_index, row) in classes.iter().enumerate() {
+
+        // Embeddings alone in their class contribute a score of zero
+        if row.len() < 2 {
+            continue;
+        }
+
+        for embedding_index in row.iter() {
+            let a = mean_distance(*embedding_index, row);
+            let b = classes
+                .iter()
+                .enumerate()
+                .filter(|(other_class_index, _)| { *other_class_index != class_index })
+                .map(|(_, other_row)| { mean_distance(*embedding_index, other_row) })
+                .fold(f64::INFINITY, f64::min);
+
+            let max = a.max(b);
+
+            if max > 0.0 {
+                score_sum += (b - a) / max;
+            }
+        }
+    }
+
+    score_sum / embedding_count as f64
+}
+
+/// Estimates how many classes a set of embeddings falls into.
+///
+/// Every class count from `2` up to `max_class_count` (and below the number of embeddings) is tried by
+/// clustering the embeddings the same way as a regular run, and the clustering is scored with `calc_silhouette_score`.
+///
+/// # Arguments
+///
+/// * `similarities` - The pairwise similarities between embeddings in row major order, as from `calc_pairwise_cosine_similarities`.
+/// * `embedding_count` - The total number of embeddings.
+/// * `max_class_count` - The largest class count to try.
+///
+/// # Returns
+///
+/// Returns a `ClassEstimateModel` holding the score of every candidate and the chosen class count.
+/// With fewer than three embeddings there is nothing to compare and a single class is chosen.
+pub fn estimate_class_count(similarities: &[f64], embedding_count: usize, max_class_count: usize) -> ClassEstimateModel {
+    let max_class_count = max_class_count.min(embedding_count.saturating_sub(1));
+    let mut scores = vec![];
+
+    for class_count in 2..=max_class_count {
+        let similarity_threshold = calc_similarity_threshold(similarities, class_count);
+        let table = cluster_embeddings(similarities, similarity_threshold, embedding_count, class_count);
+
+        scores.push((class_count, calc_silhouette_score(similarities, embedding_count, &table)));
+    }
+
+    ClassEstimateModel::new(scores, 1)
+}
+
+/// Links every embedding in an index to its nearest neighbours, searching from several threads.
+///
+/// # Returns
+///
+/// Returns the nearest neighbours of each embedding, most similar first, as a row of the table per embedding.
+pub fn gen_neighbour_graph(index: &HnswIndexModel, neighbour_count: usize) -> Table<usize> {
+    let node_count = index.node_count();
+    let chunk_size = node_count.div_ceil(default_worker_count()).max(1);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..node_count)
+            .step_by(chunk_size)
+            .map(|start| {
+                scope.spawn(move || {
+                    (start..(start + chunk_size).min(node_count))
+                        .map(|node| { index.nearest_neighbours(node, neighbour_count) })
+                        .collect::<Table<usize>>()
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| { worker.join().unwrap() })
+            .collect()
+    })
+}
+
+/// A possible merge of two clusters, ordered so the cheapest merge is popped first from a `BinaryHeap`.
+///
+/// The versions of both clusters are recorded so the merge can be skipped if either cluster changed since.
+#[derive(PartialEq, Debug)]
+struct MergeCandidate {
+    cost: f64,
+    clusters: (usize, usize),
+    versions: (usize, usize)
+}
+
+impl Eq for MergeCandidate {}
+
+impl Ord for MergeCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost
+            .total_cmp(&self.cost)
+            .then(other.clusters.cmp(&self.clusters))
+    }
+}
+
+impl PartialOrd for MergeCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Calculates how much merging two clusters increases the total squared distance of embeddings to the centroid of their cluster (Ward's criterion).
+fn calc_ward_cost(sum1: &[f32], size1: usize, sum2: &[f32], size2: usize) -> f64 {
+    let (size1, size2) = (size1 as f64, size2 as f64);
+    let distance = sum1
+        .iter()
+        .zip(sum2)
+        .map(|(value1, value2)| {
+            let difference = *value1 as f64 / size1 - *value2 as f64 / size2;
+            difference * difference
+        })
+        .sum::<f64>();
+
+    size1 * size2 / (size1 + size2) * distance
+}
+
+/// Finds the cluster holding the nearest neighbour of the embedding identifying a cluster, among the embeddings outside of it.
+///
+/// # Arguments
+///
+/// * `index` - The index holding the embeddings.
+/// * `cluster` - The cluster to search from.
+/// * `count` - How many neighbours to search through.
+/// * `owner` - Returns the cluster an embedding belongs to.
+///
+/// # Returns
+///
+/// Returns the nearest other cluster, or `None` if every neighbour found is inside the cluster.
+fn find_nearest_cluster(index: &HnswIndexModel, cluster: usize, count: usize, mut owner: impl FnMut(usize) -> usize) -> Option<usize> {
+    index
+        .nearest_neighbours(cluster, count)
+        .into_iter()
+        .map(|node| { owner(node) })
+        .find(|other| { *other != cluster })
+}
+
+/// Merges clusters of embeddings two at a time, always picking the cheapest merge by Ward's criterion among clusters that neighbour each other.
+///
+/// Every embedding starts out in a cluster of its own, and two clusters neighbour each other if any of their embeddings are
+/// linked in the neighbour graph, so only a few costs are computed for each merge instead of comparing every pair of embeddings.
+/// A merged cluster only keeps the neighbours that are cheapest to merge with, as many as each embedding has in the graph,
+/// and those it is the last neighbour of, so the work per merge does not grow with the size of the clusters.
+/// Parts of the graph that are not linked at all are joined last: while there are many, each is linked to the part holding
+/// the nearest neighbour the index finds for it, and once few are left, every part is linked to every other.
+///
+/// # Arguments
+///
+/// * `index` - The index the neighbour graph was built from, which holds the embeddings.
+/// * `graph` - The nearest neighbours of each embedding, as from `gen_neighbour_graph`.
+///
+/// # Returns
+///
+/// Returns every merge in the order it was made, each as the embedding identifying the merged cluster followed by the one identifying the cluster merged into it,
+/// until a single cluster is left.
+pub fn calc_ward_merges(index: &HnswIndexModel, graph: &Table<usize>) -> Vec<(usize, usize)> {
+    let node_count = index.node_count();
+    let neighbour_limit = graph.iter().map(|neighbours| { neighbours.len() }).max().unwrap_or_default().max(1);
+    let mut sums: Vec<Option<Vec<f32>>> = (0..node_count).map(|node| { Some(index.vector(node).to_vec()) }).collect();
+    let mut sizes = vec![1usize; node_count];
+    let mut versions = vec![0usize; node_count];
+    let mut parents: Vec<usize> = (0..node_count).collect();
+    let mut adjacency: Vec<HashSet<usize>> = vec![HashSet::new(); node_count];
+    let mut heap = BinaryHeap::new();
+
+    let find = |parents: &mut [usize], mut node: usize| {
+        while parents[node] != node {
+            parents[node] = parents[parents[node]];
+            node = parents[node];
+        }
+
+        node
+    };
+
+    let push_merge = |heap: &mut BinaryHeap<MergeCandidate>, sums: &[Option<Vec<f32>>], sizes: &[usize], versions: &[usize], cluster1: usize, cluster2: usize| {
+        if let (Some(sum1), Some(sum2)) = (&sums[cluster1], &sums[cluster2]) {
+            heap.push(MergeCandidate {
+                cost: calc_ward_cost(sum1, sizes[cluster1], sum2, sizes[cluster2]),
+                clusters: (cluster1, cluster2),
+                versions: (versions[cluster1], versions[cluster2])
+            });
+        }
+    };
+
+    // Neighbours are linked both ways, since an embedding is not always among the nearest neighbours of its own nearest neighbours
+    for (node, neighbours) in graph.iter().enumerate() {
+        for &neighbour in neighbours.iter().filter(|neighbour| { **neighbour != node }) {
+            adjacency[node].insert(neighbour);
+            adjacency[neighbour].insert(node);
+        }
+    }
+
+    for (node, neighbours) in adjacency.iter().enumerate() {
+        for &neighbour in neighbours.iter().filter(|neighbour| { **neighbour > node }) {
+            push_merge(&mut heap, &sums, &sizes, &versions, node, neighbour);
+        }
+    }
+
+    // Stale merges are dropped from the heap whenever they may have come to outnumber the rest
+    let mut compact_len = 2 * heap.len().max(node_count);
+    let mut merges = Vec::with_capacity(node_count.saturating_sub(1));
+
+    while merges.len() + 1 < node_count {
+        let Some(candidate) = heap.pop() else {
+
+            // The neighbour graph falls apart into unlinked parts
+            let remaining: Vec<usize> = (0..node_count)
+                .filter(|cluster| { sums[*cluster].is_some() })
+                .collect();
+
+            // A few parts are all linked to each other, costing no more than a pass over the embeddings
+            if remaining.len() * remaining.len() <= node_count {
+                for (i, &cluster1) in remaining.iter().enumerate() {
+                    for &cluster2 in &remaining[i + 1..] {
+                        adjacency[cluster1].insert(cluster2);
+                        adjacency[cluster2].insert(cluster1);
+                        push_merge(&mut heap, &sums, &sizes, &versions, cluster1, cluster2);
+                    }
+                }
+
+                continue;
+            }
+
+            // Many parts are each linked to the part holding the nearest neighbour the index finds for them,
+            // leaving the parts whose nearest neighbours all lie inside them for later
+            for &cluster in &remaining {
+                let nearest = find_nearest_cluster(index, cluster, neighbour_limit.max(JOIN_SEARCH_COUNT), |node| { find(&mut parents, node) });
+
+                if let Some(nearest) = nearest.filter(|nearest| { adjacency[cluster].insert(*nearest) }) {
+                    adjacency[nearest].insert(cluster);
+                    push_merge(&mut heap, &sums, &sizes, &versions, cluster, nearest);
+                }
+            }
+
+            // If every part was left, the first one is linked to the nearest part however far the search has to go
+            if heap.is_empty() {
+                let cluster = remaining[0];
+                let nearest = find_nearest_cluster(index, cluster, node_count, |node| { find(&mut parents, node) }).unwrap_or(remaining[1]);
+
+                adjacency[cluster].insert(nearest);
+                adjacency[nearest].insert(cluster);
+                push_merge(&mut heap, &sums, &sizes, &versions, cluster, nearest);
+            }
+
+            continue;
+        };
+
+        // Skip merges of clusters that have changed since the merge was considered
+        let (cluster1, cluster2) = candidate.clusters;
+
+        if candidate.versions != (versions[cluster1], versions[cluster2]) {
+            continue;
+        }
+
+        let Some(sum2) = sums[cluster2].take() else {
+            continue;
+        };
+
+        if let Some(sum1) = &mut sums[cluster1] {
+            sum1.iter_mut().zip(sum2).for_each(|(value1, value2)| { *value1 += value2 });
+        }
+
+        sizes[cluster1] += sizes[cluster2];
+        versions[cluster1] += 1;
+        versions[cluster2] += 1;
+        parents[cluster2] = cluster1;
+
+        // The merged cluster neighbours every cluster either of its parts did
+        for neighbour in mem::take(&mut adjacency[cluster2]) {
+            adjacency[neighbour].remove(&cluster2);
+
+            if neighbour != cluster1 {
+                adjacency[neighbour].insert(cluster1);
+                adjacency[cluster1].insert(neighbour);
+            }
+        }
+
+        // Only the neighbours cheapest to merge with are kept, along with those the link is the last one of
+        let mut costs: Vec<(f64, usize)> = match &sums[cluster1] {
+            Some(sum1) => adjacency[cluster1]
+                .iter()
+                .filter_map(|&neighbour| {
+                    sums[neighbour]
+                        .as_ref()
+                        .map(|sum| { (calc_ward_cost(sum1, sizes[cluster1], sum, sizes[neighbour]), neighbour) })
+                })
+                .collect(),
+            None => vec![]
+        };
+
+        costs.sort_by(|(cost1, neighbour1), (cost2, neighbour2)| { cost1.total_cmp(cost2).then(neighbour1.cmp(neighbour2)) });
+
+        for (rank, &(cost, neighbour)) in costs.iter().enumerate() {
+            if rank >= neighbour_limit && adjacency[neighbour].len() > 1 {
+                adjacency[cluster1].remove(&neighbour);
+                adjacency[neighbour].remove(&cluster1);
+                continue;
+            }
+
+            heap.push(MergeCandidate {
+                cost,
+                clusters: (cluster1, neighbour),
+                versions: (versions[cluster1], versions[neighbour])
+            });
+        }
+
+        if heap.len() > compact_len {
+            heap.retain(|candidate| { candidate.versions == (versions[candidate.clusters.0], versions[candidate.clusters.1]) });
+            compact_len = 2 * heap.len().max(node_count);
+        }
+
+        merges.push((cluster1, cluster2));
+    }
+
+    merges
+}
+
+/// Groups embeddings into classes by applying merges until `class_count` clusters are left.
+///
+/// # Arguments
+///
+/// * `merges` - The merges of the embeddings, as from `calc_ward_merges`.
+/// * `embedding_count` - The total number of embeddings that were merged.
+/// * `class_count` - The number of classes to group the embeddings into.
+///
+/// # Returns
+///
+/// Returns the indices of the embeddings in each class, ordered by the first embedding of each class.
+/// The table always has `class_count` rows, the last ones empty if there are fewer embeddings than classes.
+pub fn cut_merges(merges: &[(usize, usize)], embedding_count: usize, class_count: usize) -> Table<usize> {
+    let mut parents: Vec<usize> = (0..embedding_count).collect();
+
+    let find = |parents: &mut [usize], mut node: usize| {
+        while parents[node] != node {
+            parents[node] = parents[parents[node]];
+            node = parents[node];
+        }
+
+        node
+    };
+
+    for &(cluster1, cluster2) in merges.iter().take(embedding_count.saturating_sub(class_count)) {
+        let root1 = find(&mut parents, cluster1);
+        let root2 = find(&mut parents, cluster2);
+
+        parents[root2] = root1;
+    }
+
+    let mut rows = vec![usize::MAX; embedding_count];
+    let mut table: Table<usize> = vec![];
+
+    for embedding_index in 0..embedding_count {
+        let root = find(&mut parents, embedding_index);
+
+        if rows[root] == usize::MAX {
+            rows[root] = table.len();
+            table.push(vec![]);
+        }
+
+        table[rows[root]].push(embedding_index);
+    }
+
+    table.resize(table.len().max(class_count), vec![]);
+    table
+}
+
+/// Estimates how many classes a set of embeddings falls into from the merges of a neighbour graph.
+///
+/// Works like `estimate_class_count`, except that each candidate class count cuts the merges with `cut_merges`,
+/// and the silhouette score is computed over a scattered sample of at most `SILHOUETTE_SAMPLE_SIZE` embeddings
+/// so the pairwise similarities of every embedding are never needed.
+///
+/// # Arguments
+///
+/// * `index` - The index the merges were computed from, which holds the embeddings.
+/// * `merges` - The merges of the embeddings, as from `calc_ward_merges`.
+/// * `max_class_count` - The largest class count to try.
+///
+/// # Returns
+///
+/// Returns a `ClassEstimateModel` holding the score of every candidate and the chosen class count.
+pub fn estimate_class_count_from_merges(index: &HnswIndexModel, merges: &[(usize, usize)], max_class_count: usize) -> ClassEstimateModel {
+    let embedding_count = index.node_count();
+    let max_class_count = max_class_count.min(embedding_count.saturating_sub(1));
+    let sample_count = embedding_count.min(SILHOUETTE_SAMPLE_SIZE);
+
+    // Embeddings are sampled in the order of a multiplicative hash of their index, which scatters the sample
+    // without lining up with any regular pattern in the order the images were found
+    let mut sample: Vec<usize> = (0..embedding_count).collect();
+    sample.sort_by_key(|embedding_index| { (*embedding_index as u64).wrapping_mul(0x9e3779b97f4a7c15) });
+    sample.truncate(sample_count);
+    sample.sort();
+
+    let mut sample_indices = vec![None; embedding_count];
+
+    for (sample_index, embedding_index) in sample.iter().enumerate() {
+        sample_indices[*embedding_index] = Some(sample_index);
+    }
+
+    let similarities: Vec<f64> = sample
+        .iter()
+        .flat_map(|&embedding_index1| {
+            sample.iter().map(move |&embedding_index2| { index.similarity(embedding_index1, embedding_index2) as f64 })
+        })
+        .collect();
+
+    let mut scores = vec![];
+
+    for class_count in 2..=max_class_count {
+        let table: Table<usize> = cut_merges(merges, embedding_count, class_count)
+            .iter()
+            .map(|row| { row.iter().filter_map(|embedding_index| { sample_indices[*embedding_index] }).collect() })
+            .collect();
+
+        scores.push((class_count, calc_silhouette_score(similarities.as_slice(), sample_count, &table)));
+    }
+
+    ClassEstimateModel::new(scores, 1)
+}
+
 /// Takes a slice of tensors and returns a tensor which is an average of each dimension.
 pub fn calc_average_embedding(embeddings: &[&Tensor]) -> Tensor {
     let mut tensor_sum = Tensor::zeros_like(&embeddings[0]);
@@ -165,7 +646,17 @@ pub fn gen_default_class_names(class_count: usize) -> Vec<String> {
 
 /// Given a slice of embeddings and the sorted table,
 /// generates the most likely name for each classification (by averaging tensors).
-pub fn gen_class_names(embeddings: &[Tensor], table: &Table<usize>) -> Vec<String> {
+///
+/// # Arguments
+///
+/// * `embeddings` - The embedding of every image.
+/// * `table` - The indices of the embeddings in each class.
+/// * `class_scores` - Converts an average embedding into ImageNet class scores, as `Embeddable::class_scores`.
+///
+/// # Returns
+///
+/// Returns a name for each class, or `None` if the embeddings cannot be converted into ImageNet class scores.
+pub fn gen_class_names(embeddings: &[Tensor], table: &Table<usize>, class_scores: impl Fn(&Tensor) -> Option<Tensor>) -> Option<Vec<String>> {
     let class_count = table.len();
     let mut class_names = Vec::with_capacity(class_count);
 
@@ -184,7 +675,8 @@ pub fn gen_class_names(embeddings: &[Tensor], table: &Table<usize>) -> Vec<Strin
             .collect();
 
         let average_embedding = calc_average_embedding(row_embeddings.as_slice());
-        let class_name = match imagenet::top(&average_embedding, 1).first() {
+        let scores = class_scores(&average_embedding)?;
+        let class_name = match imagenet::top(&scores, 1).first() {
             Some(top_class_name) => format!("{} ({})", top_class_name.1, i + 1),
             None => String::new()
         };
@@ -192,7 +684,7 @@ pub fn gen_class_names(embeddings: &[Tensor], table: &Table<usize>) -> Vec<Strin
         class_names.push(class_name);
     }
 
-    class_names
+    Some(class_names)
 }
 
 #[cfg(test)]
@@ -270,6 +762,114 @@ mod tests {
         assert_eq!(table[1], vec![1, 2]);
     }
 
+    #[test]
+    fn calc_silhouette_score_prefers_separated_classes() {
+        let similarities = vec![
+            1.0, 0.1, 0.9, 0.1,
+            0.1, 1.0, 0.1, 0.9,
+            0.9, 0.1, 1.0, 0.1,
+            0.1, 0.9, 0.1, 1.0
+        ];
+
+        let separated = calc_silhouette_score(similarities.as_slice(), 4, &[vec![0, 2], vec![1, 3]]);
+        let mixed = calc_silhouette_score(similarities.as_slice(), 4, &[vec![0, 1], vec![2, 3]]);
+
+        assert_in_delta!(separated, 0.888889, 1e-6);
+        assert_lt!(mixed, 0.0);
+        assert_eq!(calc_silhouette_score(similarities.as_slice(), 4, &[vec![0, 1, 2, 3], vec![]]), -1.0);
+    }
+
+    #[test]
+    fn estimate_class_count_works() {
+        let similarities = vec![
+            1.0, 0.1, 0.9, 0.1,
+            0.1, 1.0, 0.1, 0.9,
+            0.9, 0.1, 1.0, 0.1,
+            0.1, 0.9, 0.1, 1.0
+        ];
+
+        let estimate = estimate_class_count(similarities.as_slice(), 4, 10);
+
+        assert_eq!(estimate.scores().len(), 2);
+        assert_eq!(estimate.chosen(), 2);
+
+        // Too few embeddings to compare
+        assert_eq!(estimate_class_count(&[1.0], 1, 10).chosen(), 1);
+    }
+
+    /// Builds an index over three groups of embeddings pointing in different directions.
+    fn gen_separated_index() -> HnswIndexModel {
+        let dimension = 6;
+        let values: Vec<f32> = (0..90 * dimension)
+            .map(|i| {
+                let (embedding_index, value_index) = (i / dimension, i % dimension);
+                let noise = ((i * 7919) % 101) as f32 / 400.0;
+
+                if value_index == (embedding_index % 3) * 2 { 1.0 + noise } else { noise }
+            })
+            .collect();
+
+        HnswIndexModel::build(0, values, dimension)
+    }
+
+    #[test]
+    fn cluster_from_neighbour_graph_works() {
+        let index = gen_separated_index();
+        let graph = gen_neighbour_graph(&index, 5);
+        let merges = calc_ward_merges(&index, &graph);
+
+        assert_eq!(graph.len(), 90);
+        assert_eq!(merges.len(), 89);
+
+        let table = cut_merges(&merges, 90, 3);
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(table[0], (0..90).step_by(3).collect::<Vec<_>>());
+        assert_eq!(table[1], (1..90).step_by(3).collect::<Vec<_>>());
+        assert_eq!(table[2], (2..90).step_by(3).collect::<Vec<_>>());
+
+        assert_eq!(cut_merges(&merges, 90, 1), vec![(0..90).collect::<Vec<_>>()]);
+        assert_eq!(cut_merges(&[], 2, 3), vec![vec![0], vec![1], vec![]]);
+        assert_eq!(estimate_class_count_from_merges(&index, &merges, 10).chosen(), 3);
+    }
+
+    #[test]
+    fn calc_ward_merges_joins_unlinked_parts() {
+        let index = gen_separated_index();
+        let graph: Table<usize> = (0..90).map(|embedding_index| { vec![(embedding_index + 3) % 90] }).collect();
+        let merges = calc_ward_merges(&index, &graph);
+
+        assert_eq!(merges.len(), 89);
+        assert_eq!(cut_merges(&merges, 90, 3)[0], (0..90).step_by(3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn calc_ward_merges_scales_to_fragmented_graphs() {
+        let dimension = 6;
+        let embedding_count = 3000;
+        let values: Vec<f32> = (0..embedding_count * dimension)
+            .map(|i| {
+                let (embedding_index, value_index) = (i / dimension, i % dimension);
+                let noise = (((i as u64).wrapping_mul(0x9e3779b97f4a7c15) >> 40) % 101) as f32 / 400.0;
+
+                if value_index == (embedding_index % 3) * 2 { 1.0 + noise } else { noise }
+            })
+            .collect();
+        let index = HnswIndexModel::build(0, values, dimension);
+
+        // Every embedding is only linked to one other of its group, so the graph falls apart into hundreds of parts
+        let graph: Table<usize> = (0..embedding_count).map(|embedding_index| { vec![embedding_index / 6 * 6 + (embedding_index + 3) % 6] }).collect();
+        let merges = calc_ward_merges(&index, &graph);
+
+        assert_eq!(merges.len(), embedding_count - 1);
+
+        let table = cut_merges(&merges, embedding_count, 3);
+
+        for (row, class) in table.iter().enumerate() {
+            assert_eq!(*class, (row..embedding_count).step_by(3).collect::<Vec<_>>());
+        }
+    }
+
     #[test]
     fn gen_default_class_names_works() {
         let class_count = 5;
@@ -295,11 +895,19 @@ mod tests {
         let t2 = Tensor::from_slice(&slice2);
         let embeddings = vec![t1, t2];
         let table = vec![vec![0], vec![1]];
-        let class_names = gen_class_names(embeddings.as_slice(), &table);
+        let class_names = gen_class_names(embeddings.as_slice(), &table, |embedding| { Some(embedding.shallow_clone()) }).unwrap();
 
         assert_eq!(class_names.len(), 2);
 
         assert_contains!(class_names[0], "tench");
         assert_contains!(class_names[1], "goldfish");
     }
+
+    #[test]
+    fn gen_class_names_requires_class_scores() {
+        let embeddings = vec![Tensor::from_slice(&[0.0; 512])];
+        let table = vec![vec![0], vec![]];
+
+        assert!(gen_class_names(embeddings.as_slice(), &table, |_| { None }).is_none());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,321 @@
+use std::io;
+use std::fs;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use safetensors::Dtype;
+use safetensors::tensor::TensorView;
+use serde_json::{json, Value};
+use tch::{Device, Kind, Tensor};
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::FileOptions;
+use crate::models::arguments_model::ArgumentsModel;
+use crate::models::export_model::{ExportFormat, ExportModel};
+use crate::models::preprocessing_model::Normalization;
+
+/// Bytes every NumPy `.npy` file starts with.
+pub const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Describes the model and preprocessing embeddings were generated with, to be exported alongside them.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run that generated the embeddings.
+/// * `model_file` - The weights or TorchScript file the model was loaded from, or `None` if the weights are embedded in the binary.
+///
+/// # Returns
+///
+/// Returns the name and value of each piece of metadata.
+pub fn gen_export_metadata(args: &ArgumentsModel, model_file: Option<&Path>) -> Vec<(&'static str, String)> {
+    let preprocessing = args.preprocessing();
+    let mut metadata = vec![("tensort_version", env!("CARGO_PKG_VERSION").to_string())];
+
+    match args.torchscript() {
+        Some(_) => {
+            metadata.push(("model", String::from("torchscript")));
+            metadata.push(("activation", args.activation().name().to_string()));
+        },
+        None => {
+            metadata.push(("model", args.architecture().name.to_string()));
+            metadata.push(("embedding", args.embedding().to_string()));
+            metadata.push(("augmentation", args.augmentation().to_string()));
+            metadata.push(("precision", args.precision().name().to_string()));
+        }
+    }
+
+    let model_file = model_file
+        .map(|model_file| { model_file.to_string_lossy().to_string() })
+        .unwrap_or(String::from("embedded"));
+
+    metadata.push(("model_file", model_file));
+    metadata.push(("resize", preprocessing.resize().name().to_string()));
+    metadata.push(("input_size", preprocessing.input_size().to_string()));
+    metadata.push(("normalization", preprocessing.normalization().name().to_string()));
+
+    if let Normalization::MeanStd { mean, std } = preprocessing.normalization() {
+        metadata.push(("mean", format!("{:?}", mean)));
+        metadata.push(("std", format!("{:?}", std)));
+    }
+
+    metadata
+}
+
+/// Formats metadata as a JSON object of strings.
+fn json_metadata(metadata: &[(&str, String)]) -> Value {
+    Value::Object(metadata
+        .iter()
+        .map(|(name, value)| { (name.to_string(), Value::from(value.as_str())) })
+        .collect())
+}
+
+/// Quotes a CSV field if it holds a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string()
+    }
+}
+
+/// Serializes an array in the NumPy `.npy` format (version 1.0).
+///
+/// # Arguments
+///
+/// * `descr` - The NumPy type of the values, such as `<f4`.
+/// * `shape` - The shape of the array, empty for a single value.
+/// * `data` - The values in row-major order.
+fn gen_npy(descr: &str, shape: &[usize], data: &[u8]) -> Vec<u8> {
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => format!("({})", shape.iter().map(usize::to_string).collect::<Vec<_>>().join(", "))
+    };
+
+    // The header is padded with spaces and ends with a line break so the data starts on a multiple of 64 bytes
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    let padding = (64 - (NPY_MAGIC.len() + 4 + header.len() + 1) % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut npy = NPY_MAGIC.to_vec();
+    npy.extend([1, 0]);
+    npy.extend((header.len() as u16).to_le_bytes());
+    npy.extend(header.as_bytes());
+    npy.extend(data);
+
+    npy
+}
+
+/// Serializes strings as a NumPy unicode array, which stores every string as UTF-32 padded to the longest one.
+fn gen_npy_strings(values: &[String], shape: &[usize]) -> Vec<u8> {
+    let width = values
+        .iter()
+        .map(|value| { value.chars().count() })
+        .max()
+        .unwrap_or_default()
+        .max(1);
+
+    let mut data = Vec::with_capacity(4 * width * values.len());
+
+    for value in values {
+        let len = value.chars().count();
+
+        data.extend(value.chars().flat_map(|c| { (c as u32).to_le_bytes() }));
+        data.extend(vec![0; 4 * (width - len)]);
+    }
+
+    gen_npy(&format!("<U{}", width), shape, &data)
+}
+
+/// Serializes embeddings as CSV, with a header row and then the path of each image followed by its embedding.
+fn gen_csv(values: &[f32], dimension: usize, paths: &[String]) -> String {
+    let mut csv = String::from("path");
+
+    for index in 0..dimension {
+        csv.push_str(&format!(",{}", index));
+    }
+
+    csv.push('\n');
+
+    for (index, path) in paths.iter().enumerate() {
+        csv.push_str(&csv_field(path));
+
+        for value in &values[index * dimension..(index + 1) * dimension] {
+            csv.push_str(&format!(",{}", value));
+        }
+
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Stacks embeddings into a matrix of floats.
+///
+/// # Returns
+///
+/// Returns the values of the matrix in row-major order along with its number of columns,
+/// otherwise returns an `io::Error` if the embeddings do not all have the same size.
+pub fn gen_embedding_matrix(embeddings: &[Tensor]) -> io::Result<(Vec<f32>, usize)> {
+    if embeddings.is_empty() {
+        return Ok((vec![], 0));
+    }
+
+    let rows: Vec<Tensor> = embeddings
+        .iter()
+        .map(|embedding| { embedding.to_device(Device::Cpu).to_kind(Kind::Float).flatten(0, -1) })
+        .collect();
+
+    let matrix = Tensor::f_stack(&rows, 0).map_err(io::Error::other)?;
+    let dimension = matrix.size()[1] as usize;
+    let values = Vec::<f32>::try_from(matrix.flatten(0, -1)).map_err(io::Error::other)?;
+
+    Ok((values, dimension))
+}
+
+/// Writes embeddings along with the path of each image and metadata about how they were generated.
+///
+/// The row of each embedding in the exported matrix matches the index of its image in the exported paths.
+/// Formats that cannot hold the paths and metadata (`npy` and `csv`) get them in a JSON file next to the export instead.
+///
+/// # Arguments
+///
+/// * `export` - Where and in which format the embeddings are written.
+/// * `embeddings` - The embedding of every image.
+/// * `image_paths` - The path of every image, in the same order as `embeddings`.
+/// * `metadata` - Describes how the embeddings were generated, as from `gen_export_metadata`.
+///
+/// # Returns
+///
+/// Returns the paths of every file that was written, otherwise returns an `io::Error`.
+pub fn export_embeddings(export: &ExportModel, embeddings: &[Tensor], image_paths: &[PathBuf], metadata: &[(&'static str, String)]) -> io::Result<Vec<PathBuf>> {
+    let (values, dimension) = gen_embedding_matrix(embeddings)?;
+    let matrix = Tensor::from_slice(&values)
+        .f_view([embeddings.len() as i64, dimension as i64])
+        .map_err(io::Error::other)?;
+    let paths: Vec<String> = image_paths
+        .iter()
+        .map(|image_path| { image_path.to_string_lossy().to_string() })
+        .collect();
+
+    let mut metadata = metadata.to_vec();
+    metadata.push(("count", embeddings.len().to_string()));
+    metadata.push(("dimension", dimension.to_string()));
+
+    match export.format() {
+        ExportFormat::Npy => matrix.write_npy(export.path()).map_err(io::Error::other)?,
+        ExportFormat::Csv => fs::write(export.path(), gen_csv(&values, dimension, &paths))?,
+        ExportFormat::Npz => {
+            Tensor::write_npz(&[("embeddings", &matrix)], export.path()).map_err(io::Error::other)?;
+
+            // tch only writes numeric arrays, so the string arrays are appended to its archive
+            let mut archive = ZipWriter::new_append(File::options().read(true).write(true).open(export.path())?)?;
+            let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+            for (name, npy) in [
+                ("paths.npy", gen_npy_strings(&paths, &[paths.len()])),
+                ("metadata.npy", gen_npy_strings(&[json_metadata(&metadata).to_string()], &[]))
+            ] {
+                archive.start_file(name, options)?;
+                archive.write_all(&npy)?;
+            }
+
+            archive.finish()?;
+        },
+        ExportFormat::SafeTensors => {
+            // Header metadata only holds strings, so the paths are given as a JSON array
+            let mut metadata: HashMap<String, String> = metadata
+                .iter()
+                .map(|(name, value)| { (name.to_string(), value.clone()) })
+                .collect();
+            metadata.insert(String::from("paths"), Value::from(paths.clone()).to_string());
+
+            // The writer of tch cannot add metadata, so the view is built from the values directly
+            let data: Vec<u8> = values
+                .iter()
+                .flat_map(|value| { value.to_le_bytes() })
+                .collect();
+            let view = TensorView::new(Dtype::F32, vec![embeddings.len(), dimension], &data).map_err(io::Error::other)?;
+
+            safetensors::serialize_to_file([("embeddings", view)], &Some(metadata), export.path()).map_err(io::Error::other)?
+        }
+    }
+
+    let mut written_paths = vec![export.path().to_path_buf()];
+
+    if let Some(sidecar_path) = export.sidecar_path() {
+        let sidecar = json!({ "metadata": json_metadata(&metadata), "paths": paths });
+
+        fs::write(&sidecar_path, format!("{}\n", serde_json::to_string_pretty(&sidecar).map_err(io::Error::other)?))?;
+        written_paths.push(sidecar_path);
+    }
+
+    Ok(written_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use super::*;
+
+    fn gen_embeddings() -> (Vec<Tensor>, Vec<PathBuf>) {
+        let embeddings = vec![Tensor::from_slice(&[1.0f32, 2.0, 3.0]), Tensor::from_slice(&[4.0f32, 5.0, 6.0])];
+        let image_paths = vec![PathBuf::from("a.png"), PathBuf::from("b, \"c\".png")];
+
+        (embeddings, image_paths)
+    }
+
+    #[test]
+    fn npy_and_safetensors_read_back() {
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+        let npy = ExportModel::new(&dir.path().join("vectors.npy")).unwrap();
+        let safetensors = ExportModel::new(&dir.path().join("vectors.safetensors")).unwrap();
+
+        let written_paths = export_embeddings(&npy, &embeddings, &image_paths, &[("model", String::from("resnet34"))]).unwrap();
+        assert_eq!(written_paths, vec![dir.path().join("vectors.npy"), dir.path().join("vectors.json")]);
+        assert!(Tensor::read_npy(npy.path()).unwrap().equal(&Tensor::stack(&embeddings, 0)));
+
+        let sidecar: Value = serde_json::from_str(&fs::read_to_string(dir.path().join("vectors.json")).unwrap()).unwrap();
+        assert_eq!(sidecar["metadata"]["model"], "resnet34");
+        assert_eq!(sidecar["paths"], json!(["a.png", "b, \"c\".png"]));
+
+        export_embeddings(&safetensors, &embeddings, &image_paths, &[]).unwrap();
+        let tensors = Tensor::read_safetensors(safetensors.path()).unwrap();
+
+        assert_eq!(tensors.len(), 1);
+        assert_eq!(tensors[0].0, "embeddings");
+        assert!(tensors[0].1.equal(&Tensor::stack(&embeddings, 0)));
+    }
+
+    #[test]
+    fn csv_quotes_paths() {
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+        let csv = ExportModel::new(&dir.path().join("vectors.csv")).unwrap();
+
+        export_embeddings(&csv, &embeddings, &image_paths, &[]).unwrap();
+
+        assert_eq!(fs::read_to_string(csv.path()).unwrap(), "path,0,1,2\na.png,1,2,3\n\"b, \"\"c\"\".png\",4,5,6\n");
+    }
+
+    #[test]
+    fn npy_strings_are_aligned_and_npz_holds_every_array() {
+        let npy = gen_npy_strings(&[String::from("ab"), String::from("é")], &[2]);
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+
+        assert_eq!((10 + header_len) % 64, 0);
+        assert!(String::from_utf8_lossy(&npy[10..10 + header_len]).starts_with("{'descr': '<U2', 'fortran_order': False, 'shape': (2,), }"));
+        assert_eq!(npy.len(), 10 + header_len + 16);
+
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+        let npz = ExportModel::new(&dir.path().join("vectors.npz")).unwrap();
+
+        export_embeddings(&npz, &embeddings, &image_paths, &[]).unwrap();
+        let archive = zip::ZipArchive::new(File::open(npz.path()).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+
+        assert_eq!(names, vec!["embeddings.npy", "metadata.npy", "paths.npy"]);
+    }
+}
@@ -0,0 +1,531 @@
+use std::io;
+use std::fs;
+use std::mem;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use safetensors::{SafeTensorError, SafeTensors};
+use serde_json::Value;
+use tch::{Kind, Tensor};
+use zip::ZipArchive;
+use zip::result::ZipError;
+use crate::models::export_model::ExportFormat;
+use crate::models::import_model::ImportModel;
+use crate::controllers::export_controller::NPY_MAGIC;
+
+/// Embeddings read from a file, as their values in row-major order, their number and size,
+/// and the path of each image if the file holds them.
+type EmbeddingMatrix = (Vec<f32>, [usize; 2], Option<Vec<String>>);
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Checks that a shape is that of a matrix with one embedding per row.
+fn matrix_shape(shape: &[usize]) -> io::Result<[usize; 2]> {
+    match shape {
+        [count, dimension] if *dimension > 0 => Ok([*count, *dimension]),
+        _ => Err(invalid_data(format!("the embeddings must be a matrix with one embedding per row, not of shape {:?}", shape)))
+    }
+}
+
+/// Reads the values of a matrix of floats with one embedding per row.
+///
+/// # Returns
+///
+/// Returns the values in row-major order and the shape of the matrix, otherwise returns an `io::Error`
+/// if the tensor does not hold floats or is not a matrix.
+fn matrix_values(tensor: &Tensor) -> io::Result<(Vec<f32>, [usize; 2])> {
+    if !matches!(tensor.kind(), Kind::Half | Kind::BFloat16 | Kind::Float | Kind::Double) {
+        return Err(invalid_data(format!("embeddings of type {:?} are not supported, they must be floats", tensor.kind())));
+    }
+
+    let shape: Vec<usize> = tensor.size().iter().map(|len| { *len as usize }).collect();
+    let shape = matrix_shape(&shape)?;
+    let values = tensor
+        .f_to_kind(Kind::Float)
+        .and_then(|values| { values.f_flatten(0, -1) })
+        .and_then(|values| { Vec::<f32>::try_from(values) })
+        .map_err(|err| { invalid_data(err.to_string()) })?;
+
+    Ok((values, shape))
+}
+
+/// Reads a string array of paths, as written to JSON files and safetensors metadata by an export.
+fn json_paths(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|path| { path.as_str().map(str::to_string) })
+        .collect()
+}
+
+/// Finds the value of a field in the header of a `.npy` file, which is a Python dictionary literal.
+fn npy_header_field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let value = header[header.find(&format!("'{}':", key))? + key.len() + 3..].trim_start();
+
+    let end = match value.chars().next()? {
+        '\'' => value[1..].find('\'')? + 2,
+        '(' => value.find(')')? + 1,
+        _ => value.find([',', '}'])?
+    };
+
+    Some(value[..end].trim_matches('\''))
+}
+
+/// Parses an array in the NumPy `.npy` format (versions 1.0 to 3.0).
+///
+/// # Returns
+///
+/// Returns the NumPy type of the values with its byte order removed, the shape of the array and its data,
+/// otherwise returns an `io::Error` if `bytes` is not a little endian array in C order.
+fn parse_npy(bytes: &[u8]) -> io::Result<(&str, Vec<usize>, &[u8])> {
+    let invalid = || { invalid_data("not a valid npy file") };
+
+    if !bytes.starts_with(NPY_MAGIC) {
+        return Err(invalid());
+    }
+
+    // Version 1.0 stores the length of the header in 2 bytes, later versions in 4
+    let (header_start, header_len): (usize, usize) = match bytes.get(NPY_MAGIC.len()) {
+        Some(1) => (10, read_u16(bytes, 8).ok_or_else(invalid)? as usize),
+        Some(2 | 3) => (12, read_u32(bytes, 8).ok_or_else(invalid)? as usize),
+        _ => return Err(invalid())
+    };
+
+    let header_end = header_start.checked_add(header_len).ok_or_else(invalid)?;
+    let header = bytes
+        .get(header_start..header_end)
+        .and_then(|header| { std::str::from_utf8(header).ok() })
+        .ok_or_else(invalid)?;
+
+    if npy_header_field(header, "fortran_order") != Some("False") {
+        return Err(invalid_data("arrays in Fortran order are not supported"));
+    }
+
+    let descr = npy_header_field(header, "descr").ok_or_else(invalid)?;
+    let shape = npy_header_field(header, "shape")
+        .ok_or_else(invalid)?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|len| { !len.is_empty() })
+        .map(|len| { len.parse::<usize>().map_err(|_| { invalid() }) })
+        .collect::<io::Result<Vec<usize>>>()?;
+
+    let descr = match descr.strip_prefix(['<', '|', '=']) {
+        Some(descr) => descr,
+        None => return Err(invalid_data(format!("arrays of type {} are not supported, they must be little endian", descr)))
+    };
+
+    Ok((descr, shape, &bytes[header_end..]))
+}
+
+/// Parses a matrix of floats in the NumPy `.npy` format.
+///
+/// The header is read here rather than by `Tensor::read_npy`, which cannot read the string arrays
+/// of an `.npz` archive and copies as many bytes as the header asks for even when the file is cut off.
+fn parse_npy_matrix(bytes: &[u8]) -> io::Result<(Vec<f32>, [usize; 2])> {
+    let (descr, shape, data) = parse_npy(bytes)?;
+    let shape = matrix_shape(&shape)?;
+
+    let kind = match descr {
+        "f2" => Kind::Half,
+        "f4" => Kind::Float,
+        "f8" => Kind::Double,
+        _ => return Err(invalid_data(format!("embeddings of type {} are not supported, they must be floats", descr)))
+    };
+
+    let len = shape[0]
+        .checked_mul(shape[1])
+        .and_then(|count| { count.checked_mul(kind.elt_size_in_bytes()) });
+
+    if len != Some(data.len()) {
+        return Err(invalid_data("the embeddings are cut off"));
+    }
+
+    let tensor = Tensor::f_from_data_size(data, &[shape[0] as i64, shape[1] as i64], kind)
+        .map_err(|err| { invalid_data(err.to_string()) })?;
+
+    matrix_values(&tensor)
+}
+
+/// Parses a NumPy unicode array of strings in the `.npy` format, which stores every string as UTF-32 padded to the longest one.
+fn parse_npy_strings(bytes: &[u8]) -> io::Result<Vec<String>> {
+    let (descr, shape, data) = parse_npy(bytes)?;
+
+    let width = descr
+        .strip_prefix('U')
+        .and_then(|width| { width.parse::<usize>().ok() })
+        .filter(|width| { *width > 0 && shape.len() == 1 && width.checked_mul(4 * shape[0]) == Some(data.len()) })
+        .ok_or_else(|| { invalid_data("the paths must be an array of strings") })?;
+
+    data.chunks_exact(4 * width)
+        .map(|value| {
+            value
+                .chunks_exact(4)
+                .map(|c| { u32::from_le_bytes([c[0], c[1], c[2], c[3]]) })
+                .take_while(|c| { *c != 0 })
+                .map(|c| { char::from_u32(c).ok_or_else(|| { invalid_data("the paths are not valid unicode") }) })
+                .collect()
+        })
+        .collect()
+}
+
+/// Reads a file from a ZIP archive, whether it is stored or compressed as by `numpy.savez_compressed`.
+///
+/// # Returns
+///
+/// Returns the contents of the file, `None` if the archive does not hold it, otherwise returns an `io::Error` if it cannot be read.
+fn read_zip_file(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(invalid_data(format!("not a valid npz archive: {}", err)))
+    };
+
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+
+    Ok(Some(bytes))
+}
+
+/// Reads the `embeddings` and `paths` arrays of a NumPy `.npz` archive, as written by an export, `numpy.savez` or `numpy.savez_compressed`.
+fn parse_npz(bytes: &[u8]) -> io::Result<EmbeddingMatrix> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|err| { invalid_data(format!("not a valid npz archive: {}", err)) })?;
+
+    let embeddings = read_zip_file(&mut archive, "embeddings.npy")?.ok_or_else(|| { invalid_data("the archive holds no embeddings array") })?;
+    let (values, shape) = parse_npy_matrix(&embeddings)?;
+    let paths = read_zip_file(&mut archive, "paths.npy")?
+        .map(|paths| { parse_npy_strings(&paths) })
+        .transpose()?;
+
+    Ok((values, shape, paths))
+}
+
+/// Reads the embeddings of a safetensors file, from the tensor named `embeddings` or the only tensor it holds,
+/// along with the paths stored in its metadata by an export.
+fn parse_safetensors(bytes: &[u8]) -> io::Result<EmbeddingMatrix> {
+    let invalid = |err: SafeTensorError| { invalid_data(format!("not a valid safetensors file: {}", err)) };
+
+    // Every offset and shape in the header is checked against the file before any tensor is read
+    let (_, metadata) = SafeTensors::read_metadata(bytes).map_err(invalid)?;
+    let safetensors = SafeTensors::deserialize(bytes).map_err(invalid)?;
+
+    let name = match safetensors.names().as_slice() {
+        names if names.iter().any(|name| { *name == "embeddings" }) => String::from("embeddings"),
+        [name] => name.to_string(),
+        _ => return Err(invalid_data("the file holds several tensors but none named embeddings"))
+    };
+
+    let tensor = safetensors
+        .tensor(&name)
+        .map_err(invalid)
+        .and_then(|view| { Tensor::try_from(view).map_err(|err| { invalid_data(err.to_string()) }) })?;
+    let (values, shape) = matrix_values(&tensor)?;
+
+    // Header metadata only holds strings, so an export stores the paths as a JSON array
+    let paths = metadata
+        .metadata()
+        .as_ref()
+        .and_then(|metadata| { metadata.get("paths") })
+        .and_then(|paths| { serde_json::from_str::<Value>(paths).ok() })
+        .and_then(|paths| { json_paths(&paths) });
+
+    Ok((values, shape, paths))
+}
+
+/// Splits CSV text into records of fields, handling quoted fields that hold separators, quotes or line breaks.
+fn parse_csv_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+            (true, '"') => quoted = false,
+            (false, '"') => quoted = true,
+            (false, ',') => record.push(mem::take(&mut field)),
+            (false, '\n') => {
+                record.push(mem::take(&mut field));
+                records.push(mem::take(&mut record));
+            },
+            (false, '\r') => {},
+            (_, c) => field.push(c)
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    // Blank lines hold no embedding
+    records
+        .into_iter()
+        .filter(|record| { record.len() > 1 || !record[0].is_empty() })
+        .collect()
+}
+
+/// Reads embeddings from CSV with a header row and then the path of an image followed by its embedding on each row, as written by an export.
+fn parse_csv(text: &str) -> io::Result<EmbeddingMatrix> {
+    let records = parse_csv_records(text);
+    let dimension = records.first().map(|header| { header.len().saturating_sub(1) }).unwrap_or_default();
+    let mut values = vec![];
+    let mut paths = vec![];
+
+    for (index, record) in records.iter().enumerate().skip(1) {
+        if record.len() != dimension + 1 {
+            return Err(invalid_data(format!("row {} has {} values instead of {}", index + 1, record.len() - 1, dimension)));
+        }
+
+        for value in &record[1..] {
+            values.push(value.trim().parse::<f32>().map_err(|_| { invalid_data(format!("row {} holds the invalid value {}", index + 1, value)) })?);
+        }
+
+        paths.push(record[0].clone());
+    }
+
+    Ok((values, matrix_shape(&[paths.len(), dimension])?, Some(paths)))
+}
+
+/// Reads the paths from the JSON file an export writes next to formats that cannot hold them.
+///
+/// # Returns
+///
+/// Returns the paths, `None` if there is no such file, otherwise returns an `io::Error` if the file cannot be read or holds no paths.
+fn read_sidecar_paths(sidecar_path: &Path) -> io::Result<Option<Vec<String>>> {
+    let text = match fs::read_to_string(sidecar_path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err)
+    };
+
+    // The parser stops at a nesting depth of 128, so a deeply nested file is rejected instead of overflowing the stack
+    serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|sidecar| { sidecar.get("paths").and_then(json_paths) })
+        .map(Some)
+        .ok_or_else(|| { invalid_data(format!("{} holds no paths", sidecar_path.display())) })
+}
+
+/// Reads the embeddings of an import, along with the paths stored with them.
+fn read_embedding_file(import: &ImportModel) -> io::Result<EmbeddingMatrix> {
+    let bytes = fs::read(import.path())?;
+
+    match import.format() {
+        ExportFormat::Npy => {
+            let (values, shape) = parse_npy_matrix(&bytes)?;
+            let paths = match import.sidecar_path() {
+                Some(sidecar_path) => read_sidecar_paths(&sidecar_path)?,
+                None => None
+            };
+
+            Ok((values, shape, paths))
+        },
+        ExportFormat::Npz => parse_npz(&bytes),
+        ExportFormat::SafeTensors => parse_safetensors(&bytes),
+        ExportFormat::Csv => parse_csv(std::str::from_utf8(&bytes).map_err(|_| { invalid_data("not a UTF-8 encoded CSV file") })?)
+    }
+}
+
+/// Reads embeddings that were computed elsewhere, such as by an earlier export or another system, so they can be clustered without loading a model.
+///
+/// The path of each image is read from the image list of the import if it has one, one path per line,
+/// otherwise from where an export stores them: the `paths` of an `.npz` archive or of the safetensors metadata,
+/// the first column of a `.csv` file, or the JSON file next to a `.npy` file.
+///
+/// # Arguments
+///
+/// * `import` - The file the embeddings are read from and where the paths of their images are found.
+///
+/// # Returns
+///
+/// Returns the embedding and the path of every image in the same order, otherwise returns an `io::Error`
+/// if the files cannot be read, are not valid, or do not hold a path for every embedding.
+pub fn import_embeddings(import: &ImportModel) -> io::Result<(Vec<Tensor>, Vec<PathBuf>)> {
+    let import_path = import.path().display();
+
+    let (values, [count, dimension], stored_paths) = read_embedding_file(import)
+        .map_err(|err| { io::Error::new(err.kind(), format!("Cannot import {}: {}", import_path, err)) })?;
+
+    let paths = match import.image_list() {
+        Some(image_list) => fs::read_to_string(image_list)
+            .map_err(|err| { io::Error::new(err.kind(), format!("Cannot read {}: {}", image_list.display(), err)) })?
+            .lines()
+            .filter(|line| { !line.is_empty() })
+            .map(str::to_string)
+            .collect(),
+        None => stored_paths.ok_or_else(|| { invalid_data(format!("{} holds no image paths, list them with --image-list", import_path)) })?
+    };
+
+    if paths.len() != count {
+        return Err(invalid_data(format!("{} holds {} embeddings but {} image paths were given", import_path, count, paths.len())));
+    }
+
+    let embeddings = values
+        .chunks_exact(dimension)
+        .map(Tensor::from_slice)
+        .collect();
+
+    Ok((embeddings, paths.into_iter().map(PathBuf::from).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::{CompressionMethod, ZipWriter};
+    use zip::write::FileOptions;
+    use crate::models::export_model::ExportModel;
+    use crate::controllers::export_controller::export_embeddings;
+    use super::*;
+
+    fn gen_embeddings() -> (Vec<Tensor>, Vec<PathBuf>) {
+        let embeddings = vec![Tensor::from_slice(&[1.0f32, 2.0, 3.0]), Tensor::from_slice(&[4.0f32, -5.5, 6.0])];
+        let image_paths = vec![PathBuf::from("a.png"), PathBuf::from("b, \"c\"\né.png")];
+
+        (embeddings, image_paths)
+    }
+
+    #[test]
+    fn exported_embeddings_import() {
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+
+        for file_name in ["vectors.npy", "vectors.npz", "vectors.safetensors", "vectors.csv"] {
+            let path = dir.path().join(file_name);
+
+            export_embeddings(&ExportModel::new(&path).unwrap(), &embeddings, &image_paths, &[]).unwrap();
+            let (imported_embeddings, imported_paths) = import_embeddings(&ImportModel::new(&path, None).unwrap()).unwrap();
+
+            assert_eq!(imported_paths, image_paths, "{}", file_name);
+            assert_eq!(imported_embeddings.len(), 2);
+            assert!(imported_embeddings[0].equal(&embeddings[0]), "{}", file_name);
+            assert!(imported_embeddings[1].equal(&embeddings[1]), "{}", file_name);
+        }
+    }
+
+    #[test]
+    fn image_list_provides_paths() {
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+        let path = dir.path().join("vectors.npy");
+        let image_list = dir.path().join("images.txt");
+
+        export_embeddings(&ExportModel::new(&path).unwrap(), &embeddings, &image_paths, &[]).unwrap();
+        fs::remove_file(dir.path().join("vectors.json")).unwrap();
+
+        assert!(import_embeddings(&ImportModel::new(&path, None).unwrap()).is_err());
+
+        fs::write(&image_list, "x.png\ny.png\n").unwrap();
+        let (_, imported_paths) = import_embeddings(&ImportModel::new(&path, Some(&image_list)).unwrap()).unwrap();
+
+        assert_eq!(imported_paths, vec![PathBuf::from("x.png"), PathBuf::from("y.png")]);
+
+        // Every embedding needs exactly one path
+        fs::write(&image_list, "x.png\n").unwrap();
+        assert!(import_embeddings(&ImportModel::new(&path, Some(&image_list)).unwrap()).is_err());
+    }
+
+    #[test]
+    fn compressed_npz_imports() {
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+        let path = dir.path().join("vectors.npz");
+
+        export_embeddings(&ExportModel::new(&path).unwrap(), &embeddings, &image_paths, &[]).unwrap();
+
+        // Compress every array of the archive, as numpy.savez_compressed does
+        let bytes = fs::read(&path).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+        let mut compressed = ZipWriter::new(Cursor::new(vec![]));
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).unwrap();
+            let mut contents = vec![];
+
+            file.read_to_end(&mut contents).unwrap();
+            compressed.start_file(file.name(), FileOptions::default().compression_method(CompressionMethod::Deflated)).unwrap();
+            compressed.write_all(&contents).unwrap();
+        }
+
+        fs::write(&path, compressed.finish().unwrap().into_inner()).unwrap();
+        let (imported_embeddings, imported_paths) = import_embeddings(&ImportModel::new(&path, None).unwrap()).unwrap();
+
+        assert_eq!(imported_paths, image_paths);
+        assert!(imported_embeddings[1].equal(&embeddings[1]));
+    }
+
+    #[test]
+    fn deeply_nested_sidecar_is_rejected() {
+        let dir = tempdir().unwrap();
+        let (embeddings, image_paths) = gen_embeddings();
+        let path = dir.path().join("vectors.npy");
+
+        export_embeddings(&ExportModel::new(&path).unwrap(), &embeddings, &image_paths, &[]).unwrap();
+        fs::write(dir.path().join("vectors.json"), format!("{{\"paths\": {}", "[".repeat(100_000))).unwrap();
+
+        assert!(import_embeddings(&ImportModel::new(&path, None).unwrap()).is_err());
+    }
+
+    #[test]
+    fn numpy_and_safetensors_variants_parse() {
+        // Version 2.0 headers, as written by NumPy for large headers
+        let gen_npy = |descr: &str, fortran_order: &str, data: &[u8]| {
+            let header = format!("{{'descr': '{}', 'fortran_order': {}, 'shape': (1, 2), }}\n", descr, fortran_order);
+            let mut npy = NPY_MAGIC.to_vec();
+
+            npy.extend([2, 0]);
+            npy.extend((header.len() as u32).to_le_bytes());
+            npy.extend(header.as_bytes());
+            npy.extend(data);
+            npy
+        };
+
+        let data: Vec<u8> = [1.5f64, 2.5].iter().flat_map(|value| { value.to_le_bytes() }).collect();
+        assert_eq!(parse_npy_matrix(&gen_npy("<f8", "False", &data)).unwrap(), (vec![1.5, 2.5], [1, 2]));
+        assert!(parse_npy_matrix(&gen_npy("<f8", "True", &data)).is_err());
+
+        // Half precision values are widened, and data that is cut off is rejected before it is read
+        assert_eq!(parse_npy_matrix(&gen_npy("<f2", "False", &[0x00, 0x3c, 0x00, 0xc1])).unwrap(), (vec![1.0, -2.5], [1, 2]));
+        assert!(parse_npy_matrix(&gen_npy("<f2", "False", &[0x00, 0x3c])).is_err());
+        assert!(parse_npy_matrix(&gen_npy("<i4", "False", &[0; 8])).is_err());
+
+        // Corrupt lengths and offsets are rejected instead of overflowing
+        let gen_safetensors = |header_len: u64, header: &str| {
+            let mut safetensors = header_len.to_le_bytes().to_vec();
+
+            safetensors.extend(header.as_bytes());
+            safetensors.extend(1f32.to_le_bytes());
+            safetensors
+        };
+
+        let header = "{\"embeddings\": {\"dtype\": \"F32\", \"shape\": [1, 1], \"data_offsets\": [0, 4]}}";
+        assert_eq!(parse_safetensors(&gen_safetensors(header.len() as u64, header)).unwrap().0, vec![1.0]);
+        assert!(parse_safetensors(&gen_safetensors(u64::MAX, header)).is_err());
+        assert!(parse_safetensors(&gen_safetensors(u64::MAX - 7, header)).is_err());
+
+        let header = "{\"embeddings\": {\"dtype\": \"F32\", \"shape\": [1, 1], \"data_offsets\": [1e300, 4]}}";
+        assert!(parse_safetensors(&gen_safetensors(header.len() as u64, header)).is_err());
+
+        let header = "{\"embeddings\": {\"dtype\": \"F32\", \"shape\": [4294967296, 4294967296], \"data_offsets\": [0, 4]}}";
+        assert!(parse_safetensors(&gen_safetensors(header.len() as u64, header)).is_err());
+
+        assert_eq!(parse_csv_records("path,0\n\"a,\"\"b\"\"\",1\r\n\nc,2"), vec![
+            vec![String::from("path"), String::from("0")],
+            vec![String::from("a,\"b\""), String::from("1")],
+            vec![String::from("c"), String::from("2")]
+        ]);
+    }
+}
\ No newline at end of file
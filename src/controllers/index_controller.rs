@@ -0,0 +1,106 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::models::ann_index_model::HnswIndexModel;
+use crate::controllers::io_controller::{bytes_checksum, update_checksum};
+use crate::controllers::journal_controller::STATE_DIR_NAME;
+
+const INDEX_FILE_NAME: &str = "embeddings.index";
+
+/// Returns the file the nearest neighbour index of `dir` is stored in, next to its embedding cache.
+pub fn index_path(dir: &Path) -> PathBuf {
+    dir.join(STATE_DIR_NAME).join(INDEX_FILE_NAME)
+}
+
+/// Identifies a matrix of embeddings by a checksum of its values, so an index is only reused for the exact same embeddings in the same order.
+/// The values are checksummed one at a time, so no copy of the matrix is made.
+fn gen_index_key(values: &[f32], dimension: usize) -> u64 {
+    values
+        .iter()
+        .fold(bytes_checksum(&(dimension as u64).to_le_bytes()), |checksum, value| { update_checksum(checksum, &value.to_le_bytes()) })
+}
+
+/// Builds a nearest neighbour index over a matrix of embeddings.
+///
+/// # Arguments
+///
+/// * `values` - The embeddings in row-major order.
+/// * `dimension` - The number of values of each embedding.
+pub fn build_index(values: Vec<f32>, dimension: usize) -> HnswIndexModel {
+    HnswIndexModel::build(gen_index_key(&values, dimension), values, dimension)
+}
+
+/// Loads the nearest neighbour index saved in `dir` if it was built over the same embeddings.
+///
+/// # Returns
+///
+/// Returns the index, `None` if there is no index or it was built over other embeddings,
+/// otherwise returns an `io::Error` if the index file cannot be read.
+pub fn load_index(dir: &Path, values: &[f32], dimension: usize) -> io::Result<Option<HnswIndexModel>> {
+    let bytes = match fs::read(index_path(dir)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err)
+    };
+
+    Ok(HnswIndexModel::parse(&bytes, gen_index_key(values, dimension), values, dimension))
+}
+
+/// Saves a nearest neighbour index in `dir`, replacing the index saved before.
+/// The state directory is created if there is none, so nothing should be saved during a dry run.
+pub fn save_index(dir: &Path, index: &HnswIndexModel) -> io::Result<()> {
+    let path = index_path(dir);
+    fs::create_dir_all(dir.join(STATE_DIR_NAME))?;
+
+    // Replace the index file in one step so an interruption cannot leave a partly written index
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, index.serialize())?;
+    fs::rename(&temp_path, &path)
+}
+
+/// Removes the nearest neighbour index saved in `dir`, if there is one.
+pub fn remove_index(dir: &Path) -> io::Result<()> {
+    match fs::remove_file(index_path(dir)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use super::*;
+
+    #[test]
+    fn saved_index_is_reused_for_same_embeddings() {
+        let dir = tempdir().unwrap();
+        let values: Vec<f32> = (0..64).map(|value| { (value as f32 * 0.37).sin() }).collect();
+        let index = build_index(values.clone(), 4);
+
+        assert_eq!(load_index(dir.path(), &values, 4).unwrap(), None);
+        save_index(dir.path(), &index).unwrap();
+        assert_eq!(load_index(dir.path(), &values, 4).unwrap(), Some(index));
+
+        // Changed embeddings need a new index
+        let mut changed = values.clone();
+        changed[5] += 1.0;
+        assert_eq!(load_index(dir.path(), &changed, 4).unwrap(), None);
+
+        remove_index(dir.path()).unwrap();
+        assert!(!index_path(dir.path()).exists());
+        remove_index(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn index_key_matches_checksum_of_values() {
+        let values = [0.5f32, -1.0, 2.25];
+        let bytes: Vec<u8> = 3u64
+            .to_le_bytes()
+            .into_iter()
+            .chain(values.iter().flat_map(|value| { value.to_le_bytes() }))
+            .collect();
+
+        // Checksumming value by value gives the key saved indexes were written with
+        assert_eq!(gen_index_key(&values, 3), bytes_checksum(&bytes));
+    }
+}
\ No newline at end of file
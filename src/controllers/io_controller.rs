@@ -1,10 +1,27 @@
 use std::io;
 use std::fs;
-use std::path::PathBuf;
-use tch::Tensor;
-use crate::models::cnn_model::Embeddable;
+use std::fs::{File, FileTimes};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::{mem, thread};
+use tch::{Device, Tensor};
+use crate::models::cnn_model::{EmbeddingBatch, Embeddable};
+use crate::models::embedding_cache_model::EmbeddingCacheModel;
+use crate::models::journal_model::JournalModel;
+use crate::models::pipeline_model::PipelineModel;
+use crate::models::sort_plan_model::{ConflictPolicyModel, DirConflictPolicy, FileConflictPolicy, PlacementStrategy, SortOutcome, SortPlanModel};
+use crate::models::traversal_model::TraversalModel;
+use crate::controllers::journal_controller::{gen_journal_entry, journal_path, record_created_dir, record_journal_entry, revert_journal, update_journal, write_journal, STATE_DIR_NAME};
+use crate::controllers::cache_controller::append_cache_entries;
 use crate::Table;
 
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
 /// Checks if the given file extension is an image extension.
 ///
 /// # Arguments
@@ -22,16 +39,227 @@ fn extension_is_image(extension: &str) -> bool {
     }
 }
 
+/// Checks if a file or directory name marks it as hidden.
+fn name_is_hidden(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| { name.starts_with('.') })
+}
+
+/// Recursively collects image paths from `dir` into `image_paths`.
+///
+/// # Arguments
+///
+/// * `dir` - The directory currently being searched.
+/// * `depth` - How many levels below the target directory `dir` is.
+/// * `traversal` - The options controlling the search.
+/// * `visited_dirs` - Canonical paths of every directory searched so far, used to detect symbolic link loops.
+/// * `image_paths` - The vector found image paths are appended to.
+fn collect_image_paths_in(
+    dir: &Path,
+    depth: usize,
+    traversal: &TraversalModel,
+    visited_dirs: &mut HashSet<PathBuf>,
+    image_paths: &mut Vec<PathBuf>
+) -> io::Result<()>
+{
+    // Never visit the same directory twice, which would otherwise loop forever through symbolic links
+    if !visited_dirs.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    // Sort entries so the traversal order does not depend on the filesystem
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| { entry.file_name() });
+
+    for entry in entries {
+        let name = entry.file_name();
+        let path = entry.path();
+
+        // Never descend into the directory tensort keeps its own state in
+        if name == STATE_DIR_NAME || (!traversal.include_hidden() && name_is_hidden(&name)) {
+            continue;
+        }
+
+        let mut file_type = entry.file_type()?;
+
+        // Resolve symbolic links to find out what they point to
+        if file_type.is_symlink() {
+            file_type = match fs::metadata(&path) {
+                Ok(metadata) => metadata.file_type(),
+                Err(_) => continue
+            };
+
+            if file_type.is_dir() && !traversal.follow_symlinks() {
+                continue;
+            }
+        }
+
+        if file_type.is_dir() {
+            if depth < traversal.max_depth() {
+                collect_image_paths_in(&path, depth + 1, traversal, visited_dirs, image_paths)?;
+            }
+
+            continue;
+        }
+
+        if let Some(extension) = path.extension().and_then(|extension| { extension.to_str() }) {
+            if extension_is_image(extension) {
+                image_paths.push(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the paths of every image in a directory.
+///
+/// # Arguments
+///
+/// * `dir` - A `Path` that represents the directory to search for images.
+/// * `traversal` - The options controlling how subdirectories, symbolic links and hidden files are handled.
+///
+/// # Returns
+///
+/// Returns the image paths in a deterministic order, otherwise returns an `io::Error`.
+///
+/// If the `dir` argument is not a directory, the function returns an `Err` with an `io::Error` of kind `InvalidInput`.
+pub fn collect_image_paths(dir: &Path, traversal: &TraversalModel) -> io::Result<Vec<PathBuf>> {
+    let mut image_paths = vec![];
+
+    if !dir.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path supplied is not a directory"));
+    }
+
+    collect_image_paths_in(dir, 0, traversal, &mut HashSet::new(), &mut image_paths)?;
+
+    Ok(image_paths)
+}
+
+/// Splits a batch into the embedding of each of its images, or `None` for each image that failed.
+fn unbind_batch(batch: EmbeddingBatch) -> Vec<Option<Tensor>> {
+    // The stacked embeddings only hold a row for each image that succeeded
+    let mut batch_embeddings = batch.embeddings
+        .map(|batch_embeddings| { batch_embeddings.unbind(0) })
+        .unwrap_or_default()
+        .into_iter();
+
+    batch.errors
+        .into_iter()
+        .map(|error| {
+            match error {
+                None => batch_embeddings.next(),
+                Some(_) => None
+            }
+        })
+        .collect()
+}
+
+/// Decodes `count` items on the worker threads of `pipeline` and passes them to `on_item` in order.
+///
+/// Items finish in whatever order the workers get to them, so each one waits until every earlier item has been taken.
+/// A worker only starts on an item fewer than `queue_depth` items ahead of the next one `on_item` takes,
+/// so an item that is slow to decode pauses the workers instead of letting them decode everything after it.
+fn decode_in_order<T: Send>(count: usize, pipeline: &PipelineModel, decode: impl Fn(usize) -> T + Sync, mut on_item: impl FnMut(T)) {
+    let queue_depth = pipeline.queue_depth().max(1);
+    let next_index = AtomicUsize::new(0);
+    let window_start = Mutex::new(0);
+    let window_moved = Condvar::new();
+    let (sender, receiver) = mpsc::sync_channel(queue_depth);
+
+    thread::scope(|scope| {
+        for _ in 0..pipeline.workers().min(count) {
+            let sender = sender.clone();
+            let (next_index, window_start, window_moved, decode) = (&next_index, &window_start, &window_moved, &decode);
+
+            // Each worker takes the next item in order until none are left or the model stops receiving
+            scope.spawn(move || {
+                loop {
+                    let index = next_index.fetch_add(1, Ordering::Relaxed);
+
+                    if index >= count {
+                        break;
+                    }
+
+                    let window = window_start.lock().unwrap();
+                    drop(window_moved.wait_while(window, |window_start| { index >= *window_start + queue_depth }).unwrap());
+
+                    if sender.send((index, decode(index))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        drop(sender);
+
+        let mut pending = HashMap::new();
+        let mut next = 0;
+
+        for (index, item) in receiver {
+            pending.insert(index, item);
+
+            while let Some(item) = pending.remove(&next) {
+                on_item(item);
+                next += 1;
+
+                *window_start.lock().unwrap() = next;
+                window_moved.notify_all();
+            }
+        }
+    });
+}
+
+/// Embeds images in batches, decoding them on the worker threads of `pipeline`.
+///
+/// Batches are always formed in the order of `image_paths`, however many workers there are,
+/// and each one is passed to `on_batch` in that order along with the index of its first image.
+fn embed_image_paths<T: Embeddable + ?Sized>(image_paths: &[PathBuf], model: &T, pipeline: &PipelineModel, mut on_batch: impl FnMut(usize, EmbeddingBatch)) {
+    let batch_size = pipeline.batch_size().max(1);
+
+    if pipeline.workers() == 0 {
+        for (batch_index, batch_paths) in image_paths.chunks(batch_size).enumerate() {
+            on_batch(batch_index * batch_size, model.gen_embeddings(batch_paths));
+        }
+
+        return;
+    }
+
+    let preprocessing = model.preprocessing();
+    let mut batch = vec![];
+    let mut batch_start = 0;
+
+    decode_in_order(image_paths.len(), pipeline, |index| { preprocessing.load_image(&image_paths[index]) }, |image| {
+        batch.push(image);
+
+        if batch.len() == batch_size {
+            on_batch(batch_start, model.embed_batch(mem::take(&mut batch)));
+            batch_start += batch_size;
+        }
+    });
+
+    if !batch.is_empty() {
+        on_batch(batch_start, model.embed_batch(batch));
+    }
+}
+
 /// Generates image embeddings for each image in a directory.
 ///
 /// This function takes a directory and a model that implements the `Embeddable` trait.
-/// It iterates over each file in the directory. If the file is an image (determined by the `extension_is_image` function),
-/// it generates an embedding using the `gen_embedding` method of the model.
+/// It collects each image in the directory (see `collect_image_paths`), decodes them on the worker threads of `pipeline`
+/// with the preprocessing of the model, and generates embeddings in batches using the `embed_batch` method of the model.
+/// Batches are always formed in the order the images were collected, however many workers there are.
+///
+/// When a cache is given, images whose embedding it holds are not passed through the model. The embedding of every other
+/// image is added to the cache and appended to its cache file, if it has one, as soon as its batch is done, so an interrupted
+/// run picks up where it stopped. Every embedding is then returned on the CPU.
 ///
 /// # Arguments
 ///
-/// * `dir` - A `PathBuf` that represents the directory to search for images.
+/// * `dir` - A `Path` that represents the directory to search for images.
+/// * `traversal` - The options controlling how the directory is searched.
 /// * `model` - A reference to an instance of a model that implements the `Embeddable` trait.
+/// * `pipeline` - The batch size, worker count and queue depth used to feed the model.
+/// * `cache` - The cached embeddings of `model`, as from `cache_controller::open_cache`, if any.
 ///
 /// # Returns
 ///
@@ -42,98 +270,721 @@ fn extension_is_image(extension: &str) -> bool {
 /// * A vector of `PathBuf` objects, each representing the path of an image that could not be processed.
 ///
 /// If the `dir` argument is not a directory, the function returns an `Err` with an `io::Error` of kind `InvalidInput`.
-pub fn gen_image_embeddings<T: Embeddable>(dir: &PathBuf, model: &T) -> io::Result<(Vec<Tensor>, Vec<PathBuf>, Vec<PathBuf>)> {
+/// An `io::Error` is also returned if the cache file cannot be written.
+pub fn gen_image_embeddings<T: Embeddable + ?Sized>(
+    dir: &Path,
+    traversal: &TraversalModel,
+    model: &T,
+    pipeline: &PipelineModel,
+    mut cache: Option<&mut EmbeddingCacheModel>
+) -> io::Result<(Vec<Tensor>, Vec<PathBuf>, Vec<PathBuf>)> {
+    let image_paths = collect_image_paths(dir, traversal)?;
+    let mut results: Vec<Option<Tensor>> = image_paths.iter().map(|_| { None }).collect();
+    let mut uncached_indices = vec![];
+    let mut uncached_checksums = vec![];
+
+    // Images are identified by their contents, so cached embeddings survive files being moved or renamed
+    for (index, image_path) in image_paths.iter().enumerate() {
+        let Some(cache) = cache.as_deref_mut() else {
+            uncached_indices.push(index);
+            continue;
+        };
+
+        let checksum = file_checksum(image_path).ok();
+
+        match cache.lookup(checksum) {
+            Some(embedding) => results[index] = Some(embedding),
+            None => {
+                uncached_indices.push(index);
+                uncached_checksums.push(checksum);
+            }
+        }
+    }
+
+    let uncached_paths: Vec<PathBuf> = uncached_indices
+        .iter()
+        .map(|index| { image_paths[*index].clone() })
+        .collect();
+
+    let mut cache_result = Ok(());
+
+    embed_image_paths(&uncached_paths, model, pipeline, |batch_start, batch| {
+        let mut entries = vec![];
+
+        for (offset, embedding) in unbind_batch(batch).into_iter().enumerate() {
+            let position = batch_start + offset;
+            let embedding = match (cache.as_deref_mut(), embedding) {
+                (Some(cache), Some(embedding)) => {
+                    let embedding = embedding.to_device(Device::Cpu);
+
+                    if let Some(entry) = uncached_checksums[position].and_then(|checksum| { cache.insert(checksum, &embedding) }) {
+                        entries.push(entry);
+                    }
+
+                    Some(embedding)
+                },
+                (_, embedding) => embedding
+            };
+
+            results[uncached_indices[position]] = embedding;
+        }
+
+        // Stop writing after the first failure, the error is reported once every image is embedded
+        if let Some(path) = cache.as_deref().and_then(|cache| { cache.path() }) {
+            if cache_result.is_ok() && !entries.is_empty() {
+                cache_result = append_cache_entries(path, &entries);
+            }
+        }
+    });
+
+    cache_result?;
+
     let mut embeddings = vec![];
     let mut images_paths = vec![];
     let mut missed_images_paths = vec![];
 
-    if !dir.is_dir() {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path supplied is not a directory"));
+    for (image_path, embedding) in image_paths.into_iter().zip(results) {
+        match embedding {
+            Some(embedding) => {
+                embeddings.push(embedding);
+                images_paths.push(image_path);
+            },
+            None => missed_images_paths.push(image_path)
+        }
     }
 
-    for file in fs::read_dir(dir)? {
-        let file = file?;
-        let path = file.path();
+    Ok((embeddings, images_paths, missed_images_paths))
+}
 
-        if let Some(extension) = path.extension().and_then(|extension| { extension.to_str() }) {
-            if extension_is_image(extension) {
-                match model.gen_embedding(&path) {
-                    Ok(embedding) => {
-                        embeddings.push(embedding);
-                        images_paths.push(path);
-                    },
-                    Err(_) => missed_images_paths.push(path)
-                }
-            }
+/// Checks if two files have exactly the same contents.
+pub fn files_are_identical(path1: &Path, path2: &Path) -> io::Result<bool> {
+    if fs::metadata(path1)?.len() != fs::metadata(path2)?.len() {
+        return Ok(false);
+    }
+
+    let mut reader1 = BufReader::new(File::open(path1)?);
+    let mut reader2 = BufReader::new(File::open(path2)?);
+
+    loop {
+        let buf1 = reader1.fill_buf()?;
+        let buf2 = reader2.fill_buf()?;
+        let len = buf1.len().min(buf2.len());
+
+        if len == 0 {
+            return Ok(buf1.is_empty() && buf2.is_empty());
         }
+
+        if buf1[..len] != buf2[..len] {
+            return Ok(false);
+        }
+
+        reader1.consume(len);
+        reader2.consume(len);
     }
+}
 
-    Ok((embeddings, images_paths, missed_images_paths))
+/// Appends a numbered suffix to the file stem of a path, for example `a.jpg` becomes `a_2.jpg`.
+fn with_counter(path: &Path, counter: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| { stem.to_str() }).unwrap_or_default();
+    let name = match path.extension().and_then(|extension| { extension.to_str() }) {
+        Some(extension) => format!("{}_{}.{}", stem, counter, extension),
+        None => format!("{}_{}", stem, counter)
+    };
+
+    path.with_file_name(name)
+}
+
+/// Picks the directory a class is placed in according to `policy`.
+fn resolve_class_dir(class_dir: PathBuf, policy: DirConflictPolicy) -> PathBuf {
+    if policy == DirConflictPolicy::Merge || !class_dir.exists() {
+        return class_dir;
+    }
+
+    let name = class_dir.file_name().and_then(|name| { name.to_str() }).unwrap_or_default().to_string();
+
+    (2..)
+        .map(|counter| { class_dir.with_file_name(format!("{} #{}", name, counter)) })
+        .find(|suffixed_class_dir| { !suffixed_class_dir.exists() })
+        .unwrap()
 }
 
-/// Uses the table generated from sorting and the generated class names.
-/// Will re-arrange the target `dir` to reflect the sorting results.
+/// Uses the table generated from sorting and the generated class names
+/// to compute how the images in `dir` should be re-arranged into `output_dir`.
+///
+/// Existing directories and files are only inspected, never changed, so every conflict
+/// is resolved according to `conflicts` before anything is moved.
 ///
 /// # Arguments
 ///
-/// * `dir` - A `PathBuf` that represents the target directory to be updated.
+/// * `dir` - A `Path` that represents the target directory the images were read from.
+/// * `output_dir` - A `Path` that represents the directory the class tree is built in. This may be the same as `dir`.
 /// * `image_paths` - A slice of `PathBuf` objects representing the paths of the images to be moved.
 /// * `class_names` - A slice of `String` objects representing the class names.
 /// * `table` - A reference to a `Table<usize>` object representing the sorting results.
+/// * `conflicts` - How existing class directories and destination files are handled.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the target directory is successfully updated, otherwise returns an `io::Error`.
-pub fn update_target_dir(
-    dir: &PathBuf,
+/// Returns a `SortPlanModel` containing every class directory to create and every file to move.
+/// Returns an `io::Error` if a destination file has different contents under the `identical` file policy.
+pub fn plan_target_dir(
+    dir: &Path,
+    output_dir: &Path,
     image_paths: &[PathBuf],
     class_names: &[String],
-    table: &Table<usize>
-) -> io::Result<()>
+    table: &Table<usize>,
+    conflicts: &ConflictPolicyModel
+) -> io::Result<SortPlanModel>
 {
-    let class_count = table.len();
+    let mut plan = SortPlanModel::new();
+
+    // Destinations already claimed by this plan, mapped to the file that will end up there
+    let mut planned_dests: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    // The output directory itself is created if it does not exist yet
+    if !output_dir.exists() {
+        plan.add_class_dir(output_dir.to_path_buf());
+    }
 
-    for i in 0..class_count {
-        let row = &table[i];
+    for (i, row) in table.iter().enumerate() {
 
         // Do not continue if there are empty classes
-        if row.len() == 0 {
+        if row.is_empty() {
             break;
         }
 
-        let class_dir = &dir.join(&class_names[i]);
+        let class_dir = resolve_class_dir(output_dir.join(&class_names[i]), conflicts.dirs());
 
         // Create the directory with the desired class name
-        fs::create_dir(class_dir)?;
+        if !class_dir.exists() {
+            plan.add_class_dir(class_dir.clone());
+        }
 
         // Move images into the new directory
         for &image_path_index in row {
             let src_image_path = &image_paths[image_path_index];
-            let dest_image_path = &class_dir.join(src_image_path.file_name().unwrap());
 
-            // Move the file
-            fs::rename(src_image_path, dest_image_path)?;
+            // Images from subdirectories keep their relative location so equal file names cannot collide
+            let relative_image_path = src_image_path
+                .strip_prefix(dir)
+                .unwrap_or_else(|_| { Path::new(src_image_path.file_name().unwrap()) });
+
+            let mut dest_image_path = class_dir.join(relative_image_path);
+            let mut replace = false;
+
+            // Record any nested directories that need to exist first
+            for ancestor in relative_image_path.ancestors().skip(1).collect::<Vec<_>>().iter().rev() {
+                let nested_dir = class_dir.join(ancestor);
+
+                if !ancestor.as_os_str().is_empty() && !nested_dir.exists() && !plan.class_dirs().contains(&nested_dir) {
+                    plan.add_class_dir(nested_dir);
+                }
+            }
+
+            // Resolve a conflict with an existing file or one placed earlier in this plan
+            let occupant = match planned_dests.get(&dest_image_path) {
+                Some(planned_src) => Some(planned_src.clone()),
+                None if fs::symlink_metadata(&dest_image_path).is_ok() => Some(dest_image_path.clone()),
+                None => None
+            };
+
+            if let Some(occupant) = occupant {
+                match conflicts.files() {
+                    FileConflictPolicy::Skip => {
+                        plan.add_skipped(src_image_path.clone());
+                        continue;
+                    },
+                    FileConflictPolicy::Rename => {
+                        // Numbered like suffixed class directories, the existing file being the first
+                        dest_image_path = (2..)
+                            .map(|counter| { with_counter(&dest_image_path, counter) })
+                            .find(|renamed| { !planned_dests.contains_key(renamed) && fs::symlink_metadata(renamed).is_err() })
+                            .unwrap();
+                    },
+                    FileConflictPolicy::Identical => {
+                        if !files_are_identical(src_image_path, &occupant)? {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AlreadyExists,
+                                format!("Refusing to overwrite {}, its contents differ from {}", dest_image_path.display(), src_image_path.display())
+                            ));
+                        }
+
+                        // An identical file placed earlier in this plan already covers this one
+                        if planned_dests.contains_key(&dest_image_path) {
+                            plan.add_skipped(src_image_path.clone());
+                            continue;
+                        }
+
+                        replace = true;
+                    }
+                }
+            }
+
+            planned_dests.insert(dest_image_path.clone(), src_image_path.clone());
+            plan.add_move(src_image_path.clone(), dest_image_path, replace);
         }
     }
 
+    Ok(plan)
+}
+
+/// Copies a file and carries over its permissions and timestamps.
+///
+/// # Arguments
+///
+/// * `src` - The file to copy.
+/// * `dest` - Where the copy should be created.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file and its metadata were copied, otherwise returns an `io::Error`.
+fn copy_with_metadata(src: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(src)?;
+
+    // Permissions are carried over by `fs::copy`
+    fs::copy(src, dest)?;
+
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+
+    // Setting the times of a file only takes owning it, so a read-only copy of a read-only source is not opened for writing
+    #[cfg(not(windows))]
+    let file = File::open(dest)?;
+
+    // Windows takes a handle with the right to write attributes instead, which is granted even for read-only files
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        File::options().access_mode(FILE_WRITE_ATTRIBUTES).open(dest)?
+    };
+
+    file.set_times(times)
+}
+
+/// Continues a 64-bit FNV-1a checksum with more bytes.
+pub fn update_checksum(mut checksum: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        checksum ^= byte as u64;
+        checksum = checksum.wrapping_mul(FNV_PRIME);
+    }
+
+    checksum
+}
+
+/// Computes a 64-bit FNV-1a checksum of some bytes.
+pub fn bytes_checksum(bytes: &[u8]) -> u64 {
+    update_checksum(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Computes a 64-bit FNV-1a checksum of a file's contents.
+pub fn file_checksum(path: &Path) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut checksum = FNV_OFFSET_BASIS;
+
+    loop {
+        let buf = reader.fill_buf()?;
+        let len = buf.len();
+
+        if len == 0 {
+            return Ok(checksum);
+        }
+
+        checksum = update_checksum(checksum, buf);
+        reader.consume(len);
+    }
+}
+
+/// Moves a file by copying it and then removing the source, which works across filesystems.
+///
+/// The copy is verified against the source by size and checksum before the source is removed.
+/// If copying, verification or removing the source fails, the copy is removed and the source is left untouched.
+///
+/// # Arguments
+///
+/// * `src` - The file to move.
+/// * `dest` - Where the file should be moved to.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file was moved, otherwise returns an `io::Error`.
+fn move_across_filesystems(src: &Path, dest: &Path) -> io::Result<()> {
+    let result = copy_with_metadata(src, dest)
+        .and_then(|_| { verify_copy(src, dest) })
+        .and_then(|_| { fs::remove_file(src) });
+
+    // A partial copy is removed as well, and the error that stopped the move is kept over any error removing it
+    if result.is_err() && dest.exists() {
+        let _ = fs::remove_file(dest);
+    }
+
+    result
+}
+
+/// Checks that a copy matches the file it was copied from by size and checksum.
+fn verify_copy(src: &Path, dest: &Path) -> io::Result<()> {
+    let verified = fs::metadata(src)?.len() == fs::metadata(dest)?.len()
+        && file_checksum(src)? == file_checksum(dest)?;
+
+    if !verified {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Copy of {} did not match the original", src.display())
+        ));
+    }
+
     Ok(())
 }
 
+/// Moves a file, falling back to a verified copy when `src` and `dest` are on different filesystems.
+pub fn move_file(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(src, dest) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => move_across_filesystems(src, dest),
+        result => result
+    }
+}
+
+/// Creates a symbolic link at `dest` pointing to the absolute location of `src`.
+fn symlink_file(src: &Path, dest: &Path) -> io::Result<()> {
+    let src = fs::canonicalize(src)?;
+
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(src, dest);
+
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(src, dest);
+}
+
+/// Places a single file at its destination using the given strategy.
+///
+/// # Arguments
+///
+/// * `src` - The file to place.
+/// * `dest` - The location the file should appear at.
+/// * `placement` - How the file should be placed.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file was placed, otherwise returns an `io::Error`.
+pub fn place_file(src: &Path, dest: &Path, placement: PlacementStrategy) -> io::Result<()> {
+    match placement {
+        PlacementStrategy::Move => move_file(src, dest),
+        PlacementStrategy::Copy => copy_with_metadata(src, dest),
+        PlacementStrategy::Symlink => symlink_file(src, dest),
+        PlacementStrategy::Hardlink => fs::hard_link(src, dest)
+    }
+}
+
+/// Returns the closest ancestor of `path` (including itself) that exists.
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|ancestor| { fs::symlink_metadata(ancestor).is_ok() })
+}
+
+/// Returns the number of bytes available to unprivileged users on the filesystem containing `path`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn available_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// Free space cannot be queried on this platform, so it is never checked.
+#[cfg(not(unix))]
+fn available_space(_: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Returns an identifier for the filesystem containing `path`, if the platform provides one.
+#[cfg(unix)]
+fn filesystem_id(path: &Path) -> io::Result<Option<u64>> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(Some(fs::metadata(path)?.dev()))
+}
+
+#[cfg(not(unix))]
+fn filesystem_id(_: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Checks that this process can create and remove entries in a directory.
+///
+/// The owner, group, ACLs and mount options of the directory are all taken into account, not only its permission bits.
+#[cfg(unix)]
+fn check_writable(dir: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(dir.as_os_str().as_bytes())?;
+
+    if unsafe { libc::access(c_path.as_ptr(), libc::W_OK | libc::X_OK) } != 0 {
+        let err = io::Error::last_os_error();
+
+        return Err(io::Error::new(
+            err.kind(),
+            format!("{} is not writable: {}", dir.display(), err)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that a directory is not read-only, the closest check available on this platform.
+#[cfg(not(unix))]
+fn check_writable(dir: &Path) -> io::Result<()> {
+    if fs::metadata(dir)?.permissions().readonly() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not writable", dir.display())
+        ));
+    }
+
+    Ok(())
+}
+
+/// Validates that a plan can be applied in full before anything is changed.
+///
+/// The following are checked:
+///
+/// * No two files are placed at the same destination and no destination is taken, unless it is an identical file being replaced.
+/// * No planned directory exists yet and each one has a parent that exists or is created earlier in the plan.
+/// * Every planned path is valid UTF-8, so that it can be recorded in the journal of the run.
+/// * Every source file can be read, and every directory that is written to is writable.
+/// * Every destination filesystem has enough free space for the data that has to be copied onto it.
+///
+/// # Arguments
+///
+/// * `plan` - A reference to the `SortPlanModel` to validate.
+/// * `placement` - How each file will be placed into its class directory.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the plan can be applied, otherwise returns an `io::Error` describing the first problem found.
+pub fn validate_plan(plan: &SortPlanModel, placement: PlacementStrategy) -> io::Result<()> {
+    let planned_dirs: HashSet<&Path> = plan.class_dirs().iter().map(|dir| { dir.as_path() }).collect();
+    let mut seen_dirs = HashSet::new();
+    let mut seen_dests = HashSet::new();
+    let mut writable_dirs = HashSet::new();
+    let mut required_space: HashMap<u64, (PathBuf, u64)> = HashMap::new();
+
+    let unrecordable = plan
+        .class_dirs()
+        .iter()
+        .chain(plan.moves().iter().flat_map(|planned_move| { [&planned_move.src, &planned_move.dest] }))
+        .find(|path| { path.to_str().is_none() });
+
+    if let Some(path) = unrecordable {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not valid UTF-8 and cannot be journaled", path.display())));
+    }
+
+    for dir in plan.class_dirs() {
+        if fs::symlink_metadata(dir).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", dir.display())));
+        }
+
+        match dir.parent() {
+            Some(parent) if seen_dirs.contains(parent) => {},
+            Some(parent) if parent.is_dir() => { writable_dirs.insert(parent.to_path_buf()); },
+            _ => return Err(io::Error::new(io::ErrorKind::NotFound, format!("Parent of {} does not exist", dir.display())))
+        }
+
+        seen_dirs.insert(dir.as_path());
+    }
+
+    for planned_move in plan.moves() {
+        let src = &planned_move.src;
+        let dest = &planned_move.dest;
+
+        if !seen_dests.insert(dest.as_path()) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("More than one file is placed at {}", dest.display())));
+        }
+
+        if fs::symlink_metadata(dest).is_ok() != planned_move.replace {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} changed since the sort was planned", dest.display())));
+        }
+
+        // Make sure the source can actually be read
+        let size = File::open(src)
+            .and_then(|file| { file.metadata() })
+            .map_err(|err| { io::Error::new(err.kind(), format!("Cannot read {}: {}", src.display(), err)) })?
+            .len();
+
+        let dest_parent = dest.parent().unwrap();
+
+        if !planned_dirs.contains(dest_parent) {
+            writable_dirs.insert(dest_parent.to_path_buf());
+        }
+
+        // Moving a file out of a directory changes that directory
+        if placement == PlacementStrategy::Move {
+            writable_dirs.insert(src.parent().unwrap().to_path_buf());
+        }
+
+        let dest_root = existing_ancestor(dest_parent).unwrap_or(dest_parent);
+        let (src_fs, dest_fs) = (filesystem_id(src)?, filesystem_id(dest_root)?);
+        let same_fs = src_fs == dest_fs;
+
+        if placement == PlacementStrategy::Hardlink && !same_fs {
+            return Err(io::Error::new(io::ErrorKind::CrossesDevices, format!("Cannot hardlink {} across filesystems", src.display())));
+        }
+
+        // Data is only written when copying, or when a move has to fall back to copying
+        let copied = placement == PlacementStrategy::Copy || (placement == PlacementStrategy::Move && !same_fs);
+
+        if let (true, Some(dest_fs)) = (copied, dest_fs) {
+            required_space.entry(dest_fs).or_insert_with(|| { (dest_root.to_path_buf(), 0) }).1 += size;
+        }
+    }
+
+    for dir in &writable_dirs {
+        check_writable(dir)?;
+    }
+
+    for (dest_root, size) in required_space.values() {
+        if let Some(available) = available_space(dest_root)? {
+            if available < *size {
+                return Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    format!("{} bytes are needed on the filesystem containing {} but only {} are available", size, dest_root.display(), available)
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies every step of a plan, recording each change in `journal` and in its file as soon as it is made.
+fn apply_plan(plan: &SortPlanModel, output_dir: &Path, placement: PlacementStrategy, journal: &mut JournalModel) -> io::Result<()> {
+    let mut class_dirs = plan.class_dirs();
+
+    // The journal is kept in the output directory, so a missing output directory is the only change made before it is written
+    if class_dirs.first().is_some_and(|dir| { dir == output_dir }) {
+        fs::create_dir(output_dir)?;
+        journal.add_created_dir(output_dir.to_path_buf());
+        class_dirs = &class_dirs[1..];
+    }
+
+    let journal_path = write_journal(output_dir, journal)?;
+
+    // Create the directories with the desired class names
+    for class_dir in class_dirs {
+        fs::create_dir(class_dir)?;
+        record_created_dir(&journal_path, journal, class_dir.clone())?;
+    }
+
+    // Place the files
+    for planned_move in plan.moves() {
+
+        // Only identical files are ever replaced, which was checked while planning
+        if planned_move.replace {
+            fs::remove_file(&planned_move.dest)?;
+        }
+
+        if let Err(err) = place_file(&planned_move.src, &planned_move.dest, placement) {
+
+            // Put back the identical file that was just removed, which the journal does not know about
+            if planned_move.replace {
+                if let Err(restore_err) = fs::copy(&planned_move.src, &planned_move.dest) {
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!("{}, and the identical file removed from {} could not be restored: {}", err, planned_move.dest.display(), restore_err)
+                    ));
+                }
+            }
+
+            return Err(err);
+        }
+
+        record_journal_entry(&journal_path, journal, gen_journal_entry(&planned_move.src, &planned_move.dest, planned_move.replace)?)?;
+    }
+
+    Ok(())
+}
+
+/// Applies a plan generated by `plan_target_dir` to the filesystem as a single transaction.
+///
+/// The journal of the run is written into `output_dir` before anything else is changed and grows with
+/// every change, so a run that is interrupted can still be undone. If any step fails, every change
+/// that was already applied is rolled back so the filesystem is left as it was before the sort.
+///
+/// # Arguments
+///
+/// * `plan` - A reference to the `SortPlanModel` to apply. It should be checked with `validate_plan` first.
+/// * `output_dir` - The directory the plan sorts into, which the journal is kept in.
+/// * `placement` - How each file should be placed into its class directory.
+/// * `journal` - A journal that every applied change is recorded into. Reverted changes are dropped from it and its file,
+///   so after a rollback it only lists the changes that are still applied.
+///
+/// # Returns
+///
+/// Returns a `SortOutcome` describing whether the sort was committed or rolled back.
+pub fn update_target_dir(plan: &SortPlanModel, output_dir: &Path, placement: PlacementStrategy, journal: &mut JournalModel) -> SortOutcome {
+    let cause = match apply_plan(plan, output_dir, placement, journal) {
+        Ok(()) => {
+            return SortOutcome::Committed { placed: journal.entries().len() };
+        },
+        Err(err) => err.to_string()
+    };
+
+    let journal_path = journal_path(output_dir, journal.run_id());
+    let reverted = revert_journal(&journal_path, journal);
+    let updated = update_journal(&journal_path, journal);
+
+    match (reverted, updated) {
+        (Ok(()), _) => SortOutcome::RolledBack { cause },
+        (Err(rollback_err), Ok(())) => SortOutcome::Failed { cause, rollback_cause: rollback_err.to_string() },
+        (Err(rollback_err), Err(journal_err)) => SortOutcome::Failed {
+            cause,
+            rollback_cause: format!("{}, and the journal could not be updated: {}", rollback_err, journal_err)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
     use std::io::Write;
+    use std::time::{Duration, SystemTime};
+    use tch::{Device, Kind};
+    use tch::vision::image;
     use tempfile::tempdir;
+    use crate::models::preprocessing_model::{Normalization, PreprocessingModel, ResizeStrategy};
+    use crate::controllers::journal_controller::read_journal;
 
     struct MockCNNModel;
 
+    // Embeds each image as its raw pixel values
     impl Embeddable for MockCNNModel {
-        fn gen_embedding(&self, _: &PathBuf) -> Result<Tensor, tch::TchError> {
-            Ok(Tensor::new())
+        fn preprocessing(&self) -> PreprocessingModel {
+            PreprocessingModel::new(ResizeStrategy::Stretch, 2, Normalization::Raw)
+        }
+
+        fn identity(&self) -> String {
+            String::from("mock")
+        }
+
+        fn embed_images(&self, images: &Tensor) -> Result<Tensor, tch::TchError> {
+            Ok(images.flatten(1, -1))
         }
     }
 
+    /// Writes an image whose pixels all have the value `value`.
+    fn write_image(path: &Path, value: u8) {
+        image::save(&Tensor::full([3, 4, 4], value as i64, (Kind::Uint8, Device::Cpu)), path).unwrap();
+    }
+
     #[test]
     fn extension_is_image_works() {
         assert!(extension_is_image("png"));
@@ -154,15 +1005,15 @@ mod tests {
     fn gen_image_embeddings_with_valid_input_works() {
         let model = MockCNNModel;
         let dir = tempdir().unwrap();
-        let img_path = dir.path().join("image.jpg");
+        let img_path = dir.path().join("image.png");
         let non_img_path = dir.path().join("file.txt");
 
         // Create an image file and a non-image file
-        File::create(&img_path).unwrap();
+        write_image(&img_path, 1);
         let mut file = File::create(&non_img_path).unwrap();
         writeln!(file, "Hello, world!").unwrap();
 
-        let (embeddings, images_paths, missed_images_paths) = gen_image_embeddings(&dir.path().to_path_buf(), &model).unwrap();
+        let (embeddings, images_paths, missed_images_paths) = gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &PipelineModel::default(), None).unwrap();
 
         // Check that the image file was processed and the non-image file was not
         assert_eq!(embeddings.len(), 1);
@@ -170,6 +1021,57 @@ mod tests {
         assert_eq!(missed_images_paths.len(), 0);
     }
 
+    #[test]
+    fn gen_image_embeddings_keeps_batches_in_order() {
+        let model = MockCNNModel;
+        let dir = tempdir().unwrap();
+
+        // The unreadable image fails without failing the rest of its batch
+        for (name, value) in [("a.png", 1), ("c.png", 3), ("d.png", 4), ("e.png", 5)] {
+            write_image(&dir.path().join(name), value);
+        }
+
+        fs::write(dir.path().join("b.png"), [69u8, 23]).unwrap();
+
+        // Decoding on the model thread and on any number of workers gives the same results
+        for pipeline in [PipelineModel::new(2, 0, 1), PipelineModel::new(2, 1, 1), PipelineModel::new(2, 4, 1), PipelineModel::new(3, 8, 64)] {
+            let (embeddings, images_paths, missed_images_paths) = gen_image_embeddings(dir.path(), &TraversalModel::default(), &model, &pipeline, None).unwrap();
+            let values: Vec<f64> = embeddings
+                .iter()
+                .map(|embedding| { embedding.double_value(&[0]) })
+                .collect();
+
+            assert_eq!(values, vec![1.0, 3.0, 4.0, 5.0]);
+            assert_eq!(images_paths, vec![dir.path().join("a.png"), dir.path().join("c.png"), dir.path().join("d.png"), dir.path().join("e.png")]);
+            assert_eq!(missed_images_paths, vec![dir.path().join("b.png")]);
+        }
+    }
+
+    #[test]
+    fn slow_item_does_not_grow_the_queue() {
+        let decoded = AtomicUsize::new(0);
+        let taken = AtomicUsize::new(0);
+        let most_waiting = AtomicUsize::new(0);
+        let mut order = vec![];
+
+        // While the first item is decoded, the other workers may only get a queue depth ahead of it
+        decode_in_order(200, &PipelineModel::new(4, 4, 8), |index| {
+            if index == 0 {
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            let waiting = (decoded.fetch_add(1, Ordering::SeqCst) + 1).saturating_sub(taken.load(Ordering::SeqCst));
+            most_waiting.fetch_max(waiting, Ordering::SeqCst);
+            index
+        }, |index| {
+            taken.fetch_add(1, Ordering::SeqCst);
+            order.push(index);
+        });
+
+        assert_eq!(order, (0..200).collect::<Vec<usize>>());
+        assert!(most_waiting.load(Ordering::SeqCst) <= 8);
+    }
+
     #[test]
     fn gen_image_embeddings_with_invalid_input_works() {
         let model = MockCNNModel;
@@ -180,11 +1082,465 @@ mod tests {
         File::create(&img_path).unwrap();
 
         // Attempt to process a non-existent directory
-        let result = gen_image_embeddings(&dir.path().join("non_existent"), &model);
+        let result = gen_image_embeddings(&dir.path().join("non_existent"), &TraversalModel::default(), &model, &PipelineModel::default(), None);
         assert!(result.is_err());
 
         // Attempt to process a non-directory
-        let result = gen_image_embeddings(&img_path, &model);
+        let result = gen_image_embeddings(&img_path, &TraversalModel::default(), &model, &PipelineModel::default(), None);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn plan_target_dir_does_not_touch_filesystem() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img1_path = dir_path.join("a.jpg");
+        let img2_path = dir_path.join("b.jpg");
+
+        File::create(&img1_path).unwrap();
+        File::create(&img2_path).unwrap();
+
+        let image_paths = vec![img1_path.clone(), img2_path.clone()];
+        let class_names = vec![String::from("Class 1"), String::from("Class 2")];
+        let table = vec![vec![1], vec![0]];
+        let plan = plan_target_dir(&dir_path, &dir_path, image_paths.as_slice(), class_names.as_slice(), &table, &ConflictPolicyModel::default()).unwrap();
+
+        assert_eq!(plan.class_dirs(), &[dir_path.join("Class 1"), dir_path.join("Class 2")]);
+        assert_eq!(plan.moves().len(), 2);
+        assert_eq!(plan.moves()[0].src, img2_path);
+        assert_eq!(plan.moves()[0].dest, dir_path.join("Class 1").join("b.jpg"));
+
+        // Nothing should have been created or moved
+        assert!(!dir_path.join("Class 1").exists());
+        assert!(img1_path.exists());
+        assert!(img2_path.exists());
+    }
+
+    #[test]
+    fn update_target_dir_applies_plan() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img_path = dir_path.join("a.jpg");
+
+        File::create(&img_path).unwrap();
+
+        let class_names = vec![String::from("Class 1")];
+        let table = vec![vec![0]];
+        let plan = plan_target_dir(&dir_path, &dir_path, std::slice::from_ref(&img_path), class_names.as_slice(), &table, &ConflictPolicyModel::default()).unwrap();
+        let mut journal = JournalModel::new(String::from("0"), PlacementStrategy::Move);
+
+        assert!(update_target_dir(&plan, &dir_path, PlacementStrategy::Move, &mut journal).is_committed());
+
+        assert!(!img_path.exists());
+        assert!(dir_path.join("Class 1").join("a.jpg").exists());
+        assert_eq!(journal.created_dirs(), &[dir_path.join(STATE_DIR_NAME), dir_path.join(STATE_DIR_NAME).join("journals"), dir_path.join("Class 1")]);
+        assert_eq!(journal.entries().len(), 1);
+        assert_eq!(journal.entries()[0].src, img_path);
+        assert_eq!(read_journal(&journal_path(&dir_path, "0")).unwrap(), journal);
+    }
+
+    #[test]
+    fn place_file_copy_preserves_source_and_timestamps() {
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("a.jpg");
+        let dest_path = dir.path().join("b.jpg");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        let mut file = File::create(&src_path).unwrap();
+        writeln!(file, "pixels").unwrap();
+        file.set_modified(modified).unwrap();
+
+        place_file(&src_path, &dest_path, PlacementStrategy::Copy).unwrap();
+
+        assert!(src_path.exists());
+        assert_eq!(fs::read(&src_path).unwrap(), fs::read(&dest_path).unwrap());
+        assert_eq!(fs::metadata(&dest_path).unwrap().modified().unwrap(), modified);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn place_file_copy_keeps_read_only_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("a.jpg");
+        let dest_path = dir.path().join("b.jpg");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000);
+
+        fs::write(&src_path, "pixels").unwrap();
+        File::options().write(true).open(&src_path).unwrap().set_modified(modified).unwrap();
+        fs::set_permissions(&src_path, fs::Permissions::from_mode(0o444)).unwrap();
+
+        place_file(&src_path, &dest_path, PlacementStrategy::Copy).unwrap();
+
+        assert_eq!(fs::metadata(&dest_path).unwrap().permissions().mode() & 0o777, 0o444);
+        assert_eq!(fs::metadata(&dest_path).unwrap().modified().unwrap(), modified);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn place_file_links_keep_source() {
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("a.jpg");
+        let symlink_path = dir.path().join("symlink.jpg");
+        let hardlink_path = dir.path().join("hardlink.jpg");
+
+        File::create(&src_path).unwrap();
+
+        place_file(&src_path, &symlink_path, PlacementStrategy::Symlink).unwrap();
+        place_file(&src_path, &hardlink_path, PlacementStrategy::Hardlink).unwrap();
+
+        assert!(src_path.exists());
+        assert!(fs::symlink_metadata(&symlink_path).unwrap().file_type().is_symlink());
+        assert!(hardlink_path.is_file());
+    }
+
+    #[test]
+    fn collect_image_paths_respects_depth_and_hidden() {
+        let dir = tempdir().unwrap();
+        let nested_dir = dir.path().join("2024").join("05");
+
+        fs::create_dir_all(&nested_dir).unwrap();
+        fs::create_dir(dir.path().join(".thumbnails")).unwrap();
+        File::create(dir.path().join("a.jpg")).unwrap();
+        File::create(dir.path().join(".hidden.jpg")).unwrap();
+        File::create(dir.path().join(".thumbnails").join("b.jpg")).unwrap();
+        File::create(nested_dir.join("c.png")).unwrap();
+
+        let top_level = collect_image_paths(dir.path(), &TraversalModel::default()).unwrap();
+        assert_eq!(top_level, vec![dir.path().join("a.jpg")]);
+
+        let shallow = collect_image_paths(dir.path(), &TraversalModel::new(1, false, false)).unwrap();
+        assert_eq!(shallow, vec![dir.path().join("a.jpg")]);
+
+        let deep = collect_image_paths(dir.path(), &TraversalModel::new(usize::MAX, false, false)).unwrap();
+        assert_eq!(deep, vec![nested_dir.join("c.png"), dir.path().join("a.jpg")]);
+
+        let hidden = collect_image_paths(dir.path(), &TraversalModel::new(usize::MAX, false, true)).unwrap();
+        assert_eq!(hidden.len(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn collect_image_paths_detects_symlink_loops() {
+        let dir = tempdir().unwrap();
+        let nested_dir = dir.path().join("nested");
+
+        fs::create_dir(&nested_dir).unwrap();
+        File::create(nested_dir.join("a.jpg")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), nested_dir.join("loop")).unwrap();
+
+        let not_followed = collect_image_paths(dir.path(), &TraversalModel::new(usize::MAX, false, false)).unwrap();
+        assert_eq!(not_followed, vec![nested_dir.join("a.jpg")]);
+
+        let followed = collect_image_paths(dir.path(), &TraversalModel::new(usize::MAX, true, false)).unwrap();
+        assert_eq!(followed, vec![nested_dir.join("a.jpg")]);
+    }
+
+    #[test]
+    fn plan_target_dir_keeps_relative_paths() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let image_paths = vec![dir_path.join("a").join("img.jpg"), dir_path.join("b").join("img.jpg")];
+        let class_names = vec![String::from("Class 1")];
+        let table = vec![vec![0, 1]];
+        let plan = plan_target_dir(&dir_path, &dir_path, image_paths.as_slice(), class_names.as_slice(), &table, &ConflictPolicyModel::default()).unwrap();
+        let class_dir = dir_path.join("Class 1");
+
+        assert_eq!(plan.class_dirs(), &[class_dir.clone(), class_dir.join("a"), class_dir.join("b")]);
+        assert_eq!(plan.moves()[0].dest, class_dir.join("a").join("img.jpg"));
+        assert_eq!(plan.moves()[1].dest, class_dir.join("b").join("img.jpg"));
+    }
+
+    #[test]
+    fn plan_target_dir_builds_tree_in_output_dir() {
+        let dir = tempdir().unwrap();
+        let output = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let output_dir_path = output.path().join("sorted");
+        let img_path = dir_path.join("a.jpg");
+
+        File::create(&img_path).unwrap();
+
+        let class_names = vec![String::from("Class 1")];
+        let table = vec![vec![0]];
+        let plan = plan_target_dir(&dir_path, &output_dir_path, std::slice::from_ref(&img_path), class_names.as_slice(), &table, &ConflictPolicyModel::default()).unwrap();
+        let mut journal = JournalModel::new(String::from("0"), PlacementStrategy::Copy);
+
+        assert_eq!(plan.class_dirs(), &[output_dir_path.clone(), output_dir_path.join("Class 1")]);
+
+        assert!(update_target_dir(&plan, &output_dir_path, PlacementStrategy::Copy, &mut journal).is_committed());
+        assert_eq!(read_journal(&journal_path(&output_dir_path, "0")).unwrap(), journal);
+
+        assert!(img_path.is_file());
+        assert!(output_dir_path.join("Class 1").join("a.jpg").is_file());
+        assert!(!dir_path.join("Class 1").exists());
+    }
+
+    #[test]
+    fn plan_target_dir_resolves_dir_conflicts() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img_path = dir_path.join("a.jpg");
+        let class_names = vec![String::from("Class 1")];
+        let table = vec![vec![0]];
+
+        File::create(&img_path).unwrap();
+        fs::create_dir(dir_path.join("Class 1")).unwrap();
+
+        let merged = plan_target_dir(&dir_path, &dir_path, std::slice::from_ref(&img_path), class_names.as_slice(), &table, &ConflictPolicyModel::default()).unwrap();
+        assert!(merged.class_dirs().is_empty());
+        assert_eq!(merged.moves()[0].dest, dir_path.join("Class 1").join("a.jpg"));
+
+        let conflicts = ConflictPolicyModel::new(DirConflictPolicy::Suffix, FileConflictPolicy::Rename);
+        let suffixed = plan_target_dir(&dir_path, &dir_path, std::slice::from_ref(&img_path), class_names.as_slice(), &table, &conflicts).unwrap();
+        assert_eq!(suffixed.class_dirs(), &[dir_path.join("Class 1 #2")]);
+        assert_eq!(suffixed.moves()[0].dest, dir_path.join("Class 1 #2").join("a.jpg"));
+    }
+
+    #[test]
+    fn plan_target_dir_resolves_file_conflicts() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img_path = dir_path.join("a.jpg");
+        let existing_path = dir_path.join("Class 1").join("a.jpg");
+        let class_names = vec![String::from("Class 1")];
+        let table = vec![vec![0]];
+        let plan_with = |files| {
+            let conflicts = ConflictPolicyModel::new(DirConflictPolicy::Merge, files);
+            plan_target_dir(&dir_path, &dir_path, std::slice::from_ref(&img_path), class_names.as_slice(), &table, &conflicts)
+        };
+
+        fs::create_dir(dir_path.join("Class 1")).unwrap();
+        fs::write(&img_path, "pixels").unwrap();
+        fs::write(&existing_path, "other pixels").unwrap();
+
+        let skipped = plan_with(FileConflictPolicy::Skip).unwrap();
+        assert!(skipped.moves().is_empty());
+        assert_eq!(skipped.skipped(), std::slice::from_ref(&img_path));
+
+        let renamed = plan_with(FileConflictPolicy::Rename).unwrap();
+        assert_eq!(renamed.moves()[0].dest, dir_path.join("Class 1").join("a_2.jpg"));
+
+        assert!(plan_with(FileConflictPolicy::Identical).is_err());
+
+        fs::write(&existing_path, "pixels").unwrap();
+
+        let replaced = plan_with(FileConflictPolicy::Identical).unwrap();
+        assert_eq!(replaced.moves()[0].dest, existing_path);
+        assert!(replaced.moves()[0].replace);
+    }
+
+    #[test]
+    fn file_checksum_works() {
+        let dir = tempdir().unwrap();
+        let empty_path = dir.path().join("empty");
+        let hello_path = dir.path().join("hello");
+
+        fs::write(&empty_path, "").unwrap();
+        fs::write(&hello_path, "hello").unwrap();
+
+        // Reference values of 64-bit FNV-1a
+        assert_eq!(file_checksum(&empty_path).unwrap(), 0xcbf29ce484222325);
+        assert_eq!(file_checksum(&hello_path).unwrap(), 0xa430d84680aabd0b);
+        assert_eq!(bytes_checksum(b"hello"), 0xa430d84680aabd0b);
+    }
+
+    #[test]
+    fn move_across_filesystems_verifies_and_removes_source() {
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("a.jpg");
+        let dest_path = dir.path().join("b.jpg");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000);
+
+        fs::write(&src_path, "pixels").unwrap();
+        File::options().write(true).open(&src_path).unwrap().set_modified(modified).unwrap();
+
+        move_across_filesystems(&src_path, &dest_path).unwrap();
+
+        assert!(!src_path.exists());
+        assert_eq!(fs::read_to_string(&dest_path).unwrap(), "pixels");
+        assert_eq!(fs::metadata(&dest_path).unwrap().modified().unwrap(), modified);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_across_filesystems_moves_read_only_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("a.jpg");
+        let dest_path = dir.path().join("b.jpg");
+
+        fs::write(&src_path, "pixels").unwrap();
+        fs::set_permissions(&src_path, fs::Permissions::from_mode(0o444)).unwrap();
+
+        move_across_filesystems(&src_path, &dest_path).unwrap();
+
+        assert!(!src_path.exists());
+        assert_eq!(fs::read_to_string(&dest_path).unwrap(), "pixels");
+        assert_eq!(fs::metadata(&dest_path).unwrap().permissions().mode() & 0o777, 0o444);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_across_filesystems_removes_copy_on_failure() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let locked_dir = dir.path().join("locked");
+        let src_path = locked_dir.join("a.jpg");
+        let dest_path = dir.path().join("b.jpg");
+
+        fs::create_dir(&locked_dir).unwrap();
+        fs::write(&src_path, "pixels").unwrap();
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // The source cannot be removed from a locked directory, except by the superuser
+        let is_superuser = unsafe { libc::geteuid() } == 0;
+        let result = move_across_filesystems(&src_path, &dest_path);
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(result.is_ok(), is_superuser);
+        assert_eq!(src_path.exists(), !is_superuser);
+        assert_eq!(dest_path.exists(), is_superuser);
+
+        // A copy that cannot be made leaves nothing behind
+        assert!(move_across_filesystems(&dir.path().join("missing.jpg"), &dest_path.with_file_name("c.jpg")).is_err());
+        assert!(!dir.path().join("c.jpg").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_writable_asks_the_filesystem() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let locked_dir = dir.path().join("locked");
+
+        assert!(check_writable(dir.path()).is_ok());
+        assert!(check_writable(&dir.path().join("missing")).is_err());
+
+        fs::create_dir(&locked_dir).unwrap();
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Permission bits do not stop the superuser, so only other users are refused
+        let is_superuser = unsafe { libc::geteuid() } == 0;
+        assert_eq!(check_writable(&locked_dir).is_ok(), is_superuser);
+    }
+
+    #[test]
+    fn validate_plan_detects_problems() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img_path = dir_path.join("a.jpg");
+
+        File::create(&img_path).unwrap();
+
+        let mut plan = SortPlanModel::new();
+        plan.add_class_dir(dir_path.join("Class 1"));
+        plan.add_move(img_path.clone(), dir_path.join("Class 1").join("a.jpg"), false);
+
+        assert!(validate_plan(&plan, PlacementStrategy::Move).is_ok());
+
+        // Two files placed at the same destination
+        plan.add_move(img_path.clone(), dir_path.join("Class 1").join("a.jpg"), false);
+        assert!(validate_plan(&plan, PlacementStrategy::Move).is_err());
+
+        // A source that cannot be read
+        let mut plan = SortPlanModel::new();
+        plan.add_move(dir_path.join("missing.jpg"), dir_path.join("b.jpg"), false);
+        let err = validate_plan(&plan, PlacementStrategy::Copy).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with(&format!("Cannot read {}: ", dir_path.join("missing.jpg").display())));
+
+        // A directory whose parent is never created
+        let mut plan = SortPlanModel::new();
+        plan.add_class_dir(dir_path.join("missing").join("Class 1"));
+        assert!(validate_plan(&plan, PlacementStrategy::Copy).is_err());
+    }
+
+    #[test]
+    fn update_target_dir_rolls_back_on_failure() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img1_path = dir_path.join("a.jpg");
+        let img2_path = dir_path.join("b.jpg");
+
+        File::create(&img1_path).unwrap();
+
+        // The second source is missing, so applying the plan fails halfway through
+        let mut plan = SortPlanModel::new();
+        plan.add_class_dir(dir_path.join("Class 1"));
+        plan.add_move(img1_path.clone(), dir_path.join("Class 1").join("a.jpg"), false);
+        plan.add_move(img2_path, dir_path.join("Class 1").join("b.jpg"), false);
+
+        let mut journal = JournalModel::new(String::from("0"), PlacementStrategy::Move);
+        let outcome = update_target_dir(&plan, &dir_path, PlacementStrategy::Move, &mut journal);
+
+        assert!(matches!(outcome, SortOutcome::RolledBack { .. }));
+        assert!(img1_path.is_file());
+        assert!(!dir_path.join("Class 1").exists());
+        assert!(journal.is_empty());
+        assert!(!journal_path(&dir_path, "0").exists());
+        assert!(!dir_path.join(STATE_DIR_NAME).exists());
+
+        // An output directory created by the sort is removed along with the journal kept in it
+        let output = tempdir().unwrap();
+        let output_dir_path = output.path().join("sorted");
+
+        let mut plan = SortPlanModel::new();
+        plan.add_class_dir(output_dir_path.clone());
+        plan.add_class_dir(output_dir_path.join("Class 1"));
+        plan.add_move(img1_path.clone(), output_dir_path.join("Class 1").join("a.jpg"), false);
+        plan.add_move(dir_path.join("missing.jpg"), output_dir_path.join("Class 1").join("b.jpg"), false);
+
+        let mut journal = JournalModel::new(String::from("0"), PlacementStrategy::Copy);
+        let outcome = update_target_dir(&plan, &output_dir_path, PlacementStrategy::Copy, &mut journal);
+
+        assert!(matches!(outcome, SortOutcome::RolledBack { .. }));
+        assert!(img1_path.is_file());
+        assert!(journal.is_empty());
+        assert!(!output_dir_path.exists());
+        assert_eq!(fs::read_dir(&dir_path).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn update_target_dir_keeps_journal_of_failed_rollback() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        let img1_path = dir_path.join("a.jpg");
+        let img2_path = dir_path.join("b.jpg");
+
+        File::create(&img1_path).unwrap();
+        File::create(&img2_path).unwrap();
+
+        // The second file takes the place the first came from, so the first cannot be moved back
+        let mut plan = SortPlanModel::new();
+        plan.add_class_dir(dir_path.join("Class 1"));
+        plan.add_move(img1_path.clone(), dir_path.join("Class 1").join("a.jpg"), false);
+        plan.add_move(img2_path, img1_path.clone(), false);
+        plan.add_move(dir_path.join("missing.jpg"), dir_path.join("Class 1").join("c.jpg"), false);
+
+        let mut journal = JournalModel::new(String::from("0"), PlacementStrategy::Move);
+        let outcome = update_target_dir(&plan, &dir_path, PlacementStrategy::Move, &mut journal);
+
+        assert!(matches!(outcome, SortOutcome::Failed { .. }));
+        assert_eq!(journal.entries().len(), 2);
+        assert_eq!(read_journal(&journal_path(&dir_path, "0")).unwrap(), journal);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn validate_plan_rejects_paths_the_journal_cannot_record() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir().unwrap();
+        let mut plan = SortPlanModel::new();
+        plan.add_class_dir(dir.path().join(OsStr::from_bytes(b"Class \xff")));
+
+        assert!(validate_plan(&plan, PlacementStrategy::Move).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,428 @@
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::models::journal_model::{JournalEntry, JournalModel};
+use crate::models::sort_plan_model::PlacementStrategy;
+use crate::controllers::io_controller::move_file;
+
+/// Name of the hidden directory tensort keeps its own state in.
+pub const STATE_DIR_NAME: &str = ".tensort";
+
+const JOURNALS_DIR_NAME: &str = "journals";
+const JOURNAL_EXTENSION: &str = "journal";
+
+/// Generates a unique identifier for a new run from the current time.
+pub fn gen_run_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    now.as_millis().to_string()
+}
+
+/// Returns the directory journals for runs in `dir` are stored in.
+fn journals_dir(dir: &Path) -> PathBuf {
+    dir.join(STATE_DIR_NAME).join(JOURNALS_DIR_NAME)
+}
+
+/// Returns the path of the journal for a run in `dir`.
+pub fn journal_path(dir: &Path, run_id: &str) -> PathBuf {
+    journals_dir(dir).join(format!("{}.{}", run_id, JOURNAL_EXTENSION))
+}
+
+/// Returns the modification time of a path (without following symbolic links) in nanoseconds since the epoch.
+fn modified_nanos(metadata: &fs::Metadata) -> io::Result<u128> {
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(modified.as_nanos())
+}
+
+/// Records the current state of a placed file so later changes to it can be detected.
+///
+/// # Arguments
+///
+/// * `src` - Where the file was placed from.
+/// * `dest` - Where the file was placed.
+/// * `replaced` - Whether an identical file was replaced at `dest`.
+///
+/// # Returns
+///
+/// Returns the `JournalEntry` for the placed file, or an `io::Error` if `dest` cannot be inspected.
+pub fn gen_journal_entry(src: &Path, dest: &Path, replaced: bool) -> io::Result<JournalEntry> {
+    let metadata = fs::symlink_metadata(dest)?;
+
+    Ok(JournalEntry {
+        src: src.to_path_buf(),
+        dest: dest.to_path_buf(),
+        size: metadata.len(),
+        modified: modified_nanos(&metadata)?,
+        replaced
+    })
+}
+
+/// Writes a journal into the state directory of `dir`.
+///
+/// A run writes its journal before making any change and then records each change as it is made,
+/// so whatever a run leaves behind can always be undone. The state directories created to hold the
+/// journal are recorded in it too, so undoing the run removes them again.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the run was performed on.
+/// * `journal` - The journal to write.
+///
+/// # Returns
+///
+/// Returns the path the journal was written to, otherwise returns an `io::Error`.
+pub fn write_journal(dir: &Path, journal: &mut JournalModel) -> io::Result<PathBuf> {
+    let journal_path = journal_path(dir, journal.run_id());
+
+    for state_dir in [dir.join(STATE_DIR_NAME), journals_dir(dir)] {
+        match fs::create_dir(&state_dir) {
+            Ok(()) => journal.add_created_dir(state_dir),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && state_dir.is_dir() => {},
+            Err(err) => return Err(err)
+        }
+    }
+
+    save_journal(&journal_path, journal)?;
+
+    Ok(journal_path)
+}
+
+/// Replaces a journal file with the contents of `journal` in one step.
+fn save_journal(journal_path: &Path, journal: &JournalModel) -> io::Result<()> {
+    let text = journal
+        .serialize()
+        .ok_or_else(|| { io::Error::new(io::ErrorKind::InvalidData, "Journal contains a path that is not valid UTF-8") })?;
+
+    let temp_path = journal_path.with_extension("tmp");
+    fs::write(&temp_path, text)?;
+    fs::rename(&temp_path, journal_path)
+}
+
+/// Appends a single record to a journal file.
+///
+/// The file is unbuffered, so the record reaches the operating system before this returns
+/// and survives the process being killed.
+fn append_journal_record(journal_path: &Path, record: Option<String>) -> io::Result<()> {
+    let record = record
+        .ok_or_else(|| { io::Error::new(io::ErrorKind::InvalidData, "Journal contains a path that is not valid UTF-8") })?;
+
+    File::options()
+        .append(true)
+        .open(journal_path)?
+        .write_all(record.as_bytes())
+}
+
+/// Records a directory created by a run, both in `journal` and in its file at `journal_path`.
+///
+/// The directory is recorded in `journal` even if the file cannot be written, so a rollback still removes it.
+pub fn record_created_dir(journal_path: &Path, journal: &mut JournalModel, dir: PathBuf) -> io::Result<()> {
+    let record = JournalModel::serialize_created_dir(&dir);
+    journal.add_created_dir(dir);
+
+    append_journal_record(journal_path, record)
+}
+
+/// Records a file placed by a run, both in `journal` and in its file at `journal_path`.
+///
+/// The file is recorded in `journal` even if the file cannot be written, so a rollback still reverts it.
+pub fn record_journal_entry(journal_path: &Path, journal: &mut JournalModel, entry: JournalEntry) -> io::Result<()> {
+    let record = JournalModel::serialize_entry(&entry);
+    journal.add_entry(entry);
+
+    append_journal_record(journal_path, record)
+}
+
+/// Brings a journal file in line with `journal` after some of its changes were reverted.
+///
+/// The file is removed once there is nothing left to undo.
+pub fn update_journal(journal_path: &Path, journal: &JournalModel) -> io::Result<()> {
+    match journal.is_empty() {
+        true => remove_journal_file(journal_path),
+        false => save_journal(journal_path, journal)
+    }
+}
+
+/// Removes a journal file, if it is still there.
+fn remove_journal_file(journal_path: &Path) -> io::Result<()> {
+    match fs::remove_file(journal_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(())
+    }
+}
+
+/// Finds the journal for a run in `dir`, or the most recent journal if no run id is given.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the run was performed on.
+/// * `run_id` - The run to look for, if any.
+///
+/// # Returns
+///
+/// Returns the path of the journal, otherwise returns an `io::Error` of kind `NotFound`.
+pub fn find_journal(dir: &Path, run_id: Option<&str>) -> io::Result<PathBuf> {
+    let journals_dir = journals_dir(dir);
+
+    if let Some(run_id) = run_id {
+        let journal_path = journal_path(dir, run_id);
+
+        return match journal_path.is_file() {
+            true => Ok(journal_path),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, format!("No journal found for run {}", run_id)))
+        };
+    }
+
+    let mut latest: Option<(u128, PathBuf)> = None;
+
+    if journals_dir.is_dir() {
+        for file in fs::read_dir(&journals_dir)? {
+            let path = file?.path();
+
+            if path.extension().and_then(|extension| { extension.to_str() }) != Some(JOURNAL_EXTENSION) {
+                continue;
+            }
+
+            // Run ids are timestamps so the largest one is the most recent
+            if let Some(run_time) = path.file_stem().and_then(|stem| { stem.to_str() }).and_then(|stem| { stem.parse::<u128>().ok() }) {
+                if latest.as_ref().is_none_or(|(latest_time, _)| { run_time > *latest_time }) {
+                    latest = Some((run_time, path));
+                }
+            }
+        }
+    }
+
+    latest
+        .map(|(_, path)| { path })
+        .ok_or_else(|| { io::Error::new(io::ErrorKind::NotFound, "No runs to undo") })
+}
+
+/// Reads and parses a journal file.
+pub fn read_journal(journal_path: &Path) -> io::Result<JournalModel> {
+    let text = fs::read_to_string(journal_path)?;
+
+    JournalModel::parse(&text)
+        .ok_or_else(|| { io::Error::new(io::ErrorKind::InvalidData, "Journal is malformed") })
+}
+
+/// Checks that a placed file is still exactly as it was left by its run.
+fn verify_journal_entry(entry: &JournalEntry, placement: PlacementStrategy) -> io::Result<()> {
+    let changed = |reason: &str| {
+        io::Error::other(format!("Refusing to undo, {} {}", entry.dest.display(), reason))
+    };
+
+    let metadata = fs::symlink_metadata(&entry.dest)
+        .map_err(|_| { changed("no longer exists") })?;
+
+    if metadata.len() != entry.size || modified_nanos(&metadata)? != entry.modified {
+        return Err(changed("was changed after the run"));
+    }
+
+    // Moving back must not overwrite anything that has since appeared at the original location
+    if placement == PlacementStrategy::Move && fs::symlink_metadata(&entry.src).is_ok() {
+        return Err(io::Error::other(format!("Refusing to undo, {} already exists", entry.src.display())));
+    }
+
+    Ok(())
+}
+
+/// Reverses every change recorded in a journal.
+///
+/// Every placed file is checked before anything is touched, so the revert is refused
+/// outright if any file was changed or removed after the run. Created directories
+/// are only removed if they are empty once the files have been restored, and files
+/// that replaced an identical file leave a copy of that file behind. The journal file
+/// is removed along with the directories that hold it, once nothing else is left to revert.
+///
+/// # Arguments
+///
+/// * `journal_path` - The file `journal` is kept in.
+/// * `journal` - The journal to replay backwards. Each change is dropped from it once reverted,
+///   so if the revert fails it only lists the changes that are still applied.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if every change was reverted, otherwise returns an `io::Error`.
+pub fn revert_journal(journal_path: &Path, journal: &mut JournalModel) -> io::Result<()> {
+    let placement = journal.placement();
+
+    for entry in journal.entries() {
+        verify_journal_entry(entry, placement)?;
+    }
+
+    while let Some(entry) = journal.entries().last() {
+        match (placement, entry.replaced) {
+            (PlacementStrategy::Move, false) => move_file(&entry.dest, &entry.src)?,
+            (PlacementStrategy::Move, true) => {
+                move_file(&entry.dest, &entry.src)?;
+                fs::copy(&entry.src, &entry.dest)?;
+            },
+
+            // The replaced file had the same contents, so only a copy needs to be left in its place
+            (_, true) => {
+                fs::remove_file(&entry.dest)?;
+                fs::copy(&entry.src, &entry.dest)?;
+            },
+            (_, false) => fs::remove_file(&entry.dest)?
+        }
+
+        journal.pop_entry();
+    }
+
+    while let Some(dir) = journal.created_dirs().last() {
+
+        // Directories are removed in reverse, so by the time one holding the journal is reached nothing else is left to revert
+        if journal_path.starts_with(dir) {
+            remove_journal_file(journal_path)?;
+        }
+
+        if dir.is_dir() && fs::read_dir(dir)?.next().is_none() {
+            fs::remove_dir(dir)?;
+        }
+
+        journal.pop_created_dir();
+    }
+
+    Ok(())
+}
+
+/// Reverses every change recorded in a journal file (see `revert_journal`).
+///
+/// # Arguments
+///
+/// * `journal_path` - The path of the journal to replay backwards. It is removed once the undo completes,
+///   or left listing only the changes that are still applied if the undo fails.
+///
+/// # Returns
+///
+/// Returns the `JournalModel` that was undone, otherwise returns an `io::Error`.
+pub fn undo_journal(journal_path: &Path) -> io::Result<JournalModel> {
+    let journal = read_journal(journal_path)?;
+    let mut remaining = journal.clone();
+
+    let reverted = revert_journal(journal_path, &mut remaining);
+    let updated = update_journal(journal_path, &remaining);
+    reverted?;
+    updated?;
+
+    Ok(journal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn place_and_record(dir: &Path, placement: PlacementStrategy) -> (PathBuf, PathBuf, PathBuf) {
+        let src_path = dir.join("a.jpg");
+        let class_dir = dir.join("Class 1");
+        let dest_path = class_dir.join("a.jpg");
+        let mut journal = JournalModel::new(String::from("100"), placement);
+        let journal_path = write_journal(dir, &mut journal).unwrap();
+
+        writeln!(File::create(&src_path).unwrap(), "pixels").unwrap();
+        fs::create_dir(&class_dir).unwrap();
+        record_created_dir(&journal_path, &mut journal, class_dir.clone()).unwrap();
+
+        match placement {
+            PlacementStrategy::Move => fs::rename(&src_path, &dest_path).unwrap(),
+            _ => { fs::copy(&src_path, &dest_path).unwrap(); }
+        }
+
+        record_journal_entry(&journal_path, &mut journal, gen_journal_entry(&src_path, &dest_path, false).unwrap()).unwrap();
+        assert_eq!(read_journal(&journal_path).unwrap(), journal);
+
+        (src_path, dest_path, journal_path)
+    }
+
+    #[test]
+    fn undo_journal_restores_moved_files() {
+        let dir = tempdir().unwrap();
+        let (src_path, dest_path, journal_path) = place_and_record(dir.path(), PlacementStrategy::Move);
+
+        assert_eq!(find_journal(dir.path(), None).unwrap(), journal_path);
+
+        let journal = undo_journal(&journal_path).unwrap();
+
+        assert_eq!(journal.run_id(), "100");
+        assert!(src_path.is_file());
+        assert!(!dest_path.exists());
+        assert!(!dir.path().join("Class 1").exists());
+        assert!(!journal_path.exists());
+        assert!(!dir.path().join(STATE_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn undo_journal_removes_copies() {
+        let dir = tempdir().unwrap();
+        let (src_path, dest_path, journal_path) = place_and_record(dir.path(), PlacementStrategy::Copy);
+
+        undo_journal(&journal_path).unwrap();
+
+        assert!(src_path.is_file());
+        assert!(!dest_path.exists());
+    }
+
+    #[test]
+    fn undo_journal_refuses_changed_files() {
+        let dir = tempdir().unwrap();
+        let (src_path, dest_path, journal_path) = place_and_record(dir.path(), PlacementStrategy::Move);
+
+        writeln!(File::options().append(true).open(&dest_path).unwrap(), "edited").unwrap();
+
+        assert!(undo_journal(&journal_path).is_err());
+
+        // Nothing should have been touched
+        assert!(!src_path.exists());
+        assert!(dest_path.is_file());
+        assert!(journal_path.is_file());
+    }
+
+    #[test]
+    fn undo_journal_keeps_changes_it_could_not_revert() {
+        let dir = tempdir().unwrap();
+        let nested_dir = dir.path().join("nested");
+        let class_dir = dir.path().join("Class 1");
+        let mut journal = JournalModel::new(String::from("100"), PlacementStrategy::Move);
+        let journal_path = write_journal(dir.path(), &mut journal).unwrap();
+
+        fs::create_dir(&nested_dir).unwrap();
+        fs::create_dir(&class_dir).unwrap();
+        record_created_dir(&journal_path, &mut journal, class_dir.clone()).unwrap();
+
+        for src_path in [nested_dir.join("a.jpg"), dir.path().join("b.jpg")] {
+            let dest_path = class_dir.join(src_path.file_name().unwrap());
+
+            writeln!(File::create(&src_path).unwrap(), "pixels").unwrap();
+            fs::rename(&src_path, &dest_path).unwrap();
+            record_journal_entry(&journal_path, &mut journal, gen_journal_entry(&src_path, &dest_path, false).unwrap()).unwrap();
+        }
+
+        // The first file cannot be moved back once its original directory is gone
+        fs::remove_dir(&nested_dir).unwrap();
+
+        assert!(undo_journal(&journal_path).is_err());
+        assert!(dir.path().join("b.jpg").is_file());
+        assert!(class_dir.join("a.jpg").is_file());
+
+        // Only the change that is still applied is left to undo
+        journal.pop_entry();
+        assert_eq!(read_journal(&journal_path).unwrap(), journal);
+    }
+
+    #[test]
+    fn find_journal_without_runs_returns_error() {
+        let dir = tempdir().unwrap();
+
+        assert!(find_journal(dir.path(), None).is_err());
+        assert!(find_journal(dir.path(), Some("123")).is_err());
+    }
+}
\ No newline at end of file
@@ -1,2 +1,11 @@
 pub mod io_controller;
-pub mod embeddings_controller;
\ No newline at end of file
+pub mod embeddings_controller;
+pub mod journal_controller;
+pub mod config_controller;
+pub mod weights_controller;
+pub mod device_controller;
+pub mod precision_controller;
+pub mod cache_controller;
+pub mod export_controller;
+pub mod import_controller;
+pub mod index_controller;
\ No newline at end of file
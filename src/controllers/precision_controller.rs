@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use tch::{Kind, Tensor};
+use crate::errors::PrecisionError;
+use crate::models::cnn_model::{Convertible, Embeddable};
+use crate::models::precision_model::{Precision, PrecisionDrift};
+
+/// Measures how far reduced precision embeddings are from the reference embeddings of the same images.
+///
+/// # Arguments
+///
+/// * `reference` - The `N x D` embeddings at full precision.
+/// * `reduced` - The `N x D` embeddings of the same images at reduced precision.
+///
+/// # Returns
+///
+/// Returns the mean cosine similarity between the embeddings of each image, and the largest absolute difference between any of their values.
+pub fn measure_drift(reference: &Tensor, reduced: &Tensor) -> (f64, f64) {
+    let mean_cosine_similarity = Tensor::cosine_similarity(reference, reduced, 1, 1e-12)
+        .mean(Kind::Double)
+        .double_value(&[]);
+    let max_difference = (reference - reduced)
+        .abs()
+        .max()
+        .double_value(&[]);
+
+    (mean_cosine_similarity, max_difference)
+}
+
+/// Converts the network to a reduced precision, checking how much that changes the embeddings of a sample of images.
+///
+/// # Arguments
+///
+/// * `model` - The network, at full precision.
+/// * `precision` - The precision to convert the network to.
+/// * `sample_paths` - The images embedded at both precisions, such as the first `DRIFT_SAMPLE_SIZE` images that will be sorted.
+///
+/// # Returns
+///
+/// Returns the drift of the embeddings, or `None` if the precision is not reduced or no sample image could be embedded.
+/// Returns a `PrecisionError` if the network cannot be converted, or fails on images at the reduced precision that it embeds at full precision.
+pub fn convert_precision<M: Embeddable + Convertible>(model: &mut M, precision: Precision, sample_paths: &[PathBuf]) -> Result<Option<PrecisionDrift>, PrecisionError> {
+    let convert = |model: &mut M| {
+        model
+            .convert_precision(precision)
+            .map_err(|cause| { PrecisionError { precision, cause } })
+    };
+
+    if precision == Precision::Float32 || sample_paths.is_empty() {
+        convert(model)?;
+        return Ok(None);
+    }
+
+    let reference = model.gen_embeddings(sample_paths);
+    convert(model)?;
+    let reduced = model.gen_embeddings(sample_paths);
+
+    // Images are read the same way at both precisions, so any new failure comes from the network
+    for (reference_error, reduced_error) in reference.errors.iter().zip(reduced.errors) {
+        if let (None, Some(cause)) = (reference_error, reduced_error) {
+            return Err(PrecisionError { precision, cause });
+        }
+    }
+
+    let (Some(reference), Some(reduced)) = (reference.embeddings, reduced.embeddings) else {
+        return Ok(None);
+    };
+
+    let (mean_cosine_similarity, max_difference) = measure_drift(&reference, &reduced);
+
+    Ok(Some(PrecisionDrift {
+        precision,
+        sample_size: reference.size()[0] as usize,
+        mean_cosine_similarity,
+        max_difference
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use tch::{Device, TchError};
+    use tch::vision::image;
+    use tempfile::tempdir;
+    use crate::models::architecture_model::ArchitectureSpec;
+    use crate::models::augmentation_model::AugmentationModel;
+    use crate::models::cnn_model::{CNNModel, EmbeddingMode};
+    use crate::models::preprocessing_model::{Normalization, PreprocessingModel, ResizeStrategy};
+    use super::*;
+
+    // Embeds images as they are, but fails on every batch once converted to a reduced precision
+    struct MockModel {
+        precision: Precision
+    }
+
+    impl Convertible for MockModel {
+        fn convert_precision(&mut self, precision: Precision) -> Result<(), TchError> {
+            self.precision = precision;
+
+            Ok(())
+        }
+    }
+
+    impl Embeddable for MockModel {
+        fn preprocessing(&self) -> PreprocessingModel {
+            PreprocessingModel::new(ResizeStrategy::Stretch, 4, Normalization::Raw)
+        }
+
+        fn identity(&self) -> String {
+            String::from("mock")
+        }
+
+        fn embed_images(&self, images: &Tensor) -> Result<Tensor, TchError> {
+            if self.precision != Precision::Float32 {
+                return Err(TchError::Torch(String::from("No kernel for this precision")));
+            }
+
+            Ok(images.flatten(1, -1))
+        }
+    }
+
+    #[test]
+    fn measure_drift_works() {
+        let reference = Tensor::from_slice(&[1.0f32, 0.0, 0.0, 2.0]).view([2, 2]);
+        let reduced = Tensor::from_slice(&[1.0f32, 0.0, 0.0, 2.5]).view([2, 2]);
+
+        let (mean_cosine_similarity, max_difference) = measure_drift(&reference, &reduced);
+
+        assert!((mean_cosine_similarity - 1.0).abs() < 1e-6);
+        assert!((max_difference - 0.5).abs() < 1e-6);
+
+        let reduced = Tensor::from_slice(&[0.0f32, 1.0, 0.0, 2.0]).view([2, 2]);
+        let (mean_cosine_similarity, _) = measure_drift(&reference, &reduced);
+
+        assert!((mean_cosine_similarity - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn convert_precision_without_sample_skips_check() {
+        let architecture = ArchitectureSpec::from_name("resnet34").unwrap();
+        let mut model = CNNModel::from_file(Path::new("resnet34.ot"), architecture, architecture.default_preprocessing(), EmbeddingMode::Probabilities, AugmentationModel::default(), Device::Cpu).unwrap();
+
+        assert_eq!(convert_precision(&mut model, Precision::BFloat16, &[]).unwrap(), None);
+        assert_eq!(model.precision(), Precision::BFloat16);
+
+        // Embeddings are always returned at full precision
+        let images = Tensor::zeros([1, 3, 224, 224], (Kind::Float, Device::Cpu));
+        assert_eq!(model.embed_images(&images).unwrap().kind(), Kind::Float);
+    }
+
+    #[test]
+    fn convert_precision_reports_failing_forward() {
+        let dir = tempdir().unwrap();
+        let sample_path = dir.path().join("a.png");
+        image::save(&Tensor::full([3, 4, 4], 1, (Kind::Uint8, Device::Cpu)), &sample_path).unwrap();
+
+        let mut model = MockModel { precision: Precision::Float32 };
+        let err = convert_precision(&mut model, Precision::Float16, &[sample_path]).unwrap_err();
+
+        assert_eq!(err.precision, Precision::Float16);
+        assert!(matches!(err.cause, TchError::Torch(_)));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,200 @@
+use std::env;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tch::Device;
+use crate::errors::WeightsError;
+use crate::models::architecture_model::{ArchitectureSpec, DEFAULT_ARCHITECTURE};
+use crate::models::augmentation_model::AugmentationModel;
+use crate::models::cnn_model::{CNNModel, EmbeddingMode, WeightsSource};
+use crate::models::preprocessing_model::PreprocessingModel;
+use crate::models::torchscript_model::{OutputActivation, TorchScriptModel};
+
+/// Weights of the default architecture compiled into the binary, only available with the `embedded-weights` feature.
+#[cfg(feature = "embedded-weights")]
+const EMBEDDED_WEIGHTS: Option<&[u8]> = Some(include_bytes!("../../resnet34.ot"));
+
+#[cfg(not(feature = "embedded-weights"))]
+const EMBEDDED_WEIGHTS: Option<&[u8]> = None;
+
+/// Returns every location the weights of an architecture are looked up in, in order:
+/// the working directory, the directory of the executable, then the user data directory
+/// (`$XDG_DATA_HOME/tensort`, falling back to `~/.local/share/tensort` or `%APPDATA%\tensort`).
+///
+/// The weights file is named after the architecture, such as `resnet34.ot`.
+pub fn default_weights_paths(architecture: &ArchitectureSpec) -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(".")];
+
+    if let Some(exe_dir) = env::current_exe().ok().and_then(|exe| { exe.parent().map(Path::to_path_buf) }) {
+        dirs.push(exe_dir);
+    }
+
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| { !dir.is_empty() })
+        .map(PathBuf::from)
+        .or_else(|| { env::var_os("HOME").map(|home| { PathBuf::from(home).join(".local").join("share") }) })
+        .or_else(|| { env::var_os("APPDATA").map(PathBuf::from) });
+
+    if let Some(data_dir) = data_dir {
+        dirs.push(data_dir.join("tensort"));
+    }
+
+    dirs
+        .into_iter()
+        .map(|dir| { dir.join(architecture.weights_name()) })
+        .collect()
+}
+
+/// Works out where the weights of the neural network should be loaded from.
+///
+/// An explicitly given path always wins. Otherwise the first existing default location is used,
+/// then the embedded weights if the binary was built with them and the default architecture is selected.
+///
+/// # Arguments
+///
+/// * `weights_path` - The weights file given on the command line or in a config file, if any.
+/// * `architecture` - The architecture the weights are for.
+/// * `default_paths` - The locations to look for weights in otherwise, as from `default_weights_paths`.
+///
+/// # Returns
+///
+/// Returns the `WeightsSource` to load, otherwise returns a `WeightsError` naming every location that was searched.
+pub fn resolve_weights(weights_path: Option<&Path>, architecture: &ArchitectureSpec, default_paths: Vec<PathBuf>) -> Result<WeightsSource, WeightsError> {
+    if let Some(weights_path) = weights_path {
+        if !weights_path.is_file() {
+            return Err(WeightsError::Missing(weights_path.to_path_buf()));
+        }
+
+        return Ok(WeightsSource::File(weights_path.to_path_buf()));
+    }
+
+    if let Some(default_path) = default_paths.iter().find(|path| { path.is_file() }) {
+        return Ok(WeightsSource::File(default_path.clone()));
+    }
+
+    match EMBEDDED_WEIGHTS {
+        Some(_) if architecture.name == DEFAULT_ARCHITECTURE => Ok(WeightsSource::Embedded),
+        _ => Err(WeightsError::NotFound {
+            architecture: architecture.name,
+            searched: default_paths
+        })
+    }
+}
+
+/// Loads the neural network with the given weights.
+///
+/// # Arguments
+///
+/// * `weights` - Where to load the weights from, as from `resolve_weights`.
+/// * `architecture` - The architecture of the network to build.
+/// * `preprocessing` - How images are prepared for the network.
+/// * `mode` - What the network outputs as embeddings.
+/// * `augmentation` - Which extra views of each image are embedded and how their embeddings are combined.
+/// * `device` - The device the network runs on, as from `device_controller::resolve_device`.
+///
+/// # Returns
+///
+/// Returns the loaded `CNNModel`, otherwise returns a `WeightsError` if the weights are invalid or do not match the network.
+pub fn load_cnn_model(weights: &WeightsSource, architecture: &'static ArchitectureSpec, preprocessing: PreprocessingModel, mode: EmbeddingMode, augmentation: AugmentationModel, device: Device) -> Result<CNNModel, WeightsError> {
+    let result = match weights {
+        WeightsSource::File(path) => CNNModel::from_file(path, architecture, preprocessing, mode, augmentation, device),
+        WeightsSource::Embedded => CNNModel::new(Cursor::new(EMBEDDED_WEIGHTS.unwrap_or_default()), architecture, preprocessing, mode, augmentation, device)
+    };
+
+    result.map_err(|cause| {
+        WeightsError::Invalid {
+            origin: weights.to_string(),
+            architecture: architecture.name,
+            cause
+        }
+    })
+}
+
+/// Loads a TorchScript model exported from PyTorch.
+///
+/// # Arguments
+///
+/// * `path` - The TorchScript file to load.
+/// * `preprocessing` - How images are prepared for the model.
+/// * `activation` - How the output of the model is turned into an embedding.
+/// * `device` - The device the model runs on, as from `device_controller::resolve_device`.
+///
+/// # Returns
+///
+/// Returns the loaded `TorchScriptModel`, otherwise returns a `WeightsError` if the file does not exist or is not a valid TorchScript module.
+pub fn load_torchscript_model(path: &Path, preprocessing: PreprocessingModel, activation: OutputActivation, device: Device) -> Result<TorchScriptModel, WeightsError> {
+    if !path.is_file() {
+        return Err(WeightsError::Missing(path.to_path_buf()));
+    }
+
+    TorchScriptModel::new(path, preprocessing, activation, device).map_err(|cause| {
+        WeightsError::InvalidTorchScript {
+            path: path.to_path_buf(),
+            cause
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::tempdir;
+    use super::*;
+
+    #[test]
+    fn resolve_weights_prefers_explicit_path() {
+        let dir = tempdir().unwrap();
+        let architecture = ArchitectureSpec::default_architecture();
+        let explicit_path = dir.path().join("custom.ot");
+        let default_path = dir.path().join(architecture.weights_name());
+
+        fs::write(&explicit_path, [0u8]).unwrap();
+        fs::write(&default_path, [0u8]).unwrap();
+
+        let weights = resolve_weights(Some(&explicit_path), architecture, vec![default_path.clone()]).unwrap();
+        assert_eq!(weights, WeightsSource::File(explicit_path));
+
+        let weights = resolve_weights(None, architecture, vec![dir.path().join("missing.ot"), default_path.clone()]).unwrap();
+        assert_eq!(weights, WeightsSource::File(default_path));
+    }
+
+    #[test]
+    fn resolve_weights_reports_missing_files() {
+        let dir = tempdir().unwrap();
+        let architecture = ArchitectureSpec::from_name("resnet50").unwrap();
+        let missing_path = dir.path().join("missing.ot");
+
+        let result = resolve_weights(Some(&missing_path), architecture, vec![]);
+        assert!(matches!(result, Err(WeightsError::Missing(path)) if path == missing_path));
+
+        // Embedded weights only exist for the default architecture
+        let result = resolve_weights(None, architecture, vec![missing_path.clone()]);
+        assert!(matches!(result, Err(WeightsError::NotFound { searched, .. }) if searched == vec![missing_path]));
+    }
+
+    #[test]
+    fn load_cnn_model_reports_invalid_weights() {
+        let dir = tempdir().unwrap();
+        let invalid_path = dir.path().join("invalid.ot");
+
+        fs::write(&invalid_path, [69u8, 23]).unwrap();
+
+        let architecture = ArchitectureSpec::default_architecture();
+        let result = load_cnn_model(&WeightsSource::File(invalid_path), architecture, architecture.default_preprocessing(), EmbeddingMode::Probabilities, AugmentationModel::default(), Device::Cpu);
+        assert!(matches!(result, Err(WeightsError::Invalid { .. })));
+    }
+
+    #[test]
+    fn load_torchscript_model_reports_missing_and_invalid_files() {
+        let dir = tempdir().unwrap();
+        let invalid_path = dir.path().join("invalid.pt");
+        let preprocessing = ArchitectureSpec::default_architecture().default_preprocessing();
+
+        let result = load_torchscript_model(&invalid_path, preprocessing, OutputActivation::None, Device::Cpu);
+        assert!(matches!(result, Err(WeightsError::Missing(path)) if path == invalid_path));
+
+        fs::write(&invalid_path, [69u8, 23]).unwrap();
+
+        let result = load_torchscript_model(&invalid_path, preprocessing, OutputActivation::None, Device::Cpu);
+        assert!(matches!(result, Err(WeightsError::InvalidTorchScript { .. })));
+    }
+}
\ No newline at end of file
@@ -1,5 +1,79 @@
 use std::error::Error;
+use std::path::PathBuf;
+use tch::TchError;
+use crate::models::cli_model::Command;
+use crate::models::device_model::DeviceSelection;
+use crate::models::precision_model::Precision;
 
+/// Raised when the command line cannot be understood.
+/// Carries the command being parsed, if known, so its usage can be shown alongside the message.
 #[derive(Debug)]
-pub struct InvalidUsageError(pub &'static str);
-impl Error for InvalidUsageError {}
\ No newline at end of file
+pub struct InvalidUsageError {
+    pub message: String,
+    pub command: Option<Command>
+}
+
+impl InvalidUsageError {
+
+    /// Creates an error for a specific command.
+    pub fn new(command: Command, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            command: Some(command)
+        }
+    }
+
+    /// Creates an error that is not tied to any command.
+    pub fn global(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            command: None
+        }
+    }
+}
+
+impl Error for InvalidUsageError {}
+
+/// Raised when the weights of the neural network cannot be loaded.
+#[derive(Debug)]
+pub enum WeightsError {
+    /// No weights were given and none were found in any of the default locations.
+    NotFound {
+        architecture: &'static str,
+        searched: Vec<PathBuf>
+    },
+    /// The given weights file does not exist.
+    Missing(PathBuf),
+    /// The weights could not be read or do not match the architecture of the network.
+    Invalid {
+        origin: String,
+        architecture: &'static str,
+        cause: TchError
+    },
+    /// The given TorchScript file could not be loaded.
+    InvalidTorchScript {
+        path: PathBuf,
+        cause: TchError
+    }
+}
+
+impl Error for WeightsError {}
+
+/// Raised when the selected CUDA device does not exist.
+#[derive(Debug)]
+pub struct DeviceUnavailableError {
+    pub selection: DeviceSelection,
+    pub cuda_device_count: usize
+}
+
+impl Error for DeviceUnavailableError {}
+
+/// Raised when the network fails on images at a reduced precision that it embeds at full precision,
+/// usually because the device has no kernels for that precision.
+#[derive(Debug)]
+pub struct PrecisionError {
+    pub precision: Precision,
+    pub cause: TchError
+}
+
+impl Error for PrecisionError {}
\ No newline at end of file
@@ -9,55 +9,173 @@ mod controllers;
 mod errors;
 
 use std::env::args;
-use tch::vision::resnet;
-use anyhow::Result;
-use crate::models::arguments_model::ArgumentsModel;
-use crate::models::cnn_model::CNNModel;
+use std::process::exit;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Result};
+use tch::Tensor;
+use crate::models::arguments_model::{ArgumentsModel, CacheArgumentsModel, InvocationModel, UndoArgumentsModel};
+use crate::models::cli_model::Command;
+use crate::models::cnn_model::{imagenet_class_scores, Embeddable, WeightsSource};
+use crate::models::embedding_cache_model::CacheAction;
+use crate::models::journal_model::JournalModel;
+use crate::models::precision_model::{Precision, DRIFT_SAMPLE_SIZE};
 use crate::views::results_view::*;
+use crate::views::usage_view::format_usage;
 use crate::controllers::io_controller::*;
 use crate::controllers::embeddings_controller::*;
-
-const VARSTORE_BYTES: &[u8] = include_bytes!("../resnet34.ot");
+use crate::controllers::journal_controller::*;
+use crate::controllers::config_controller::load_config;
+use crate::controllers::weights_controller::*;
+use crate::controllers::device_controller::*;
+use crate::controllers::precision_controller::convert_precision;
+use crate::controllers::cache_controller::*;
+use crate::controllers::export_controller::*;
+use crate::controllers::import_controller::import_embeddings;
+use crate::controllers::index_controller::{build_index, index_path, load_index, save_index};
 
 fn run(args: Vec<String>) -> Result<()> {
 
-	// Read in command line arguments
-	let args = ArgumentsModel::from(args)?;
+	// Global help and version are answered first, so a broken config file cannot hide them
+	// Settings not given on the command line fall back to config files and the environment
+	let invocation = match InvocationModel::from_global(&args) {
+		Some(invocation) => invocation,
+		None => {
+			let (config, ignored_vars) = load_config()?;
+
+			for name in ignored_vars {
+				eprintln!("Ignoring {}, which is not a tensort setting", name);
+			}
+
+			InvocationModel::from(args, &config)?
+		}
+	};
+
+	// Dispatch to the requested command
+	match invocation {
+		InvocationModel::Run(args) => run_command(*args),
+		InvocationModel::Undo(args) => run_undo(args),
+		InvocationModel::Cache(args) => run_cache(args),
+		InvocationModel::Help(command) => {
+			println!("{}", format_usage(command));
+			Ok(())
+		},
+		InvocationModel::Version => {
+			println!("tensort {}", env!("CARGO_PKG_VERSION"));
+			Ok(())
+		}
+	}
+}
+
+fn run_command(args: ArgumentsModel) -> Result<()> {
 
 	// Print selected arguments
 	println!("{}", args);
 
-	// Initialize convolutional nesural network and print related info
-	let model = CNNModel::new(VARSTORE_BYTES, resnet::resnet34)?;
-	println!("{}\n", model);
+	// Embeddings computed elsewhere are clustered as they are, without loading a model
+	let (embeddings, image_paths, model) = match args.import() {
+		Some(import) => {
+			println!("Importing embeddings from {}...", import.path().display());
 
-	// Read the target dir and process each image
-	println!("Generating image embeddings...");
-	let (embeddings, image_paths, missed_image_paths) = gen_image_embeddings(args.target_dir(), &model)?;
+			let (embeddings, image_paths) = import_embeddings(import)?;
+			println!("Imported {} embeddings.", embeddings.len());
 
-	// If some images failed to process, list them
-	if missed_image_paths.len() > 0 {
-		println!("{}", format_missed_images(missed_image_paths));
-	}
+			(embeddings, image_paths, None)
+		},
+		None => {
+
+			// Initialize the neural network and print related info
+			let (model, model_file) = load_model(&args)?;
+			let (embeddings, image_paths) = embed_target_dir(&args, model.as_ref(), model_file.as_deref())?;
+
+			// Embedding stops here, after writing the embeddings out if requested
+			if args.command() == Command::Embed {
+				println!("Generated {} image embeddings.", embeddings.len());
+
+				if let Some(export) = args.export() {
+					let metadata = gen_export_metadata(&args, model_file.as_deref());
+					let written_paths = export_embeddings(export, &embeddings, &image_paths, &metadata)?;
+
+					for written_path in written_paths {
+						println!("Exported to {}", written_path.display());
+					}
+				}
+
+				return Ok(());
+			}
+
+			(embeddings, image_paths, Some(model))
+		}
+	};
 
 	// Group embeddings together
-	println!("Computing similarities and clustering embeddings...");
-	let similarities = calc_pairwise_cosine_similarities(embeddings.as_slice());
-	let similarity_threshold = calc_similarity_threshold(&similarities, args.class_count());
-	let similarity_table = cluster_embeddings(similarities.as_slice(), similarity_threshold, embeddings.len(), args.class_count());
+	// Large collections are clustered from their nearest neighbours, since comparing every pair takes quadratic memory
+	let similarity_table = match args.similarity().uses_index(embeddings.len()) {
+		true => cluster_with_index(&args, embeddings.as_slice())?,
+		false => cluster_exactly(&args, embeddings.as_slice())
+	};
+
+	let class_count = similarity_table.len();
 
 	// Generate class names if option is set
-	let class_names = if args.should_not_gen_names() {
-		gen_default_class_names(args.class_count())
+	// Names can only be derived from embeddings that map to ImageNet classes
+	let class_names = if args.should_not_gen_names() || args.command() == Command::Cluster {
+		gen_default_class_names(class_count)
 	}
 	else {
 		println!("Averaging tensors and deriving class names...");
-		gen_class_names(embeddings.as_slice(), &similarity_table)
+
+		// Imported embeddings are named as they are if they hold ImageNet class scores
+		let class_scores = |embedding: &Tensor| {
+			match &model {
+				Some(model) => model.class_scores(embedding),
+				None => imagenet_class_scores(embedding)
+			}
+		};
+
+		match gen_class_names(embeddings.as_slice(), &similarity_table, class_scores) {
+			Some(class_names) => class_names,
+			None => {
+				println!("The embeddings do not map to ImageNet classes, using default class names...");
+				gen_default_class_names(class_count)
+			}
+		}
 	};
 
-	// Manipulate file locations
-	println!("Moving files...");
-	update_target_dir(args.target_dir(), image_paths.as_slice(), class_names.as_slice(), &similarity_table)?;
+	// Clustering and naming only report the classes without touching any files
+	if args.command() != Command::Sort {
+		print!("\nResults:\n{}", format_classified_images(similarity_table, image_paths, class_names));
+		return Ok(());
+	}
+
+	// Work out every filesystem change up front
+	// Conflicts with existing directories and files are resolved here, before anything is moved
+	let plan = plan_target_dir(args.target_dir(), args.output_dir(), image_paths.as_slice(), class_names.as_slice(), &similarity_table, args.conflicts())?;
+
+	// Only report the planned changes on a dry run
+	if args.dry_run() {
+		print!("\nDry run, no files were moved.\n{}\n", plan);
+	}
+
+	// Make sure the whole plan can be applied before changing anything
+	println!("Validating planned changes...");
+	validate_plan(&plan, args.placement())?;
+
+	if !args.dry_run() {
+		println!("Placing files ({})...", args.placement());
+
+		// The journal is written before the first change and records each change as it is made
+		let mut journal = JournalModel::new(gen_run_id(), args.placement());
+		let outcome = update_target_dir(&plan, args.output_dir(), args.placement(), &mut journal);
+
+		// A sort that was not committed is reported as an error, so scripts can tell it failed
+		if !outcome.is_committed() {
+			bail!("{}", outcome);
+		}
+
+		println!("{}", outcome);
+
+		println!("Recorded run {} (undo with: tensort undo {} {})", journal.run_id(), args.output_dir().display(), journal.run_id());
+	}
 
 	// Print classification results
 	// Move all the values since this is the end
@@ -66,10 +184,191 @@ fn run(args: Vec<String>) -> Result<()> {
 	Ok(())
 }
 
+fn cluster_exactly(args: &ArgumentsModel, embeddings: &[Tensor]) -> Table<usize> {
+	println!("Computing similarities and clustering embeddings...");
+	let similarities = calc_pairwise_cosine_similarities(embeddings);
+
+	// Estimate the class count if none was given
+	let class_count = match args.class_count() {
+		Some(class_count) => class_count,
+		None => {
+			println!("Estimating class count...");
+			let estimate = estimate_class_count(similarities.as_slice(), embeddings.len(), args.max_class_count());
+
+			println!("{}", estimate);
+			estimate.chosen()
+		}
+	};
+
+	let similarity_threshold = calc_similarity_threshold(&similarities, class_count);
+	cluster_embeddings(similarities.as_slice(), similarity_threshold, embeddings.len(), class_count)
+}
+
+fn cluster_with_index(args: &ArgumentsModel, embeddings: &[Tensor]) -> Result<Table<usize>> {
+	let (values, dimension) = gen_embedding_matrix(embeddings)?;
+
+	// Reuse the index built over the same embeddings by an earlier run
+	// Like the embedding cache, the index is only an optimization, so one that cannot be read or written is rebuilt every run
+	let saved_index = match args.use_cache() {
+		true => load_index(args.cache_dir(), &values, dimension).unwrap_or_else(|err| {
+			println!("Not using the saved nearest neighbour index: {}", err);
+			None
+		}),
+		false => None
+	};
+
+	let index = match saved_index {
+		Some(index) => {
+			println!("Reusing the nearest neighbour index in {}", index_path(args.cache_dir()).display());
+			index
+		},
+		None => {
+			println!("Building a nearest neighbour index over {} embeddings...", embeddings.len());
+			let index = build_index(values, dimension);
+
+			// A dry run leaves the index unsaved, like the embeddings it computes
+			if args.stores_cache() {
+				if let Err(err) = save_index(args.cache_dir(), &index) {
+					println!("Not saving the nearest neighbour index: {}", err);
+				}
+			}
+
+			index
+		}
+	};
+
+	println!("Finding the {} nearest neighbours of each image and clustering embeddings...", args.neighbour_count());
+	let graph = gen_neighbour_graph(&index, args.neighbour_count());
+	let merges = calc_ward_merges(&index, &graph);
+
+	// Estimate the class count if none was given
+	let class_count = match args.class_count() {
+		Some(class_count) => class_count,
+		None => {
+			println!("Estimating class count...");
+			let estimate = estimate_class_count_from_merges(&index, &merges, args.max_class_count());
+
+			println!("{}", estimate);
+			estimate.chosen()
+		}
+	};
+
+	Ok(cut_merges(&merges, embeddings.len(), class_count))
+}
+
+fn embed_target_dir(args: &ArgumentsModel, model: &dyn Embeddable, model_file: Option<&Path>) -> Result<(Vec<Tensor>, Vec<PathBuf>)> {
+
+	// Reuse the embeddings of images the same model has embedded before
+	// The cache is only an optimization, so a directory it cannot be written to is embedded without it
+	let mut cache = match args.use_cache() {
+		true => match open_cache(args.cache_dir(), model, model_file, args.stores_cache()) {
+			Ok(cache) => Some(cache),
+			Err(err) => {
+				println!("Not using the embedding cache: {}", err);
+				None
+			}
+		},
+		false => None
+	};
+
+	// Read the target dir and process each image
+	println!("Generating image embeddings...");
+	let (embeddings, image_paths, missed_image_paths) = gen_image_embeddings(args.target_dir(), args.traversal(), model, args.pipeline(), cache.as_mut())?;
+
+	if let Some(cache) = &cache {
+		println!("{}", cache);
+	}
+
+	// If some images failed to process, list them
+	if missed_image_paths.len() > 0 {
+		println!("{}", format_missed_images(missed_image_paths));
+	}
+
+	Ok((embeddings, image_paths))
+}
+
+fn load_model(args: &ArgumentsModel) -> Result<(Box<dyn Embeddable>, Option<PathBuf>)> {
+
+	// Size the thread pools before anything runs on them
+	if let Some(threads) = args.threads() {
+		set_thread_count(threads);
+	}
+
+	let device = resolve_device(args.device())?;
+
+	// A TorchScript model is used as is, without looking up weights
+	if let Some(torchscript) = args.torchscript() {
+		println!("Loading TorchScript model from {}...", torchscript.display());
+
+		let model = load_torchscript_model(torchscript, args.preprocessing(), args.activation(), device)?;
+		println!("{}\n", model);
+
+		return Ok((Box::new(model), Some(torchscript.to_path_buf())));
+	}
+
+	let weights = resolve_weights(args.weights(), args.architecture(), default_weights_paths(args.architecture()))?;
+	println!("Loading model weights from {}...", weights);
+
+	let mut model = load_cnn_model(&weights, args.architecture(), args.preprocessing(), args.embedding(), args.augmentation(), device)?;
+
+	// Check how much reduced precision changes the embeddings of the first few images
+	if args.precision() != Precision::Float32 {
+		println!("Converting the network to {}...", args.precision().name());
+
+		let sample_paths: Vec<_> = collect_image_paths(args.target_dir(), args.traversal())?
+			.into_iter()
+			.take(DRIFT_SAMPLE_SIZE)
+			.collect();
+
+		if let Some(drift) = convert_precision(&mut model, args.precision(), &sample_paths)? {
+			println!("{}", drift);
+		}
+	}
+
+	println!("{}\n", model);
+
+	let model_file = match weights {
+		WeightsSource::File(path) => Some(path),
+		WeightsSource::Embedded => None
+	};
+
+	Ok((Box::new(model), model_file))
+}
+
+fn run_undo(args: UndoArgumentsModel) -> Result<()> {
+
+	// Replay the journal of the requested run backwards
+	let journal_path = find_journal(args.target_dir(), args.run_id())?;
+	println!("Undoing changes recorded in {}...", journal_path.display());
+
+	let journal = undo_journal(&journal_path)?;
+	println!("Undo complete.\n{}", journal);
+
+	Ok(())
+}
+
+fn run_cache(args: CacheArgumentsModel) -> Result<()> {
+	match args.action() {
+		CacheAction::Prune => {
+			println!("Pruning the embedding cache of {}...", args.target_dir().display());
+
+			let (kept, removed) = prune_cache(args.target_dir())?;
+			println!("Removed {} cached embeddings, kept {}.", removed, kept);
+		},
+		CacheAction::Clear => {
+			let removed = clear_cache(args.target_dir())?;
+			println!("Removed {} cached embeddings.", removed);
+		}
+	}
+
+	Ok(())
+}
+
 fn main() {
 	let args = args().collect();
 
 	if let Err(err) = run(args) {
 		eprintln!("{}", err);
+		exit(1);
 	}
 }
\ No newline at end of file
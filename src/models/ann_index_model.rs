@@ -0,0 +1,530 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+/// Bytes every index file starts with, changed whenever the layout of the file changes.
+pub const INDEX_MAGIC: &[u8; 8] = b"TSINDEX1";
+
+/// Largest number of images compared pair by pair when the similarity mode is `auto`.
+pub const DENSE_SIMILARITY_LIMIT: usize = 2000;
+
+/// Number of nearest neighbours each image is linked to in the neighbour graph, unless configured otherwise.
+pub const DEFAULT_NEIGHBOUR_COUNT: usize = 15;
+
+/// Number of links each node keeps on every layer above the bottom one, which keeps twice as many.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Number of candidates kept while searching for the neighbours of a node being inserted.
+const CONSTRUCTION_EF: usize = 100;
+
+/// Number of candidates kept while searching, at least the number of neighbours requested.
+const SEARCH_EF: usize = 64;
+
+/// How the similarities between images are computed before clustering them.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum SimilarityMode {
+    /// Exact up to `DENSE_SIMILARITY_LIMIT` images, through the index above.
+    #[default]
+    Auto,
+    /// Compare every pair of images, which takes memory and time quadratic in the number of images.
+    Exact,
+    /// Link each image to its nearest neighbours found through an approximate nearest neighbour index.
+    Index
+}
+
+impl SimilarityMode {
+
+    /// Looks up a similarity mode by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "exact" => Some(Self::Exact),
+            "index" => Some(Self::Index),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Exact => "exact",
+            Self::Index => "index"
+        }
+    }
+
+    /// Returns `true` if `embedding_count` embeddings are clustered through the index.
+    pub fn uses_index(&self, embedding_count: usize) -> bool {
+        match self {
+            Self::Auto => embedding_count > DENSE_SIMILARITY_LIMIT,
+            Self::Exact => false,
+            Self::Index => true
+        }
+    }
+}
+
+/// A node reached while searching, ordered by its similarity to the query.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Candidate {
+    similarity: f32,
+    node: usize
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then(other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn dot(v1: &[f32], v2: &[f32]) -> f32 {
+    v1.iter()
+        .zip(v2)
+        .map(|(value1, value2)| { value1 * value2 })
+        .sum()
+}
+
+/// Scales every row of a matrix to unit length, so the dot product of two rows is their cosine similarity.
+fn normalize_rows(mut values: Vec<f32>, dimension: usize) -> Vec<f32> {
+    for row in values.chunks_exact_mut(dimension) {
+        let norm = dot(row, row).sqrt();
+
+        if norm > 0.0 {
+            row.iter_mut().for_each(|value| { *value /= norm });
+        }
+    }
+
+    values
+}
+
+/// Draws the highest layer of a node from an exponentially decaying distribution.
+///
+/// The draw is a hash of the index of the node rather than a random number, so the same embeddings always build the same index.
+fn gen_level(node: usize) -> usize {
+    let mut hash = (node as u64).wrapping_add(0x9e3779b97f4a7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+
+    let uniform = ((hash >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    (-uniform.ln() / (MAX_CONNECTIONS as f64).ln()) as usize
+}
+
+fn max_links(layer: usize) -> usize {
+    match layer {
+        0 => 2 * MAX_CONNECTIONS,
+        _ => MAX_CONNECTIONS
+    }
+}
+
+/// A hierarchical navigable small world (HNSW) graph over embeddings, used to find the nearest neighbours of an embedding
+/// without comparing it to every other one.
+///
+/// Every embedding is a node on the bottom layer, and exponentially fewer are also on each layer above.
+/// A search descends greedily from the sparse top layer and then explores the neighbourhood of the closest node found on the bottom layer.
+///
+/// # Fields
+///
+/// - `key`: Identifies the embeddings the index was built over, so a saved index is only reused for the same embeddings.
+/// - `dimension`: The number of values of each embedding.
+/// - `vectors`: The embeddings scaled to unit length, in row-major order.
+/// - `links`: The neighbours of each node on each layer it is on, from the bottom layer up.
+/// - `entry_point`: The node on the highest layer that searches start from, or `None` if the index is empty.
+#[derive(PartialEq, Debug)]
+pub struct HnswIndexModel {
+    key: u64,
+    dimension: usize,
+    vectors: Vec<f32>,
+    links: Vec<Vec<Vec<u32>>>,
+    entry_point: Option<usize>
+}
+
+impl HnswIndexModel {
+
+    /// Builds an index over the rows of a matrix of embeddings.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Identifies the embeddings, see `key`.
+    /// * `values` - The embeddings in row-major order.
+    /// * `dimension` - The number of values of each embedding.
+    pub fn build(key: u64, values: Vec<f32>, dimension: usize) -> Self {
+        let mut index = Self {
+            key,
+            dimension,
+            vectors: normalize_rows(values, dimension),
+            links: vec![],
+            entry_point: None
+        };
+
+        for node in 0..index.node_count() {
+            index.insert(node);
+        }
+
+        index
+    }
+
+    /// Parses an index serialized by `serialize`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the index, or `None` if `bytes` is not an index or was built over other embeddings than `values`.
+    /// The embeddings are only copied into the index once the links are known to be valid.
+    pub fn parse(bytes: &[u8], key: u64, values: &[f32], dimension: usize) -> Option<Self> {
+        fn read(words: &mut impl Iterator<Item = Option<u32>>) -> Option<u32> {
+            words.next().flatten()
+        }
+
+        let mut words = bytes
+            .strip_prefix(INDEX_MAGIC)?
+            .chunks(4)
+            .map(|word| { word.try_into().ok().map(u32::from_le_bytes) });
+
+        let stored_key = read(&mut words)? as u64 | (read(&mut words)? as u64) << 32;
+        let node_count = read(&mut words)? as usize;
+
+        if stored_key != key || read(&mut words)? as usize != dimension || read(&mut words)? as usize != MAX_CONNECTIONS || node_count * dimension != values.len() {
+            return None;
+        }
+
+        let entry_point = match read(&mut words)? {
+            u32::MAX => None,
+            entry_point => Some(entry_point as usize)
+        };
+
+        // Every node, layer and link takes at least one word, so larger counts come from a corrupted file
+        // and are rejected before anything is allocated for them
+        if node_count > words.len() {
+            return None;
+        }
+
+        let mut links = Vec::with_capacity(node_count);
+
+        for _ in 0..node_count {
+            let layer_count = read(&mut words)? as usize;
+
+            if layer_count > words.len() {
+                return None;
+            }
+
+            let mut node_links = Vec::with_capacity(layer_count);
+
+            for _ in 0..layer_count {
+                let link_count = read(&mut words)? as usize;
+
+                if link_count > words.len() {
+                    return None;
+                }
+
+                node_links.push((0..link_count).map(|_| { read(&mut words) }).collect::<Option<Vec<u32>>>()?);
+            }
+
+            links.push(node_links);
+        }
+
+        // Every link has to lead to a node on the same layer, or searching would go out of bounds
+        let links_are_valid = links
+            .iter()
+            .all(|node_links| {
+                node_links.iter().enumerate().all(|(layer, layer_links)| {
+                    layer_links.iter().all(|link| { links.get(*link as usize).is_some_and(|other_links| { other_links.len() > layer }) })
+                })
+            });
+
+        // Searches start on the top layer of the entry point, so it needs at least one
+        let entry_point_is_valid = match entry_point {
+            Some(entry_point) => links.get(entry_point).is_some_and(|entry_links| { !entry_links.is_empty() }),
+            None => node_count == 0
+        };
+
+        (read(&mut words).is_none() && links_are_valid && entry_point_is_valid).then(|| {
+            Self {
+                key,
+                dimension,
+                vectors: normalize_rows(values.to_vec(), dimension),
+                links,
+                entry_point
+            }
+        })
+    }
+
+    /// Serializes the links of the index, as little endian 32-bit words following `INDEX_MAGIC`.
+    /// The embeddings themselves are not included, they are given again when parsing.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut words = vec![
+            self.key as u32,
+            (self.key >> 32) as u32,
+            self.node_count() as u32,
+            self.dimension as u32,
+            MAX_CONNECTIONS as u32,
+            self.entry_point.map_or(u32::MAX, |entry_point| { entry_point as u32 })
+        ];
+
+        for node_links in &self.links {
+            words.push(node_links.len() as u32);
+
+            for layer_links in node_links {
+                words.push(layer_links.len() as u32);
+                words.extend(layer_links);
+            }
+        }
+
+        let mut bytes = INDEX_MAGIC.to_vec();
+        bytes.extend(words.iter().flat_map(|word| { word.to_le_bytes() }));
+
+        bytes
+    }
+
+    /// Returns the number of embeddings in the index.
+    pub fn node_count(&self) -> usize {
+        self.vectors.len().checked_div(self.dimension).unwrap_or_default()
+    }
+
+    /// Returns the embedding of a node, scaled to unit length.
+    pub fn vector(&self, node: usize) -> &[f32] {
+        &self.vectors[node * self.dimension..(node + 1) * self.dimension]
+    }
+
+    /// Returns the cosine similarity between the embeddings of two nodes.
+    pub fn similarity(&self, node1: usize, node2: usize) -> f32 {
+        dot(self.vector(node1), self.vector(node2))
+    }
+
+    /// Searches one layer for the `ef` nodes most similar to `query`, starting from `entry_points`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the nodes found, most similar first.
+    fn search_layer(&self, query: &[f32], entry_points: &[Candidate], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entry_points.iter().map(|candidate| { candidate.node }).collect();
+        let mut candidates: BinaryHeap<Candidate> = entry_points.iter().copied().collect();
+        let mut found: BinaryHeap<Reverse<Candidate>> = entry_points.iter().copied().map(Reverse).collect();
+
+        while let Some(candidate) = candidates.pop() {
+
+            // Stop once the closest node left to explore is further than every node found
+            if found.len() >= ef && found.peek().is_some_and(|furthest| { candidate < furthest.0 }) {
+                break;
+            }
+
+            for &neighbour in &self.links[candidate.node][layer] {
+                let neighbour = neighbour as usize;
+
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+
+                let next = Candidate { similarity: dot(query, self.vector(neighbour)), node: neighbour };
+
+                if found.len() < ef || found.peek().is_some_and(|furthest| { next > furthest.0 }) {
+                    candidates.push(next);
+                    found.push(Reverse(next));
+
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        let mut found: Vec<Candidate> = found.into_iter().map(|Reverse(candidate)| { candidate }).collect();
+        found.sort_by(|candidate1, candidate2| { candidate2.cmp(candidate1) });
+
+        found
+    }
+
+    /// Picks up to `max` links among candidates sorted most similar first.
+    ///
+    /// A candidate is preferred if it is more similar to the node being linked than to any link picked before it,
+    /// so the links spread out in every direction instead of all leading into the same cluster.
+    /// The remaining places are filled with the most similar candidates that were passed over.
+    fn select_links(&self, candidates: &[Candidate], max: usize) -> Vec<u32> {
+        let mut selected: Vec<Candidate> = vec![];
+        let mut passed_over = vec![];
+
+        for &candidate in candidates {
+            if selected.len() >= max {
+                break;
+            }
+
+            match selected.iter().all(|link| { self.similarity(candidate.node, link.node) < candidate.similarity }) {
+                true => selected.push(candidate),
+                false => passed_over.push(candidate)
+            }
+        }
+
+        let remaining = max - selected.len();
+        selected.extend(passed_over.into_iter().take(remaining));
+
+        selected
+            .iter()
+            .map(|link| { link.node as u32 })
+            .collect()
+    }
+
+    /// Links a node into every layer up to its level, which is drawn by `gen_level`.
+    fn insert(&mut self, node: usize) {
+        let level = gen_level(node);
+        self.links.push(vec![vec![]; level + 1]);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(node);
+            return;
+        };
+
+        let query = self.vector(node).to_vec();
+        let top_layer = self.links[entry_point].len() - 1;
+        let mut entry_points = vec![Candidate { similarity: dot(&query, self.vector(entry_point)), node: entry_point }];
+
+        // Descend greedily through the layers above the level of the node
+        for layer in (level + 1..=top_layer).rev() {
+            entry_points = self.search_layer(&query, &entry_points, 1, layer);
+        }
+
+        for layer in (0..=level.min(top_layer)).rev() {
+            let found = self.search_layer(&query, &entry_points, CONSTRUCTION_EF, layer);
+            let links = self.select_links(&found, MAX_CONNECTIONS);
+
+            // Link back from every neighbour, dropping its least useful link if it has too many
+            for &neighbour in &links {
+                let neighbour = neighbour as usize;
+                self.links[neighbour][layer].push(node as u32);
+
+                if self.links[neighbour][layer].len() > max_links(layer) {
+                    let mut candidates: Vec<Candidate> = self.links[neighbour][layer]
+                        .iter()
+                        .map(|&other| { Candidate { similarity: self.similarity(neighbour, other as usize), node: other as usize } })
+                        .collect();
+
+                    candidates.sort_by(|candidate1, candidate2| { candidate2.cmp(candidate1) });
+                    self.links[neighbour][layer] = self.select_links(&candidates, max_links(layer));
+                }
+            }
+
+            self.links[node][layer] = links;
+            entry_points = found;
+        }
+
+        if level > top_layer {
+            self.entry_point = Some(node);
+        }
+    }
+
+    /// Searches the index for the embeddings most similar to `query`, which must have unit length.
+    ///
+    /// # Returns
+    ///
+    /// Returns up to `count` nodes along with their cosine similarity to `query`, most similar first.
+    pub fn search(&self, query: &[f32], count: usize) -> Vec<(usize, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return vec![];
+        };
+
+        let mut entry_points = vec![Candidate { similarity: dot(query, self.vector(entry_point)), node: entry_point }];
+
+        for layer in (1..self.links[entry_point].len()).rev() {
+            entry_points = self.search_layer(query, &entry_points, 1, layer);
+        }
+
+        self.search_layer(query, &entry_points, SEARCH_EF.max(count), 0)
+            .into_iter()
+            .take(count)
+            .map(|candidate| { (candidate.node, candidate.similarity) })
+            .collect()
+    }
+
+    /// Returns the `count` nodes most similar to a node, not counting the node itself.
+    pub fn nearest_neighbours(&self, node: usize, count: usize) -> Vec<usize> {
+        self.search(self.vector(node), count + 1)
+            .into_iter()
+            .map(|(neighbour, _)| { neighbour })
+            .filter(|neighbour| { *neighbour != node })
+            .take(count)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates points scattered around a few well separated centers.
+    fn gen_values(count: usize, dimension: usize) -> Vec<f32> {
+        let mut state = 1u64;
+
+        (0..count * dimension)
+            .map(|index| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let noise = (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+                let center = (index / dimension) % 3 == (index % dimension) % 3;
+
+                if center { 2.0 + noise } else { noise }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn search_finds_nearest_neighbours() {
+        let (count, dimension) = (600, 12);
+        let index = HnswIndexModel::build(7, gen_values(count, dimension), dimension);
+        let mut matches = 0;
+
+        for node in (0..count).step_by(20) {
+            let mut exact: Vec<usize> = (0..count).filter(|other| { *other != node }).collect();
+            exact.sort_by(|other1, other2| { index.similarity(node, *other2).total_cmp(&index.similarity(node, *other1)) });
+
+            let approximate = index.nearest_neighbours(node, 10);
+            assert!(!approximate.contains(&node));
+
+            matches += approximate.iter().filter(|neighbour| { exact[..10].contains(neighbour) }).count();
+        }
+
+        // Recall of the approximate search
+        assert!(matches >= 270, "{}", matches);
+        assert_eq!(index.search(index.vector(42), 1)[0].0, 42);
+    }
+
+    #[test]
+    fn index_round_trips() {
+        let values = gen_values(200, 6);
+        let index = HnswIndexModel::build(7, values.clone(), 6);
+        let bytes = index.serialize();
+
+        assert_eq!(HnswIndexModel::parse(&bytes, 7, &values, 6), Some(index));
+
+        // Another set of embeddings or a cut off file is not reused
+        assert_eq!(HnswIndexModel::parse(&bytes, 8, &values, 6), None);
+        assert_eq!(HnswIndexModel::parse(&bytes, 7, &values[..600], 6), None);
+        assert_eq!(HnswIndexModel::parse(&bytes[..bytes.len() - 4], 7, &values, 6), None);
+
+        // Counts larger than the rest of the file are rejected without allocating for them
+        let mut corrupted = bytes.clone();
+        corrupted[INDEX_MAGIC.len() + 24..INDEX_MAGIC.len() + 28].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(HnswIndexModel::parse(&corrupted, 7, &values, 6), None);
+
+        // An entry point without any layer cannot start a search
+        let words = [7, 0, 1, 6, MAX_CONNECTIONS as u32, 0, 0];
+        let empty_entry_point: Vec<u8> = INDEX_MAGIC.iter().copied().chain(words.iter().flat_map(|word| { word.to_le_bytes() })).collect();
+        assert_eq!(HnswIndexModel::parse(&empty_entry_point, 7, &values[..6], 6), None);
+
+        assert_eq!(HnswIndexModel::build(0, vec![], 4).search(&[1.0, 0.0, 0.0, 0.0], 3), vec![]);
+    }
+
+    #[test]
+    fn similarity_mode_names_round_trip() {
+        for mode in [SimilarityMode::Auto, SimilarityMode::Exact, SimilarityMode::Index] {
+            assert_eq!(SimilarityMode::from_name(mode.name()), Some(mode));
+        }
+
+        assert!(!SimilarityMode::Auto.uses_index(DENSE_SIMILARITY_LIMIT));
+        assert!(SimilarityMode::Auto.uses_index(DENSE_SIMILARITY_LIMIT + 1));
+        assert!(!SimilarityMode::Exact.uses_index(usize::MAX));
+    }
+}
\ No newline at end of file
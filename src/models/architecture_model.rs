@@ -0,0 +1,164 @@
+use std::fmt::{Debug, Formatter, Result};
+use tch::nn::{ModuleT, Path};
+use tch::vision::{alexnet, convmixer, densenet, efficientnet, imagenet, inception, mobilenet, resnet, squeezenet, vgg};
+use crate::models::feature_network_model::{self, FeatureNetwork};
+use crate::models::preprocessing_model::{Normalization, PreprocessingModel, ResizeStrategy};
+
+// Define a type alias for the CNN signature
+pub type CNNSignature = fn(p: &Path, class_count: i64) -> Box<dyn ModuleT>;
+
+// Define a type alias for the signature of a network split into stages
+pub type FeaturesSignature = fn(p: &Path, class_count: i64) -> FeatureNetwork;
+
+/// Describes how feature vectors can be read from inside an architecture.
+///
+/// # Fields
+///
+/// - `build`: Builds the network split into stages, with the same variables as `ArchitectureSpec::build`.
+/// - `layers`: The name of each stage, from first to last.
+pub struct FeatureSpec {
+    pub build: FeaturesSignature,
+    pub layers: &'static [&'static str]
+}
+
+const RESNET_LAYERS: &[&str] = &["layer1", "layer2", "layer3", "layer4"];
+const DENSENET_LAYERS: &[&str] = &["denseblock1", "denseblock2", "denseblock3", "denseblock4"];
+
+/// Describes a backbone network that images can be embedded with.
+///
+/// # Fields
+///
+/// - `name`: The name the architecture is selected by, and the stem of its default weights file.
+/// - `build`: Builds the network under the given variable store path.
+/// - `input_size`: The width and height images are resized to before being passed to the network.
+/// - `output_dim`: The number of values the network outputs, which is the length of each embedding.
+/// - `features`: How feature vectors are read from inside the network, if supported.
+/// - `description`: A short description shown in the usage text.
+pub struct ArchitectureSpec {
+    pub name: &'static str,
+    pub build: CNNSignature,
+    pub features: Option<FeatureSpec>,
+    pub input_size: i64,
+    pub output_dim: i64,
+    pub description: &'static str
+}
+
+/// Name of the architecture used unless another one is selected.
+pub const DEFAULT_ARCHITECTURE: &str = "resnet34";
+
+/// Every architecture that can be selected, in the order they are listed in the usage text.
+///
+/// All of them are ImageNet classifiers, so their outputs can also be used to name classes.
+pub const ARCHITECTURES: &[ArchitectureSpec] = &[
+    ArchitectureSpec { name: "resnet18", build: |p, n| { Box::new(resnet::resnet18(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::resnet(p, n, [2, 2, 2, 2], false) }, layers: RESNET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Light residual network" },
+    ArchitectureSpec { name: "resnet34", build: |p, n| { Box::new(resnet::resnet34(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::resnet(p, n, [3, 4, 6, 3], false) }, layers: RESNET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Residual network (default)" },
+    ArchitectureSpec { name: "resnet50", build: |p, n| { Box::new(resnet::resnet50(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::resnet(p, n, [3, 4, 6, 3], true) }, layers: RESNET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Heavier residual network" },
+    ArchitectureSpec { name: "resnet101", build: |p, n| { Box::new(resnet::resnet101(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::resnet(p, n, [3, 4, 23, 3], true) }, layers: RESNET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Heavy residual network" },
+    ArchitectureSpec { name: "resnet152", build: |p, n| { Box::new(resnet::resnet152(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::resnet(p, n, [3, 8, 36, 3], true) }, layers: RESNET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Heaviest residual network" },
+    ArchitectureSpec { name: "densenet121", build: |p, n| { Box::new(densenet::densenet121(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::densenet(p, n, 64, 32, [6, 12, 24, 16]) }, layers: DENSENET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Densely connected network" },
+    ArchitectureSpec { name: "densenet161", build: |p, n| { Box::new(densenet::densenet161(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::densenet(p, n, 96, 48, [6, 12, 36, 24]) }, layers: DENSENET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Wide densely connected network" },
+    ArchitectureSpec { name: "densenet169", build: |p, n| { Box::new(densenet::densenet169(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::densenet(p, n, 64, 32, [6, 12, 32, 32]) }, layers: DENSENET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Deeper densely connected network" },
+    ArchitectureSpec { name: "densenet201", build: |p, n| { Box::new(densenet::densenet201(p, n)) }, features: Some(FeatureSpec { build: |p, n| { feature_network_model::densenet(p, n, 64, 32, [6, 12, 48, 32]) }, layers: DENSENET_LAYERS }), input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Deepest densely connected network" },
+    ArchitectureSpec { name: "vgg11", build: |p, n| { Box::new(vgg::vgg11(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 11 layers" },
+    ArchitectureSpec { name: "vgg11_bn", build: |p, n| { Box::new(vgg::vgg11_bn(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 11 layers with batch normalization" },
+    ArchitectureSpec { name: "vgg13", build: |p, n| { Box::new(vgg::vgg13(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 13 layers" },
+    ArchitectureSpec { name: "vgg13_bn", build: |p, n| { Box::new(vgg::vgg13_bn(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 13 layers with batch normalization" },
+    ArchitectureSpec { name: "vgg16", build: |p, n| { Box::new(vgg::vgg16(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 16 layers" },
+    ArchitectureSpec { name: "vgg16_bn", build: |p, n| { Box::new(vgg::vgg16_bn(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 16 layers with batch normalization" },
+    ArchitectureSpec { name: "vgg19", build: |p, n| { Box::new(vgg::vgg19(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 19 layers" },
+    ArchitectureSpec { name: "vgg19_bn", build: |p, n| { Box::new(vgg::vgg19_bn(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "VGG, 19 layers with batch normalization" },
+    ArchitectureSpec { name: "squeezenet1_0", build: |p, n| { Box::new(squeezenet::v1_0(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Very light, suited to CPU-only machines" },
+    ArchitectureSpec { name: "squeezenet1_1", build: |p, n| { Box::new(squeezenet::v1_1(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Very light and faster, suited to CPU-only machines" },
+    ArchitectureSpec { name: "mobilenet_v2", build: |p, n| { Box::new(mobilenet::v2(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "Light, suited to CPU-only machines" },
+    ArchitectureSpec { name: "efficientnet_b0", build: |p, n| { Box::new(efficientnet::b0(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet, smallest" },
+    ArchitectureSpec { name: "efficientnet_b1", build: |p, n| { Box::new(efficientnet::b1(p, n)) }, features: None, input_size: 240, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet" },
+    ArchitectureSpec { name: "efficientnet_b2", build: |p, n| { Box::new(efficientnet::b2(p, n)) }, features: None, input_size: 260, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet" },
+    ArchitectureSpec { name: "efficientnet_b3", build: |p, n| { Box::new(efficientnet::b3(p, n)) }, features: None, input_size: 300, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet" },
+    ArchitectureSpec { name: "efficientnet_b4", build: |p, n| { Box::new(efficientnet::b4(p, n)) }, features: None, input_size: 380, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet" },
+    ArchitectureSpec { name: "efficientnet_b5", build: |p, n| { Box::new(efficientnet::b5(p, n)) }, features: None, input_size: 456, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet" },
+    ArchitectureSpec { name: "efficientnet_b6", build: |p, n| { Box::new(efficientnet::b6(p, n)) }, features: None, input_size: 528, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet" },
+    ArchitectureSpec { name: "efficientnet_b7", build: |p, n| { Box::new(efficientnet::b7(p, n)) }, features: None, input_size: 600, output_dim: imagenet::CLASS_COUNT, description: "EfficientNet, largest" },
+    ArchitectureSpec { name: "inception_v3", build: |p, n| { Box::new(inception::v3(p, n)) }, features: None, input_size: 299, output_dim: imagenet::CLASS_COUNT, description: "Inception" },
+    ArchitectureSpec { name: "alexnet", build: |p, n| { Box::new(alexnet::alexnet(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "AlexNet" },
+    ArchitectureSpec { name: "convmixer1024_20", build: |p, n| { Box::new(convmixer::c1024_20(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "ConvMixer, 1024 wide" },
+    ArchitectureSpec { name: "convmixer1536_20", build: |p, n| { Box::new(convmixer::c1536_20(p, n)) }, features: None, input_size: 224, output_dim: imagenet::CLASS_COUNT, description: "ConvMixer, 1536 wide" }
+];
+
+impl ArchitectureSpec {
+
+    /// Looks up an architecture by its name.
+    pub fn from_name(name: &str) -> Option<&'static Self> {
+        ARCHITECTURES
+            .iter()
+            .find(|architecture| { architecture.name == name })
+    }
+
+    /// Returns the architecture used unless another one is selected.
+    pub fn default_architecture() -> &'static Self {
+        Self::from_name(DEFAULT_ARCHITECTURE).unwrap()
+    }
+
+    /// Looks up a layer feature vectors can be read from, returning `None` if the layer does not exist or features are not supported.
+    pub fn feature_layer(&self, name: &str) -> Option<&'static str> {
+        self.features
+            .as_ref()?
+            .layers
+            .iter()
+            .find(|layer| { **layer == name })
+            .copied()
+    }
+
+    /// Returns how images are prepared for the network unless configured otherwise, the way it was trained on ImageNet.
+    pub fn default_preprocessing(&self) -> PreprocessingModel {
+        PreprocessingModel::new(ResizeStrategy::CenterCrop, self.input_size, Normalization::ImageNet)
+    }
+
+    /// Returns the file name weights for this architecture are looked up by, such as `resnet34.ot`.
+    pub fn weights_name(&self) -> String {
+        format!("{}.ot", self.name)
+    }
+}
+
+// Architectures are unique by name, so they can be compared without comparing function pointers
+impl PartialEq for ArchitectureSpec {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Debug for ArchitectureSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "ArchitectureSpec({})", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_works() {
+        let architecture = ArchitectureSpec::from_name("efficientnet_b3").unwrap();
+
+        assert_eq!(architecture.input_size, 300);
+        assert_eq!(architecture.weights_name(), "efficientnet_b3.ot");
+        assert_eq!(ArchitectureSpec::default_architecture().name, DEFAULT_ARCHITECTURE);
+        assert!(ArchitectureSpec::from_name("resnet35").is_none());
+    }
+
+    #[test]
+    fn feature_layer_works() {
+        let architecture = ArchitectureSpec::from_name("densenet121").unwrap();
+
+        assert_eq!(architecture.feature_layer("denseblock3"), Some("denseblock3"));
+        assert_eq!(architecture.feature_layer("layer3"), None);
+        assert_eq!(ArchitectureSpec::from_name("alexnet").unwrap().feature_layer("features"), None);
+    }
+
+    #[test]
+    fn architecture_names_are_unique() {
+        for (i, architecture) in ARCHITECTURES.iter().enumerate() {
+            assert!(ARCHITECTURES[i + 1..].iter().all(|other| { other.name != architecture.name }));
+        }
+    }
+}
\ No newline at end of file
@@ -1,94 +1,715 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use crate::errors::InvalidUsageError;
+use crate::models::ann_index_model::{SimilarityMode, DEFAULT_NEIGHBOUR_COUNT};
+use crate::models::architecture_model::ArchitectureSpec;
+use crate::models::augmentation_model::{AugmentationModel, AugmentationView, ViewReduction};
+use crate::models::cli_model::{split_command, Command, ParsedArgsModel, HELP_OPTION, VERSION_OPTION};
+use crate::models::cnn_model::EmbeddingMode;
+use crate::models::config_model::{parse_bool, ConfigModel, ConfigSource, ConfigValue};
+use crate::models::device_model::DeviceSelection;
+use crate::models::embedding_cache_model::CacheAction;
+use crate::models::export_model::ExportModel;
+use crate::models::feature_network_model::Pooling;
+use crate::models::import_model::ImportModel;
+use crate::models::pipeline_model::{default_worker_count, PipelineModel, DEFAULT_BATCH_SIZE, DEFAULT_QUEUE_DEPTH};
+use crate::models::sort_plan_model::{ConflictPolicyModel, DirConflictPolicy, FileConflictPolicy, PlacementStrategy};
+use crate::models::precision_model::Precision;
+use crate::models::preprocessing_model::{parse_channel_values, Normalization, PreprocessingModel, ResizeStrategy, DEFAULT_INPUT_SIZE};
+use crate::models::torchscript_model::OutputActivation;
+use crate::models::traversal_model::TraversalModel;
 
-const MIN_ARG_COUNT: usize = 3;
+/// Reads the effective value of each setting, recording where it came from.
+///
+/// Command line options take precedence over the configuration, which already
+/// layers the environment over config files.
+struct SettingsReader<'a> {
+    parsed: &'a ParsedArgsModel,
+    config: &'a ConfigModel,
+    sources: HashMap<&'static str, ConfigSource>
+}
+
+impl<'a> SettingsReader<'a> {
+
+    fn new(parsed: &'a ParsedArgsModel, config: &'a ConfigModel) -> Self {
+        Self {
+            parsed,
+            config,
+            sources: HashMap::new()
+        }
+    }
+
+    /// Looks up a setting on the command line, then in the configuration.
+    fn lookup(&self, key: &str) -> Option<ConfigValue> {
+        let from_command_line = match key {
+            "class-count" => self.parsed.positional(1),
+            _ if self.parsed.flag(key) => Some(self.parsed.value(key).unwrap_or("true")),
+            _ => None
+        };
+
+        match from_command_line {
+            Some(value) => Some(ConfigValue { value: value.to_string(), source: ConfigSource::CommandLine }),
+            None => self.config.get(key).cloned()
+        }
+    }
+
+    /// Parses the value of a setting with `parse`, reporting `message` if it is invalid.
+    fn value<T>(&mut self, key: &'static str, parse: impl Fn(&str) -> Option<T>, message: &str) -> Result<Option<T>, InvalidUsageError> {
+        let Some(setting) = self.lookup(key) else {
+            return Ok(None);
+        };
+
+        let value = parse(&setting.value).ok_or_else(|| {
+            let origin = match &setting.source {
+                ConfigSource::CommandLine => String::new(),
+                source => format!(", from {}", source)
+            };
+
+            InvalidUsageError::new(self.parsed.command(), format!("{} ({}{})", message, setting.value, origin))
+        })?;
 
-/// This module defines the command-line interface for the application.
+        self.sources.insert(key, setting.source);
+        Ok(Some(value))
+    }
+
+    /// Reads a flag, which is `false` unless it was given.
+    fn flag(&mut self, key: &'static str) -> Result<bool, InvalidUsageError> {
+        Ok(self.value(key, parse_bool, "Invalid boolean provided")?.unwrap_or(false))
+    }
+}
+
+/// Parses the target directory, which is always the first positional argument.
+fn parse_target_dir(parsed: &ParsedArgsModel) -> Result<PathBuf, InvalidUsageError> {
+    let target_dir = PathBuf::from(parsed.positional(0).unwrap_or_default());
+
+    if !target_dir.is_dir() {
+        return Err(InvalidUsageError::new(parsed.command(), "Provided path is not a directory"));
+    }
+
+    Ok(target_dir)
+}
+
+/// This module defines the command-line interface for the sorting commands
+/// (`sort`, `embed`, `cluster` and `name`). The accepted arguments of each
+/// command are defined in `cli_model::COMMANDS`.
 ///
 /// # Arguments
 ///
-/// The application requires the following arguments:
+/// The commands require the following arguments:
 ///
 /// - `target_dir`: The directory to be processed.
-/// - `class_count`: The number of classes to be used in the model.
+/// - `class_count`: The number of classes to be used in the model. Not used by `embed`.
+///   Optional, when left out it is estimated from the embeddings by trying every count up to `max_classes`.
+///
+/// Every sorting command accepts the following optional arguments:
+///
+/// - `model`: The backbone architecture images are embedded with (see `architecture_model::ARCHITECTURES`). Defaults to `resnet34`.
+/// - `weights`: The model weights file. Defaults to the first `<model>.ot` found in the default locations
+///   (see `weights_controller::default_weights_paths`).
+/// - `embedding`: Whether images are embedded as class `probabilities` or pooled `features`. Defaults to `probabilities`.
+/// - `layer`: The layer features are read from, such as `layer3`. Defaults to the last layer before the classifier.
+/// - `pooling`: How features are pooled (`avg`, `max` or `gem`). Defaults to `avg`.
+/// - `augment`: The extra views of each image that are embedded (`flip`, `crops` and `scales`, separated by commas). Defaults to none.
+/// - `augment_reduce`: How the embeddings of the views are combined (`mean` or `max`). Defaults to `mean`.
+/// - `precision`: The precision the network computes in (`fp32`, `fp16` or `bf16`), or `int8` to quantize only its classifier head. Defaults to `fp32`.
+/// - `torchscript`: A TorchScript (`.pt`) model to embed images with instead of `model`. Cannot be combined with `weights`.
+/// - `resize`: How images are fitted into the input of the model (`crop`, `letterbox` or `stretch`). Defaults to `crop`.
+/// - `input_size`: The image size the model expects. Defaults to that of `model`, or `224` for a TorchScript model.
+/// - `normalization`: How pixel values are scaled (`imagenet`, `zero-one` or `raw`). Defaults to `imagenet`.
+/// - `mean` and `std`: The mean and standard deviation of each RGB channel, given together in place of `normalization`.
+/// - `activation`: What is applied to the TorchScript output (`none`, `softmax` or `l2`). Defaults to `none`.
+/// - `device`: The device the model runs on (`cpu`, `cuda:N` or `auto`). Defaults to `auto`, the first CUDA device if there is one.
+/// - `threads`: The number of CPU threads the model uses. Defaults to the number of cores.
+/// - `batch_size`: How many images are passed through the model at once. Defaults to `16`.
+/// - `workers`: How many threads decode images ahead of the model, `0` to decode on the model thread. Defaults to the number of cores.
+/// - `queue_depth`: How many decoded images may wait for the model. Defaults to `64`.
+/// - `no_cache`: A flag that, if present, indicates that embeddings should neither be read from nor stored in the cache of `target_dir`. Defaults to `false`.
+///
+/// The `embed` command accepts the following optional argument:
+///
+/// - `export`: The file the embeddings are written to, in the `.npy`, `.npz`, `.safetensors` or `.csv` format chosen by its extension.
+///
+/// Every command except `embed` accepts the following optional arguments:
 ///
-/// In addition, the application accepts the following optional arguments:
+/// - `max_classes`: The largest class count tried when estimating it. Defaults to `10`.
+/// - `similarity`: Whether every pair of images is compared (`exact`) or images are clustered from their nearest neighbours found through an index (`index`).
+///   Defaults to `auto`, which uses the index above `ann_index_model::DENSE_SIMILARITY_LIMIT` images.
+/// - `neighbours`: How many nearest neighbours each image is linked to when clustering through the index. Defaults to `15`.
+/// - `import`: A `.npy`, `.npz`, `.safetensors` or `.csv` file of embeddings computed elsewhere, which are clustered instead of
+///   embedding the images of `target_dir`. No model is loaded, so the model and image settings are ignored.
+/// - `image_list`: A text file with the path of the image of each imported embedding, one per line.
+///   Defaults to the paths stored with the embeddings by an export.
+///
+/// In addition, the `sort` command accepts the following optional arguments:
 ///
 /// - `no_names`: A flag that, if present, indicates that names should not be used. Defaults to `false`.
+/// - `dry_run`: A flag that, if present, indicates that the planned changes should only be printed. Defaults to `false`.
+/// - `output_dir`: The directory the class tree is built in. Defaults to `target_dir`.
+/// - `placement`: How files are placed into class directories (`move`, `copy`, `symlink` or `hardlink`).
+///   Defaults to `move`, or to `copy` when `output_dir` is given so the target directory is left untouched.
+/// - `dir_conflict`: What to do when a class directory already exists (`merge` or `suffix`). Defaults to `merge`.
+/// - `file_conflict`: What to do when a destination file already exists (`skip`, `rename` or `identical`). Defaults to `rename`.
+///
+/// Every sorting command accepts the following optional arguments:
+///
+/// - `recursive`: A flag that, if present, indicates that subdirectories should be searched. Defaults to `false`.
+/// - `max_depth`: How many levels of subdirectories to search, implies `recursive`. Defaults to unlimited.
+/// - `follow_symlinks`: A flag that, if present, indicates that symbolic links to directories should be followed. Defaults to `false`.
+/// - `hidden`: A flag that, if present, indicates that hidden files and directories should be included. Defaults to `false`.
+///
+/// Every setting except `target_dir`, `dry_run`, `export`, `import` and `image_list` can also be given in a config file or a `TENSORT_*`
+/// environment variable (see `config_model::SETTINGS`), for example the `class_count`.
+/// The command line takes precedence, and where each value came from is kept in `sources`.
 ///
 /// # Example
 ///
 /// ```
-/// program_name target_dir class_count --no_names
+/// program_name sort target_dir class_count --no-names --dry-run --output sorted_dir --max-depth 2
 /// ```
 #[derive(PartialEq, Debug)]
 pub struct ArgumentsModel {
+    command: Command,
     target_dir: PathBuf,
-    class_count: usize,
-    should_not_gen_names: bool
+    output_dir: Option<PathBuf>,
+    architecture: &'static ArchitectureSpec,
+    weights: Option<PathBuf>,
+    embedding: EmbeddingMode,
+    augmentation: AugmentationModel,
+    precision: Precision,
+    torchscript: Option<PathBuf>,
+    preprocessing: PreprocessingModel,
+    activation: OutputActivation,
+    device: DeviceSelection,
+    threads: Option<usize>,
+    pipeline: PipelineModel,
+    use_cache: bool,
+    export: Option<ExportModel>,
+    import: Option<ImportModel>,
+    class_count: Option<usize>,
+    max_class_count: usize,
+    similarity: SimilarityMode,
+    neighbour_count: usize,
+    should_not_gen_names: bool,
+    dry_run: bool,
+    placement: PlacementStrategy,
+    conflicts: ConflictPolicyModel,
+    traversal: TraversalModel,
+    sources: HashMap<&'static str, ConfigSource>
 }
 
+/// Largest class count tried when estimating it, unless configured otherwise.
+pub const DEFAULT_MAX_CLASS_COUNT: usize = 10;
+
+/// Source of every setting that was left at its default value.
+static DEFAULT_SOURCE: ConfigSource = ConfigSource::Default;
+
 impl ArgumentsModel {
     
-    /// Factory method for a new arguments structure <br />
-    /// Represents the values passed to the CLI in the context of this tool
-    pub fn from(args: Vec<String>) -> Result<Self, InvalidUsageError> {
+    /// Builds the arguments structure from the parsed arguments of a sorting command. <br />
+    /// Settings that were not given on the command line are taken from `config`
+    pub fn from_parsed(parsed: &ParsedArgsModel, config: &ConfigModel) -> Result<Self, InvalidUsageError> {
+        let command = parsed.command();
+        let target_dir = parse_target_dir(parsed)?;
+        let mut settings = SettingsReader::new(parsed, config);
 
-        // Check if enough arguments are provided
-        if args.len() < MIN_ARG_COUNT {
-            return Err(InvalidUsageError("Not enough arguments provided"));
+        let architecture = settings
+            .value("model", ArchitectureSpec::from_name, "Unknown model architecture provided")?
+            .unwrap_or_else(ArchitectureSpec::default_architecture);
+        let weights = settings.value("weights", |weights| { Some(PathBuf::from(weights)) }, "Invalid weights path provided")?;
+        let features = settings
+            .value("embedding", |embedding| { match embedding { "probabilities" => Some(false), "features" => Some(true), _ => None } }, "Invalid embedding provided")?
+            .unwrap_or(false);
+        let layer = settings.value("layer", |layer| { architecture.feature_layer(layer) }, "Unknown layer provided for the selected model")?;
+        let pooling = settings.value("pooling", Pooling::from_name, "Invalid pooling provided")?;
+        let augment = settings.value("augment", AugmentationView::from_names, "Invalid augmentation views provided")?;
+        let augment_reduce = settings.value("augment-reduce", ViewReduction::from_name, "Invalid augmentation reduction provided")?;
+        let precision = settings.value("precision", Precision::from_name, "Invalid precision provided")?;
+        let torchscript = settings.value("torchscript", |torchscript| { Some(PathBuf::from(torchscript)) }, "Invalid TorchScript path provided")?;
+        let resize = settings.value("resize", ResizeStrategy::from_name, "Invalid resize strategy provided")?;
+        let input_size = settings.value("input-size", |input_size| { input_size.parse::<i64>().ok().filter(|size| { *size > 0 }) }, "Invalid number provided for input size")?;
+        let normalization = settings.value("normalization", Normalization::from_name, "Invalid normalization provided")?;
+        let mean = settings.value("mean", parse_channel_values, "Invalid channel means provided")?;
+        let std = settings.value("std", |std| { parse_channel_values(std).filter(|std| { std.iter().all(|value| { *value > 0.0 }) }) }, "Invalid channel standard deviations provided")?;
+        let activation = settings.value("activation", OutputActivation::from_name, "Invalid activation provided")?;
+        let device = settings.value("device", DeviceSelection::from_name, "Invalid device provided")?;
+        let threads = settings.value("threads", |threads| { threads.parse::<usize>().ok().filter(|count| { *count > 0 }) }, "Invalid number provided for threads")?;
+        let batch_size = settings.value("batch-size", |batch_size| { batch_size.parse::<usize>().ok().filter(|count| { *count > 0 }) }, "Invalid number provided for batch size")?;
+        let workers = settings.value("workers", |workers| { workers.parse::<usize>().ok() }, "Invalid number provided for workers")?;
+        let queue_depth = settings.value("queue-depth", |queue_depth| { queue_depth.parse::<usize>().ok().filter(|count| { *count > 0 }) }, "Invalid number provided for queue depth")?;
+        let no_cache = settings.flag("no-cache")?;
+
+        if weights.is_some() && torchscript.is_some() {
+            return Err(InvalidUsageError::new(command, "Weights cannot be provided for a TorchScript model"));
         }
 
-        let target_dir = PathBuf::from(args[1].clone());
-        if !target_dir.is_dir() {
-            return Err(InvalidUsageError("Provided path is not a directory"));
+        if precision.is_some() && torchscript.is_some() {
+            return Err(InvalidUsageError::new(command, "Precision cannot be changed for TorchScript models"));
         }
 
-        let class_count = match args[2].parse::<usize>() {
-            Ok(class_count) => class_count,
-            Err(_) => {
-                return Err(InvalidUsageError("Invalid number provided for class count"));
+        // Only the networks split into feature stages have a classifier head that can be quantized
+        if precision == Some(Precision::Int8) && architecture.features.is_none() {
+            return Err(InvalidUsageError::new(command, format!("int8 only quantizes the classifier head of ResNet and DenseNet, not {}", architecture.name)));
+        }
+
+        // Images are prepared the way the architecture was trained unless configured otherwise
+        let default_preprocessing = architecture.default_preprocessing();
+
+        // A custom mean and standard deviation replace the named normalizations
+        let normalization = match (normalization, mean, std) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                return Err(InvalidUsageError::new(command, "Mean and standard deviation cannot be combined with a normalization"));
+            },
+            (normalization, None, None) => normalization.unwrap_or(default_preprocessing.normalization()),
+            (None, Some(mean), Some(std)) => Normalization::MeanStd { mean, std },
+            (None, _, _) => {
+                return Err(InvalidUsageError::new(command, "Mean and standard deviation must be provided together"));
             }
         };
 
-        let gen_names = match args.get(3) {
-            Some(flag) => {
-                match flag.as_str() {
-                    "-n" | "--no-names" => true,
-                    _ => false
-                }
+        // The output activation is part of a TorchScript model, the built-in architectures have their own
+        if activation.is_some() && torchscript.is_none() {
+            return Err(InvalidUsageError::new(command, "Activation only applies to TorchScript models"));
+        }
+
+        let input_size = match &torchscript {
+            Some(_) => input_size.unwrap_or(DEFAULT_INPUT_SIZE),
+            None => input_size.unwrap_or(default_preprocessing.input_size())
+        };
+
+        // Views are generated by the built-in architectures, and their reduction only applies when there are views
+        let augmentation = match (augment, augment_reduce) {
+            (Some(_), _) if torchscript.is_some() => {
+                return Err(InvalidUsageError::new(command, "Test-time augmentation is not available for TorchScript models"));
+            },
+            (Some(views), reduction) => AugmentationModel::new(&views, reduction.unwrap_or_default()),
+            (None, None) => AugmentationModel::default(),
+            (None, Some(_)) => {
+                return Err(InvalidUsageError::new(command, "Augmentation reduction only applies when views are augmented"));
+            }
+        };
+
+        // Layers and pooling only apply when reading features from one of the built-in architectures
+        let embedding = match (features, layer, pooling) {
+            (true, _, _) if torchscript.is_some() => {
+                return Err(InvalidUsageError::new(command, "Feature embeddings are not available for TorchScript models"));
+            },
+            (true, _, _) if architecture.features.is_none() => {
+                return Err(InvalidUsageError::new(command, format!("The {} architecture does not support feature embeddings", architecture.name)));
             },
-            None => false
+            (true, layer, pooling) => EmbeddingMode::Features { layer, pooling: pooling.unwrap_or_default() },
+            (false, None, None) => EmbeddingMode::Probabilities,
+            (false, _, _) => {
+                return Err(InvalidUsageError::new(command, "Layer and pooling only apply to feature embeddings"));
+            }
+        };
+
+        // Exports name a file of their own for every run, so they are only read from the command line
+        let export = match parsed.value("export") {
+            Some(export) => Some(ExportModel::new(Path::new(export))
+                .ok_or_else(|| { InvalidUsageError::new(command, "Unknown export format, use a .npy, .npz, .safetensors or .csv file") })?),
+            None => None
+        };
+
+        // Imported embeddings are clustered in place of those of the target directory
+        let import = match (parsed.value("import"), parsed.value("image-list")) {
+            (Some(import), image_list) => Some(ImportModel::new(Path::new(import), image_list.map(Path::new))
+                .ok_or_else(|| { InvalidUsageError::new(command, "Unknown import format, use a .npy, .npz, .safetensors or .csv file") })?),
+            (None, None) => None,
+            (None, Some(_)) => {
+                return Err(InvalidUsageError::new(command, "Image list only applies to imported embeddings"));
+            }
+        };
+
+        // Every command except `embed` groups images, into an estimated number of classes unless a count is given
+        let (class_count, max_class_count) = match command {
+            Command::Embed => (None, DEFAULT_MAX_CLASS_COUNT),
+            _ => (
+                settings.value("class-count", |class_count| { class_count.parse::<usize>().ok().filter(|count| { *count > 0 }) }, "Invalid number provided for class count")?,
+                settings
+                    .value("max-classes", |max_classes| { max_classes.parse::<usize>().ok().filter(|count| { *count >= 2 }) }, "Invalid number provided for max classes")?
+                    .unwrap_or(DEFAULT_MAX_CLASS_COUNT)
+            )
+        };
+
+        // Large collections are clustered from the nearest neighbours of each image instead of comparing every pair
+        let (similarity, neighbour_count) = match command {
+            Command::Embed => (SimilarityMode::default(), DEFAULT_NEIGHBOUR_COUNT),
+            _ => {
+                let similarity = settings
+                    .value("similarity", SimilarityMode::from_name, "Invalid similarity mode provided")?
+                    .unwrap_or_default();
+                let neighbour_count = settings.value("neighbours", |neighbours| { neighbours.parse::<usize>().ok().filter(|count| { *count > 0 }) }, "Invalid number provided for neighbours")?;
+
+                if similarity == SimilarityMode::Exact && neighbour_count.is_some() {
+                    return Err(InvalidUsageError::new(command, "Neighbours only apply when clustering through the nearest neighbour index"));
+                }
+
+                (similarity, neighbour_count.unwrap_or(DEFAULT_NEIGHBOUR_COUNT))
+            }
+        };
+
+        let output_dir = settings.value("output", |output_dir| { Some(PathBuf::from(output_dir)) }, "Invalid output path provided")?;
+        let placement = settings.value("placement", PlacementStrategy::from_name, "Invalid placement strategy provided")?;
+        let dir_conflict = settings.value("dir-conflict", DirConflictPolicy::from_name, "Invalid directory conflict policy provided")?;
+        let file_conflict = settings.value("file-conflict", FileConflictPolicy::from_name, "Invalid file conflict policy provided")?;
+        let max_depth = settings.value("max-depth", |depth| { depth.parse::<usize>().ok() }, "Invalid number provided for max depth")?;
+        let recursive = settings.flag("recursive")?;
+        let should_not_gen_names = settings.flag("no-names")?;
+        let follow_symlinks = settings.flag("follow-symlinks")?;
+        let include_hidden = settings.flag("hidden")?;
+
+        if output_dir.as_ref().is_some_and(|output_dir| { output_dir.exists() && !output_dir.is_dir() }) {
+            return Err(InvalidUsageError::new(command, "Provided output path is not a directory"));
+        }
+
+        // Leave the target directory untouched by default when sorting into a separate output directory
+        let placement = match (placement, &output_dir) {
+            (Some(placement), _) => placement,
+            (None, Some(_)) => PlacementStrategy::Copy,
+            (None, None) => PlacementStrategy::Move
+        };
+
+        // Only the target directory itself is searched unless recursion is requested
+        let max_depth = match (max_depth, recursive) {
+            (Some(max_depth), _) => max_depth,
+            (None, true) => usize::MAX,
+            (None, false) => 0
         };
 
         Ok(Self {
+            command,
             target_dir,
+            output_dir,
+            architecture,
+            weights,
+            embedding,
+            augmentation,
+            precision: precision.unwrap_or_default(),
+            torchscript,
+            preprocessing: PreprocessingModel::new(resize.unwrap_or(default_preprocessing.resize()), input_size, normalization),
+            activation: activation.unwrap_or_default(),
+            device: device.unwrap_or_default(),
+            threads,
+            pipeline: PipelineModel::new(
+                batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+                workers.unwrap_or_else(default_worker_count),
+                queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH)
+            ),
+            use_cache: !no_cache,
+            export,
+            import,
             class_count,
-            should_not_gen_names: gen_names,
+            max_class_count,
+            similarity,
+            neighbour_count,
+            should_not_gen_names,
+            dry_run: parsed.flag("dry-run"),
+            placement,
+            conflicts: ConflictPolicyModel::new(dir_conflict.unwrap_or_default(), file_conflict.unwrap_or_default()),
+            traversal: TraversalModel::new(max_depth, follow_symlinks, include_hidden),
+            sources: settings.sources
         })
     }
 
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
     pub fn target_dir(&self) -> &PathBuf {
         &self.target_dir
     }
 
-    pub fn class_count(&self) -> usize {
+    /// Returns the directory the class tree is built in, which is the target directory unless an output directory was given.
+    pub fn output_dir(&self) -> &PathBuf {
+        self.output_dir.as_ref().unwrap_or(&self.target_dir)
+    }
+
+    pub fn architecture(&self) -> &'static ArchitectureSpec {
+        self.architecture
+    }
+
+    /// Returns the weights file to load, or `None` if the default locations should be searched.
+    pub fn weights(&self) -> Option<&Path> {
+        self.weights.as_deref()
+    }
+
+    pub fn embedding(&self) -> EmbeddingMode {
+        self.embedding
+    }
+
+    pub fn augmentation(&self) -> AugmentationModel {
+        self.augmentation
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Returns the TorchScript model to embed images with, or `None` if the built-in architecture is used.
+    pub fn torchscript(&self) -> Option<&Path> {
+        self.torchscript.as_deref()
+    }
+
+    /// Returns how images are prepared for the model, whether it is a built-in architecture or a TorchScript model.
+    pub fn preprocessing(&self) -> PreprocessingModel {
+        self.preprocessing
+    }
+
+    /// Returns what is applied to the output of a TorchScript model.
+    pub fn activation(&self) -> OutputActivation {
+        self.activation
+    }
+
+    pub fn device(&self) -> DeviceSelection {
+        self.device
+    }
+
+    /// Returns the number of CPU threads the model uses, or `None` to use every core.
+    pub fn threads(&self) -> Option<usize> {
+        self.threads
+    }
+
+    pub fn pipeline(&self) -> &PipelineModel {
+        &self.pipeline
+    }
+
+    /// Returns `true` if embeddings are reused from the cache of `cache_dir`.
+    pub fn use_cache(&self) -> bool {
+        self.use_cache
+    }
+
+    /// Returns `true` if embeddings and the nearest neighbour index computed during the run are stored in the cache.
+    /// A dry run only reads the cache, so it leaves every directory as it was.
+    pub fn stores_cache(&self) -> bool {
+        self.use_cache && !self.dry_run
+    }
+
+    /// Returns the directory the embedding cache and nearest neighbour index are kept in.
+    /// They are kept with the class tree, so a target directory sorted into an output directory is only ever read.
+    pub fn cache_dir(&self) -> &PathBuf {
+        self.output_dir()
+    }
+
+    /// Returns where the embeddings are exported to, or `None` if they are not.
+    pub fn export(&self) -> Option<&ExportModel> {
+        self.export.as_ref()
+    }
+
+    /// Returns the embeddings that are clustered instead of embedding images, or `None` if images are embedded.
+    pub fn import(&self) -> Option<&ImportModel> {
+        self.import.as_ref()
+    }
+
+    /// Returns the requested number of classes, or `None` if it should be estimated.
+    pub fn class_count(&self) -> Option<usize> {
         self.class_count
     }
 
+    /// Returns the largest class count tried when estimating it.
+    pub fn max_class_count(&self) -> usize {
+        self.max_class_count
+    }
+
+    /// Returns how the similarities between images are computed before clustering them.
+    pub fn similarity(&self) -> SimilarityMode {
+        self.similarity
+    }
+
+    /// Returns how many nearest neighbours each image is linked to when clustering through the index.
+    pub fn neighbour_count(&self) -> usize {
+        self.neighbour_count
+    }
+
     pub fn should_not_gen_names(&self) -> bool {
         self.should_not_gen_names
     }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn placement(&self) -> PlacementStrategy {
+        self.placement
+    }
+
+    pub fn conflicts(&self) -> &ConflictPolicyModel {
+        &self.conflicts
+    }
+
+    pub fn traversal(&self) -> &TraversalModel {
+        &self.traversal
+    }
+
+    /// Returns where the effective value of a setting came from.
+    pub fn source(&self, key: &str) -> &ConfigSource {
+        self.sources.get(key).unwrap_or(&DEFAULT_SOURCE)
+    }
+}
+
+/// Arguments for the `undo` command, which reverses a previous run.
+///
+/// # Example
+///
+/// ```
+/// program_name undo target_dir [run_id]
+/// ```
+#[derive(PartialEq, Debug)]
+pub struct UndoArgumentsModel {
+    target_dir: PathBuf,
+    run_id: Option<String>
+}
+
+impl UndoArgumentsModel {
+
+    /// Builds the undo arguments structure from the parsed arguments of the `undo` command.
+    pub fn from_parsed(parsed: &ParsedArgsModel) -> Result<Self, InvalidUsageError> {
+        Ok(Self {
+            target_dir: parse_target_dir(parsed)?,
+            run_id: parsed.positional(1).map(String::from)
+        })
+    }
+
+    pub fn target_dir(&self) -> &PathBuf {
+        &self.target_dir
+    }
+
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+}
+
+/// Arguments for the `cache` command, which prunes or clears the embeddings cached for a directory.
+///
+/// # Example
+///
+/// ```
+/// program_name cache target_dir prune
+/// ```
+#[derive(PartialEq, Debug)]
+pub struct CacheArgumentsModel {
+    target_dir: PathBuf,
+    action: CacheAction
+}
+
+impl CacheArgumentsModel {
+
+    /// Builds the cache arguments structure from the parsed arguments of the `cache` command.
+    pub fn from_parsed(parsed: &ParsedArgsModel) -> Result<Self, InvalidUsageError> {
+        let action = parsed
+            .positional(1)
+            .and_then(CacheAction::from_name)
+            .ok_or_else(|| { InvalidUsageError::new(parsed.command(), "Invalid cache action provided") })?;
+
+        Ok(Self {
+            target_dir: parse_target_dir(parsed)?,
+            action
+        })
+    }
+
+    pub fn target_dir(&self) -> &PathBuf {
+        &self.target_dir
+    }
+
+    pub fn action(&self) -> CacheAction {
+        self.action
+    }
+}
+
+/// Represents what the tool was asked to do.
+#[derive(PartialEq, Debug)]
+pub enum InvocationModel {
+    /// Run one of the sorting commands.
+    Run(Box<ArgumentsModel>),
+    /// Undo a previous run.
+    Undo(UndoArgumentsModel),
+    /// Prune or clear the embedding cache of a directory.
+    Cache(CacheArgumentsModel),
+    /// Print the usage of a command, or the overall usage if no command is given.
+    Help(Option<Command>),
+    /// Print the version.
+    Version
+}
+
+impl InvocationModel {
+
+    /// Recognizes the global help and version options, which are only recognized in place of a command. <br />
+    /// Neither depends on any setting, so they are answered before the configuration is loaded
+    pub fn from_global(args: &[String]) -> Option<Self> {
+        match args.get(1) {
+            Some(arg) if HELP_OPTION.matches(arg) => Some(Self::Help(None)),
+            Some(arg) if VERSION_OPTION.matches(arg) => Some(Self::Version),
+            _ => None
+        }
+    }
+
+    /// Factory method for a new invocation <br />
+    /// Parses the full command line, including the program name, on top of the settings in `config`
+    pub fn from(args: Vec<String>, config: &ConfigModel) -> Result<Self, InvalidUsageError> {
+        if let Some(invocation) = Self::from_global(&args) {
+            return Ok(invocation);
+        }
+
+        if args.len() < 2 {
+            return Err(InvalidUsageError::global("No command provided"));
+        }
+
+        let (command, rest) = split_command(&args);
+        let parsed = ParsedArgsModel::parse(command, rest)?;
+
+        if parsed.flag(HELP_OPTION.long) {
+            return Ok(Self::Help(Some(command)));
+        }
+
+        match command {
+            Command::Undo => Ok(Self::Undo(UndoArgumentsModel::from_parsed(&parsed)?)),
+            Command::Cache => Ok(Self::Cache(CacheArgumentsModel::from_parsed(&parsed)?)),
+            _ => Ok(Self::Run(Box::new(ArgumentsModel::from_parsed(&parsed, config)?)))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use assertables::*;
-    use super::ArgumentsModel;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use super::{ArgumentsModel, CacheArgumentsModel, InvocationModel, UndoArgumentsModel, DEFAULT_MAX_CLASS_COUNT};
+    use crate::models::ann_index_model::{SimilarityMode, DEFAULT_NEIGHBOUR_COUNT};
+    use crate::models::architecture_model::ArchitectureSpec;
+    use crate::models::augmentation_model::{AugmentationModel, AugmentationView, ViewReduction};
+    use crate::errors::InvalidUsageError;
+    use crate::models::cli_model::{split_command, Command, ParsedArgsModel};
+    use crate::models::cnn_model::EmbeddingMode;
+    use crate::models::config_model::{ConfigModel, ConfigSource};
+    use crate::models::device_model::DeviceSelection;
+    use crate::models::embedding_cache_model::CacheAction;
+    use crate::models::export_model::ExportFormat;
+    use crate::models::feature_network_model::Pooling;
+    use crate::models::pipeline_model::{PipelineModel, DEFAULT_BATCH_SIZE};
+    use crate::models::sort_plan_model::{ConflictPolicyModel, DirConflictPolicy, FileConflictPolicy, PlacementStrategy};
+    use crate::models::precision_model::Precision;
+    use crate::models::preprocessing_model::{Normalization, PreprocessingModel, ResizeStrategy};
+    use crate::models::torchscript_model::OutputActivation;
+    use crate::models::traversal_model::TraversalModel;
+
+    fn parse_args(args: Vec<String>) -> Result<ArgumentsModel, InvalidUsageError> {
+        let (command, rest) = split_command(&args);
+        ArgumentsModel::from_parsed(&ParsedArgsModel::parse(command, rest)?, &ConfigModel::new())
+    }
+
+    fn parse_undo_args(args: Vec<String>) -> Result<UndoArgumentsModel, InvalidUsageError> {
+        let (command, rest) = split_command(&args);
+        UndoArgumentsModel::from_parsed(&ParsedArgsModel::parse(command, rest)?)
+    }
+
+    fn parse_cache_args(args: Vec<String>) -> Result<CacheArgumentsModel, InvalidUsageError> {
+        let (command, rest) = split_command(&args);
+        CacheArgumentsModel::from_parsed(&ParsedArgsModel::parse(command, rest)?)
+    }
 
     #[test]
     fn not_enough_args_returns_error() {
-        let result = ArgumentsModel::from(vec![]);
+        let result = parse_args(vec![]);
         assert!(result.is_err());
     }
 
@@ -101,7 +722,7 @@ mod tests {
             .unwrap()
             .to_string();
 
-        let result = ArgumentsModel::from(vec![String::from("tensort"), not_a_dir, String::from("5")]);
+        let result = parse_args(vec![String::from("tensort"), not_a_dir, String::from("5")]);
 
         match result {
             Ok(_) => assert!(false),
@@ -121,7 +742,7 @@ mod tests {
             .unwrap()
             .to_string();
 
-        let result = ArgumentsModel::from(vec![String::from("tensort"), valid_dir, String::from("-3")]);
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("-3")]);
 
         assert!(result.is_err());
     }
@@ -134,11 +755,35 @@ mod tests {
             .unwrap()
             .to_string();
 
-        let result = ArgumentsModel::from(vec![String::from("tensort"), valid_dir, String::from("4"), String::from("-n")]).unwrap();
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("4"), String::from("-n")]).unwrap();
         let expected = ArgumentsModel {
-            class_count: 4,
+            command: Command::Sort,
+            class_count: Some(4),
+            max_class_count: DEFAULT_MAX_CLASS_COUNT,
+            similarity: SimilarityMode::Auto,
+            neighbour_count: DEFAULT_NEIGHBOUR_COUNT,
             target_dir: valid_dir_path,
-            should_not_gen_names: true
+            output_dir: None,
+            architecture: ArchitectureSpec::default_architecture(),
+            weights: None,
+            embedding: EmbeddingMode::Probabilities,
+            augmentation: AugmentationModel::default(),
+            precision: Precision::Float32,
+            torchscript: None,
+            preprocessing: ArchitectureSpec::default_architecture().default_preprocessing(),
+            activation: OutputActivation::None,
+            device: DeviceSelection::Auto,
+            threads: None,
+            pipeline: PipelineModel::default(),
+            use_cache: true,
+            export: None,
+            import: None,
+            should_not_gen_names: true,
+            dry_run: false,
+            placement: PlacementStrategy::Move,
+            conflicts: ConflictPolicyModel::default(),
+            traversal: TraversalModel::default(),
+            sources: HashMap::from([("class-count", ConfigSource::CommandLine), ("no-names", ConfigSource::CommandLine)])
         };
 
         assert_eq!(expected, result);
@@ -152,16 +797,392 @@ mod tests {
             .unwrap()
             .to_string();
 
-        let result = ArgumentsModel::from(vec![String::from("tensort"), valid_dir, String::from("8")]).unwrap();
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("8")]).unwrap();
+        let expected = ArgumentsModel {
+            command: Command::Sort,
+            class_count: Some(8),
+            max_class_count: DEFAULT_MAX_CLASS_COUNT,
+            similarity: SimilarityMode::Auto,
+            neighbour_count: DEFAULT_NEIGHBOUR_COUNT,
+            target_dir: valid_dir_path,
+            output_dir: None,
+            architecture: ArchitectureSpec::default_architecture(),
+            weights: None,
+            embedding: EmbeddingMode::Probabilities,
+            augmentation: AugmentationModel::default(),
+            precision: Precision::Float32,
+            torchscript: None,
+            preprocessing: ArchitectureSpec::default_architecture().default_preprocessing(),
+            activation: OutputActivation::None,
+            device: DeviceSelection::Auto,
+            threads: None,
+            pipeline: PipelineModel::default(),
+            use_cache: true,
+            export: None,
+            import: None,
+            should_not_gen_names: false,
+            dry_run: false,
+            placement: PlacementStrategy::Move,
+            conflicts: ConflictPolicyModel::default(),
+            traversal: TraversalModel::default(),
+            sources: HashMap::from([("class-count", ConfigSource::CommandLine)])
+        };
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn valid_input_with_dry_run_constructs() {
+        let valid_dir_path = std::env::current_dir().unwrap();
+        let valid_dir = valid_dir_path
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--dry-run"), String::from("-n")]).unwrap();
         let expected = ArgumentsModel {
-            class_count: 8,
+            command: Command::Sort,
+            class_count: Some(3),
+            max_class_count: DEFAULT_MAX_CLASS_COUNT,
+            similarity: SimilarityMode::Auto,
+            neighbour_count: DEFAULT_NEIGHBOUR_COUNT,
             target_dir: valid_dir_path,
-            should_not_gen_names: false
+            output_dir: None,
+            architecture: ArchitectureSpec::default_architecture(),
+            weights: None,
+            embedding: EmbeddingMode::Probabilities,
+            augmentation: AugmentationModel::default(),
+            precision: Precision::Float32,
+            torchscript: None,
+            preprocessing: ArchitectureSpec::default_architecture().default_preprocessing(),
+            activation: OutputActivation::None,
+            device: DeviceSelection::Auto,
+            threads: None,
+            pipeline: PipelineModel::default(),
+            use_cache: true,
+            export: None,
+            import: None,
+            should_not_gen_names: true,
+            dry_run: true,
+            placement: PlacementStrategy::Move,
+            conflicts: ConflictPolicyModel::default(),
+            traversal: TraversalModel::default(),
+            sources: HashMap::from([("class-count", ConfigSource::CommandLine), ("no-names", ConfigSource::CommandLine)])
         };
 
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn export_args_construct() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--export"), String::from("vectors.npz")]).unwrap();
+
+        assert_eq!(result.export().map(|export| { export.format() }), Some(ExportFormat::Npz));
+        assert!(parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--export"), String::from("vectors.txt")]).is_err());
+
+        // Only the embed command exports
+        assert!(parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--export"), String::from("vectors.npz")]).is_err());
+    }
+
+    #[test]
+    fn import_args_construct() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("cluster"), valid_dir.clone(), String::from("--import"), String::from("vectors.npy"), String::from("--image-list"), String::from("images.txt")]).unwrap();
+        let import = result.import().unwrap();
+
+        assert_eq!(import.format(), ExportFormat::Npy);
+        assert_eq!(import.image_list(), Some(Path::new("images.txt")));
+        assert!(parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("--import"), String::from("vectors.parquet")]).is_err());
+        assert!(parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("--image-list"), String::from("images.txt")]).is_err());
+
+        // Embedding imported embeddings again makes no sense
+        assert!(parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir, String::from("--import"), String::from("vectors.npy")]).is_err());
+    }
+
+    #[test]
+    fn similarity_args_construct() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("cluster"), valid_dir.clone(), String::from("--similarity"), String::from("index"), String::from("--neighbours"), String::from("30")]).unwrap();
+
+        assert_eq!(result.similarity(), SimilarityMode::Index);
+        assert_eq!(result.neighbour_count(), 30);
+        assert_eq!(parse_args(vec![String::from("tensort"), valid_dir.clone()]).unwrap().neighbour_count(), DEFAULT_NEIGHBOUR_COUNT);
+        assert!(parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("--similarity"), String::from("approximate")]).is_err());
+        assert!(parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("--neighbours"), String::from("0")]).is_err());
+
+        // Exact similarities have no neighbours to link
+        assert!(parse_args(vec![String::from("tensort"), valid_dir, String::from("--similarity"), String::from("exact"), String::from("--neighbours"), String::from("30")]).is_err());
+    }
+
+    #[test]
+    fn valid_input_with_placement_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("-p"), String::from("hardlink")]).unwrap();
+
+        assert_eq!(result.placement(), PlacementStrategy::Hardlink);
+    }
+
+    #[test]
+    fn valid_input_with_model_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--weights=custom.ot")]).unwrap();
+        assert_eq!(result.weights(), Some(Path::new("custom.ot")));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--model"), String::from("mobilenet_v2")]).unwrap();
+        assert_eq!(result.architecture().name, "mobilenet_v2");
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("-m"), String::from("resnet35")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_device_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--device"), String::from("cuda:1"), String::from("-j"), String::from("4")]).unwrap();
+        assert_eq!(result.device(), DeviceSelection::Cuda(1));
+        assert_eq!(result.threads(), Some(4));
+        assert_eq!(result.pipeline().batch_size(), DEFAULT_BATCH_SIZE);
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--device=gpu")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--threads"), String::from("0")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("-b"), String::from("64"), String::from("--workers"), String::from("0"), String::from("--queue-depth=8")]).unwrap();
+        assert_eq!(*result.pipeline(), PipelineModel::new(64, 0, 8));
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir, String::from("--queue-depth"), String::from("0")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_features_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-e"), String::from("features"), String::from("--pooling=gem")]).unwrap();
+        assert_eq!(result.embedding(), EmbeddingMode::Features { layer: None, pooling: Pooling::Gem });
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-m"), String::from("densenet121"), String::from("-e"), String::from("features"), String::from("--layer"), String::from("denseblock3")]).unwrap();
+        assert_eq!(result.embedding(), EmbeddingMode::Features { layer: Some("denseblock3"), pooling: Pooling::Average });
+
+        // Layers belong to a specific architecture
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-e"), String::from("features"), String::from("--layer"), String::from("denseblock3")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-m"), String::from("alexnet"), String::from("-e"), String::from("features")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--pooling"), String::from("max")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_augmentation_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--augment"), String::from("flip,scales"), String::from("--augment-reduce=max")]).unwrap();
+        assert_eq!(result.augmentation(), AugmentationModel::new(&[AugmentationView::Flip, AugmentationView::Scales], ViewReduction::Max));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--augment"), String::from("rotate")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--augment-reduce"), String::from("max")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir, String::from("--torchscript"), String::from("tuned.pt"), String::from("--augment"), String::from("flip")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_precision_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--precision"), String::from("bf16")]).unwrap();
+        assert_eq!(result.precision(), Precision::BFloat16);
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--precision=int4")]);
+        assert!(result.is_err());
+
+        // Only architectures with a separate classifier head can be quantized
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--precision"), String::from("int8")]).unwrap();
+        assert_eq!(result.precision(), Precision::Int8);
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--model"), String::from("vgg16"), String::from("--precision"), String::from("int8")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir, String::from("--torchscript"), String::from("tuned.pt"), String::from("--precision"), String::from("fp16")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_preprocessing_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // Built-in architectures default to their own input size
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-m"), String::from("efficientnet_b3"), String::from("--resize"), String::from("letterbox")]).unwrap();
+        assert_eq!(result.preprocessing(), PreprocessingModel::new(ResizeStrategy::Letterbox, 300, Normalization::ImageNet));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--input-size=256"), String::from("--mean"), String::from("0.5,0.5,0.5"), String::from("--std=0.25,0.25,0.25")]).unwrap();
+        assert_eq!(result.preprocessing(), PreprocessingModel::new(ResizeStrategy::CenterCrop, 256, Normalization::MeanStd { mean: [0.5; 3], std: [0.25; 3] }));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--mean"), String::from("0.5,0.5,0.5")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--normalization=raw"), String::from("--mean"), String::from("0.5,0.5,0.5"), String::from("--std=1,1,1")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--mean"), String::from("0.5,0.5,0.5"), String::from("--std=1,0,1")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--resize"), String::from("squash")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--activation"), String::from("l2")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_torchscript_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("cluster"), valid_dir.clone(), String::from("--torchscript"), String::from("tuned.pt"), String::from("--input-size=384"), String::from("--activation"), String::from("l2")]).unwrap();
+        assert_eq!(result.torchscript(), Some(Path::new("tuned.pt")));
+        assert_eq!(result.preprocessing(), PreprocessingModel::new(ResizeStrategy::CenterCrop, 384, Normalization::ImageNet));
+        assert_eq!(result.activation(), OutputActivation::L2);
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--torchscript"), String::from("tuned.pt"), String::from("-w"), String::from("custom.ot")]);
+        assert!(result.is_err());
+
+        let result = parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir, String::from("--torchscript"), String::from("tuned.pt"), String::from("--normalization"), String::from("imagenet21k")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn invalid_placement_returns_error() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--placement"), String::from("teleport")]);
+        assert!(result.is_err());
+
+        // A missing value is also an error
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--placement")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_traversal_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-r"), String::from("-a")]).unwrap();
+        assert_eq!(*result.traversal(), TraversalModel::new(usize::MAX, false, true));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--max-depth"), String::from("2"), String::from("-L")]).unwrap();
+        assert_eq!(*result.traversal(), TraversalModel::new(2, true, false));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--max-depth"), String::from("deep")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_input_with_output_dir_constructs() {
+        let valid_dir_path = std::env::current_dir().unwrap();
+        let valid_dir = valid_dir_path
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let output_dir_path = valid_dir_path.join("does_not_exist_yet");
+        let output_dir = output_dir_path
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("-o"), output_dir.clone()]).unwrap();
+
+        assert_eq!(*result.output_dir(), output_dir_path);
+        assert_eq!(result.placement(), PlacementStrategy::Copy);
+
+        // An explicit placement still takes priority
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--output"), output_dir, String::from("-p"), String::from("move")]).unwrap();
+
+        assert_eq!(result.placement(), PlacementStrategy::Move);
+    }
+
+    #[test]
+    fn valid_input_with_conflict_policies_constructs() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("3"), String::from("--dir-conflict"), String::from("suffix"), String::from("--file-conflict"), String::from("identical")]).unwrap();
+        assert_eq!(*result.conflicts(), ConflictPolicyModel::new(DirConflictPolicy::Suffix, FileConflictPolicy::Identical));
+
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("3"), String::from("--file-conflict"), String::from("overwrite")]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn getters_work() {
         let valid_dir_path = std::env::current_dir().unwrap();
@@ -170,10 +1191,157 @@ mod tests {
             .unwrap()
             .to_string();
 
-        let result = ArgumentsModel::from(vec![String::from("tensort"), valid_dir, String::from("8")]).unwrap();
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("8")]).unwrap();
 
         assert_eq!(*result.target_dir(), valid_dir_path);
-        assert_eq!(result.class_count(), 8);
+        assert_eq!(*result.output_dir(), valid_dir_path);
+        assert_eq!(result.architecture().name, "resnet34");
+        assert_eq!(result.weights(), None);
+        assert_eq!(result.class_count(), Some(8));
+        assert_eq!(result.max_class_count(), DEFAULT_MAX_CLASS_COUNT);
         assert_eq!(result.should_not_gen_names(), false);
+        assert!(!result.dry_run());
+        assert_eq!(result.placement(), PlacementStrategy::Move);
+        assert!(!result.traversal().is_recursive());
+    }
+
+    #[test]
+    fn undo_args_construct() {
+        let valid_dir_path = std::env::current_dir().unwrap();
+        let valid_dir = valid_dir_path
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_undo_args(vec![String::from("tensort"), String::from("undo"), valid_dir.clone()]).unwrap();
+
+        assert_eq!(*result.target_dir(), valid_dir_path);
+        assert_eq!(result.run_id(), None);
+
+        let result = parse_undo_args(vec![String::from("tensort"), String::from("undo"), valid_dir, String::from("42")]).unwrap();
+
+        assert_eq!(result.run_id(), Some("42"));
+        assert!(parse_undo_args(vec![String::from("tensort"), String::from("undo")]).is_err());
+    }
+
+    #[test]
+    fn cache_args_construct() {
+        let valid_dir_path = std::env::current_dir().unwrap();
+        let valid_dir = valid_dir_path
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_cache_args(vec![String::from("tensort"), String::from("cache"), valid_dir.clone(), String::from("prune")]).unwrap();
+
+        assert_eq!(*result.target_dir(), valid_dir_path);
+        assert_eq!(result.action(), CacheAction::Prune);
+        assert!(parse_cache_args(vec![String::from("tensort"), String::from("cache"), valid_dir.clone(), String::from("compact")]).is_err());
+        assert!(parse_cache_args(vec![String::from("tensort"), String::from("cache"), valid_dir.clone()]).is_err());
+
+        // Sorting commands use the cache unless told otherwise
+        assert!(parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone()]).unwrap().use_cache());
+        assert!(!parse_args(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("--no-cache")]).unwrap().use_cache());
+
+        // A dry run only reads the cache, and an output directory keeps it away from the target directory
+        let result = parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("--dry-run")]).unwrap();
+
+        assert!(result.use_cache());
+        assert!(!result.stores_cache());
+        assert_eq!(*result.cache_dir(), valid_dir_path);
+
+        let output_dir_path = valid_dir_path.join("does_not_exist_yet");
+        let result = parse_args(vec![String::from("tensort"), valid_dir, String::from("-o"), output_dir_path.to_str().unwrap().to_string()]).unwrap();
+
+        assert!(result.stores_cache());
+        assert_eq!(*result.cache_dir(), output_dir_path);
+    }
+
+    #[test]
+    fn invocation_dispatches_commands() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = InvocationModel::from(vec![String::from("tensort"), String::from("--help")], &ConfigModel::new()).unwrap();
+        assert_eq!(result, InvocationModel::Help(None));
+
+        let result = InvocationModel::from(vec![String::from("tensort"), String::from("-V")], &ConfigModel::new()).unwrap();
+        assert_eq!(result, InvocationModel::Version);
+
+        // Global options are recognized without any configuration
+        assert_eq!(InvocationModel::from_global(&[String::from("tensort"), String::from("--version")]), Some(InvocationModel::Version));
+        assert_eq!(InvocationModel::from_global(&[String::from("tensort"), String::from("sort"), String::from("--help")]), None);
+        assert!(InvocationModel::from(vec![String::from("tensort")], &ConfigModel::new()).is_err());
+
+        let result = InvocationModel::from(vec![String::from("tensort"), String::from("cluster"), String::from("-h")], &ConfigModel::new()).unwrap();
+        assert_eq!(result, InvocationModel::Help(Some(Command::Cluster)));
+
+        match InvocationModel::from(vec![String::from("tensort"), String::from("embed"), valid_dir.clone(), String::from("-r")], &ConfigModel::new()).unwrap() {
+            InvocationModel::Run(args) => {
+                assert_eq!(args.command(), Command::Embed);
+                assert!(args.traversal().is_recursive());
+            },
+            _ => panic!("Expected a sorting command")
+        }
+
+        // The legacy form without a command name still sorts
+        match InvocationModel::from(vec![String::from("tensort"), valid_dir.clone(), String::from("3")], &ConfigModel::new()).unwrap() {
+            InvocationModel::Run(args) => assert_eq!(args.command(), Command::Sort),
+            _ => panic!("Expected a sorting command")
+        }
+
+        assert!(matches!(InvocationModel::from(vec![String::from("tensort"), String::from("undo"), valid_dir], &ConfigModel::new()).unwrap(), InvocationModel::Undo(_)));
+        assert!(InvocationModel::from(vec![String::from("tensort")], &ConfigModel::new()).is_err());
+    }
+
+    #[test]
+    fn config_settings_apply_below_command_line() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let config_path = PathBuf::from(".tensort.toml");
+        let mut config = ConfigModel::new();
+        config.merge_toml("class_count = 5\nplacement = \"symlink\"\nhidden = true", config_path.clone()).unwrap();
+
+        let args = vec![String::from("tensort"), valid_dir.clone(), String::from("-p"), String::from("copy")];
+        let (command, rest) = split_command(&args);
+        let result = ArgumentsModel::from_parsed(&ParsedArgsModel::parse(command, rest).unwrap(), &config).unwrap();
+
+        assert_eq!(result.class_count(), Some(5));
+        assert_eq!(*result.source("class-count"), ConfigSource::File(config_path));
+        assert_eq!(result.placement(), PlacementStrategy::Copy);
+        assert_eq!(*result.source("placement"), ConfigSource::CommandLine);
+        assert!(result.traversal().include_hidden());
+        assert_eq!(*result.source("recursive"), ConfigSource::Default);
+
+        // Invalid values name where they came from
+        config.merge_toml("file_conflict = \"overwrite\"", PathBuf::from("config.toml")).unwrap();
+
+        let err = ArgumentsModel::from_parsed(&ParsedArgsModel::parse(command, rest).unwrap(), &config).unwrap_err();
+        assert_contains!(err.message, "from config.toml");
+    }
+
+    #[test]
+    fn omitted_class_count_is_estimated() {
+        let valid_dir = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = parse_args(vec![String::from("tensort"), String::from("cluster"), valid_dir.clone(), String::from("--max-classes"), String::from("4")]).unwrap();
+
+        assert_eq!(result.class_count(), None);
+        assert_eq!(result.max_class_count(), 4);
+
+        // At least two counts have to be compared
+        assert!(parse_args(vec![String::from("tensort"), valid_dir.clone(), String::from("--max-classes"), String::from("1")]).is_err());
+        assert!(parse_args(vec![String::from("tensort"), valid_dir, String::from("0")]).is_err());
     }
 }
\ No newline at end of file
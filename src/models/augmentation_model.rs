@@ -0,0 +1,231 @@
+use tch::{Kind, TchError, Tensor};
+
+/// Fraction of the image side kept by each crop of `AugmentationView::Crops`.
+pub const CROP_FRACTION: f64 = 0.875;
+
+/// Fractions of the image side kept by the zoomed views of `AugmentationView::Scales`.
+pub const SCALE_FRACTIONS: [f64; 2] = [0.8, 0.6];
+
+/// A kind of extra view of an image that is embedded alongside the image itself.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AugmentationView {
+    /// The horizontal mirror image of every other view.
+    Flip,
+    /// The four corner crops and the center crop, each covering `CROP_FRACTION` of the image side.
+    Crops,
+    /// Center crops covering each of `SCALE_FRACTIONS` of the image side, so objects are seen at several scales.
+    Scales
+}
+
+impl AugmentationView {
+
+    /// Looks up a view by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "flip" => Some(Self::Flip),
+            "crops" => Some(Self::Crops),
+            "scales" => Some(Self::Scales),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Flip => "flip",
+            Self::Crops => "crops",
+            Self::Scales => "scales"
+        }
+    }
+
+    /// Looks up a comma separated list of views, such as `flip,crops`.
+    pub fn from_names(names: &str) -> Option<Vec<Self>> {
+        names
+            .split(',')
+            .map(|name| { Self::from_name(name.trim()) })
+            .collect()
+    }
+}
+
+/// How the embeddings of the views of an image are combined into one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ViewReduction {
+    /// The mean of each value.
+    #[default]
+    Mean,
+    /// The largest of each value.
+    Max
+}
+
+impl ViewReduction {
+
+    /// Looks up a reduction by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mean" => Some(Self::Mean),
+            "max" => Some(Self::Max),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mean => "mean",
+            Self::Max => "max"
+        }
+    }
+}
+
+/// Controls test-time augmentation, where several views of each image are embedded and their embeddings combined,
+/// which makes the embeddings less sensitive to framing and orientation. Every view has the size of the original image.
+///
+/// # Fields
+///
+/// - `flip`: Whether the mirror image of every view is embedded as well.
+/// - `crops`: Whether corner and center crops are embedded.
+/// - `scales`: Whether zoomed center crops are embedded.
+/// - `reduction`: How the embeddings of the views are combined.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct AugmentationModel {
+    flip: bool,
+    crops: bool,
+    scales: bool,
+    reduction: ViewReduction
+}
+
+impl AugmentationModel {
+
+    /// Creates a new set of augmentation options, embedding only the image itself if `views` is empty.
+    pub fn new(views: &[AugmentationView], reduction: ViewReduction) -> Self {
+        Self {
+            flip: views.contains(&AugmentationView::Flip),
+            crops: views.contains(&AugmentationView::Crops),
+            scales: views.contains(&AugmentationView::Scales),
+            reduction
+        }
+    }
+
+    /// Returns the kinds of views that are embedded alongside the image itself.
+    pub fn views(&self) -> Vec<AugmentationView> {
+        [(self.flip, AugmentationView::Flip), (self.crops, AugmentationView::Crops), (self.scales, AugmentationView::Scales)]
+            .into_iter()
+            .filter_map(|(enabled, view)| { enabled.then_some(view) })
+            .collect()
+    }
+
+    pub fn reduction(&self) -> ViewReduction {
+        self.reduction
+    }
+
+    /// Returns `true` if any views are embedded besides the image itself.
+    pub fn is_enabled(&self) -> bool {
+        self.flip || self.crops || self.scales
+    }
+
+    /// Returns how many views of each image are embedded, including the image itself.
+    pub fn view_count(&self) -> usize {
+        let unflipped = 1 + if self.crops { 5 } else { 0 } + if self.scales { SCALE_FRACTIONS.len() } else { 0 };
+
+        if self.flip { 2 * unflipped } else { unflipped }
+    }
+
+    /// Generates every view of a batch of `N x C x H x W` images, starting with the images themselves.
+    pub fn gen_views(&self, images: &Tensor) -> Result<Vec<Tensor>, TchError> {
+        let (_, _, height, width) = images.size4()?;
+        let mut views = vec![images.shallow_clone()];
+
+        if self.crops {
+            let (crop_height, crop_width) = (scale(height, CROP_FRACTION), scale(width, CROP_FRACTION));
+
+            for (top, left) in [(0, 0), (0, width - crop_width), (height - crop_height, 0), (height - crop_height, width - crop_width)] {
+                views.push(crop(images, top, left, crop_height, crop_width)?);
+            }
+
+            views.push(crop(images, (height - crop_height) / 2, (width - crop_width) / 2, crop_height, crop_width)?);
+        }
+
+        if self.scales {
+            for fraction in SCALE_FRACTIONS {
+                let (crop_height, crop_width) = (scale(height, fraction), scale(width, fraction));
+                views.push(crop(images, (height - crop_height) / 2, (width - crop_width) / 2, crop_height, crop_width)?);
+            }
+        }
+
+        if self.flip {
+            let flipped = views
+                .iter()
+                .map(|view| { view.f_flip([3]) })
+                .collect::<Result<Vec<Tensor>, TchError>>()?;
+
+            views.extend(flipped);
+        }
+
+        Ok(views)
+    }
+
+    /// Combines the `N x D` embeddings of every view into the `N x D` embeddings of the images.
+    pub fn reduce(&self, embeddings: &[Tensor]) -> Result<Tensor, TchError> {
+        let embeddings = Tensor::f_stack(embeddings, 0)?;
+
+        match self.reduction {
+            ViewReduction::Mean => embeddings.f_mean_dim(0, false, Kind::Float),
+            ViewReduction::Max => embeddings.f_amax(0, false)
+        }
+    }
+}
+
+fn scale(length: i64, fraction: f64) -> i64 {
+    ((length as f64 * fraction).round() as i64).max(1)
+}
+
+/// Crops a batch of images and resizes the crops back to the size of the images.
+fn crop(images: &Tensor, top: i64, left: i64, height: i64, width: i64) -> Result<Tensor, TchError> {
+    let size = images.size();
+
+    images
+        .f_narrow(2, top, height)?
+        .f_narrow(3, left, width)?
+        .f_upsample_bilinear2d([size[2], size[3]], false, None::<f64>, None::<f64>)
+}
+
+#[cfg(test)]
+mod tests {
+    use tch::Device;
+    use super::*;
+
+    #[test]
+    fn from_names_works() {
+        assert_eq!(AugmentationView::from_names("flip, crops"), Some(vec![AugmentationView::Flip, AugmentationView::Crops]));
+        assert_eq!(AugmentationView::from_names("flip,rotate"), None);
+        assert_eq!(ViewReduction::from_name(ViewReduction::Max.name()), Some(ViewReduction::Max));
+    }
+
+    #[test]
+    fn gen_views_keeps_image_size() {
+        let images = Tensor::arange(2 * 3 * 8 * 8, (Kind::Float, Device::Cpu)).view([2, 3, 8, 8]);
+        let augmentation = AugmentationModel::new(&[AugmentationView::Flip, AugmentationView::Crops, AugmentationView::Scales], ViewReduction::Mean);
+        let views = augmentation.gen_views(&images).unwrap();
+
+        assert_eq!(views.len(), augmentation.view_count());
+        assert_eq!(views.len(), 16);
+
+        for view in &views {
+            assert_eq!(view.size(), [2, 3, 8, 8]);
+        }
+
+        // The first view is the image itself and the mirror images come last
+        assert!(views[0].equal(&images));
+        assert!(views[8].equal(&images.flip([3])));
+        assert_eq!(AugmentationModel::default().gen_views(&images).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reduce_combines_views() {
+        let embeddings = [Tensor::from_slice(&[1.0f32, 4.0]).view([1, 2]), Tensor::from_slice(&[3.0f32, 2.0]).view([1, 2])];
+
+        let mean = AugmentationModel::new(&[AugmentationView::Flip], ViewReduction::Mean).reduce(&embeddings).unwrap();
+        let max = AugmentationModel::new(&[AugmentationView::Flip], ViewReduction::Max).reduce(&embeddings).unwrap();
+
+        assert_eq!(Vec::<f32>::try_from(mean.flatten(0, -1)).unwrap(), vec![2.0, 3.0]);
+        assert_eq!(Vec::<f32>::try_from(max.flatten(0, -1)).unwrap(), vec![3.0, 4.0]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,54 @@
+/// The result of estimating how many classes a set of images falls into.
+///
+/// Each candidate class count is scored with the mean silhouette coefficient of the
+/// clustering it produces, which ranges from `-1.0` (poorly separated) to `1.0` (well separated).
+#[derive(PartialEq, Debug, Clone)]
+pub struct ClassEstimateModel {
+    scores: Vec<(usize, f64)>,
+    chosen: usize
+}
+
+impl ClassEstimateModel {
+
+    /// Picks the candidate with the highest score, preferring fewer classes on ties.
+    /// Falls back to `fallback` if there are no candidates.
+    pub fn new(scores: Vec<(usize, f64)>, fallback: usize) -> Self {
+        let chosen = scores
+            .iter()
+            .fold(None, |best: Option<(usize, f64)>, &(class_count, score)| {
+                match best {
+                    Some((_, best_score)) if best_score >= score => best,
+                    _ => Some((class_count, score))
+                }
+            })
+            .map_or(fallback, |(class_count, _)| { class_count });
+
+        Self {
+            scores,
+            chosen
+        }
+    }
+
+    /// Returns each candidate class count along with its score, in the order they were tried.
+    pub fn scores(&self) -> &[(usize, f64)] {
+        &self.scores
+    }
+
+    pub fn chosen(&self) -> usize {
+        self.chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_chooses_best_score() {
+        let estimate = ClassEstimateModel::new(vec![(2, 0.4), (3, 0.7), (4, 0.7), (5, -0.1)], 1);
+        assert_eq!(estimate.chosen(), 3);
+
+        let estimate = ClassEstimateModel::new(vec![], 1);
+        assert_eq!(estimate.chosen(), 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use crate::errors::InvalidUsageError;
+
+/// The subcommands understood by the command line interface.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Command {
+    Sort,
+    Embed,
+    Cluster,
+    Name,
+    Undo,
+    Cache
+}
+
+/// Describes a positional argument of a command.
+pub struct PositionalSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub required: bool
+}
+
+/// Describes an option of a command. Options without a `value_name` are flags.
+pub struct OptionSpec {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value_name: Option<&'static str>,
+    pub description: &'static str
+}
+
+/// Describes a command along with every argument and option it accepts.
+///
+/// These definitions drive both argument parsing and the generated usage text.
+pub struct CommandSpec {
+    pub command: Command,
+    pub name: &'static str,
+    pub about: &'static str,
+    pub positionals: &'static [PositionalSpec],
+    pub options: &'static [OptionSpec]
+}
+
+pub const HELP_OPTION: OptionSpec = OptionSpec { short: Some('h'), long: "help", value_name: None, description: "Print help" };
+pub const VERSION_OPTION: OptionSpec = OptionSpec { short: Some('V'), long: "version", value_name: None, description: "Print version" };
+
+const TARGET_DIR: PositionalSpec = PositionalSpec { name: "target_dir", description: "Path to the target directory", required: true };
+const CLASS_COUNT: PositionalSpec = PositionalSpec { name: "class_count", description: "Number of classes, estimated from the images if omitted", required: false };
+const RUN_ID: PositionalSpec = PositionalSpec { name: "run_id", description: "Run to undo, defaults to the most recent run", required: false };
+const CACHE_ACTION: PositionalSpec = PositionalSpec { name: "action", description: "Either prune (forget images no longer in the directory) or clear (forget every image)", required: true };
+
+const RECURSIVE: OptionSpec = OptionSpec { short: Some('r'), long: "recursive", value_name: None, description: "Also search subdirectories" };
+const MAX_DEPTH: OptionSpec = OptionSpec { short: None, long: "max-depth", value_name: Some("depth"), description: "How many levels of subdirectories to search, implies --recursive" };
+const FOLLOW_SYMLINKS: OptionSpec = OptionSpec { short: Some('L'), long: "follow-symlinks", value_name: None, description: "Follow symbolic links to directories" };
+const HIDDEN: OptionSpec = OptionSpec { short: Some('a'), long: "hidden", value_name: None, description: "Include hidden files and directories" };
+const MAX_CLASSES: OptionSpec = OptionSpec { short: None, long: "max-classes", value_name: Some("count"), description: "Largest class count tried when estimating it, defaults to 10" };
+const SIMILARITY: OptionSpec = OptionSpec { short: None, long: "similarity", value_name: Some("mode"), description: "Compare every pair of images exactly, or through a nearest neighbour index, exact, index or auto, defaults to auto (index above 2000 images)" };
+const NEIGHBOURS: OptionSpec = OptionSpec { short: None, long: "neighbours", value_name: Some("count"), description: "Nearest neighbours linked to each image when clustering through the index, defaults to 15" };
+const MODEL: OptionSpec = OptionSpec { short: Some('m'), long: "model", value_name: Some("arch"), description: "Backbone architecture to embed images with, defaults to resnet34" };
+const WEIGHTS: OptionSpec = OptionSpec { short: Some('w'), long: "weights", value_name: Some("path"), description: "Model weights file, defaults to <arch>.ot in the working, executable or data directory" };
+const DEVICE: OptionSpec = OptionSpec { short: None, long: "device", value_name: Some("device"), description: "Run the model on cpu, cuda:N or auto, defaults to auto" };
+const THREADS: OptionSpec = OptionSpec { short: Some('j'), long: "threads", value_name: Some("count"), description: "CPU threads the model uses, defaults to the number of cores" };
+const BATCH_SIZE: OptionSpec = OptionSpec { short: Some('b'), long: "batch-size", value_name: Some("count"), description: "Images passed through the model at once, defaults to 16" };
+const WORKERS: OptionSpec = OptionSpec { short: None, long: "workers", value_name: Some("count"), description: "Threads decoding images ahead of the model, defaults to the number of cores" };
+const QUEUE_DEPTH: OptionSpec = OptionSpec { short: None, long: "queue-depth", value_name: Some("count"), description: "Decoded images waiting for the model, defaults to 64" };
+const NO_CACHE: OptionSpec = OptionSpec { short: None, long: "no-cache", value_name: None, description: "Neither reuse nor store embeddings or the nearest neighbour index in the cache of the output directory" };
+const EMBEDDING: OptionSpec = OptionSpec { short: Some('e'), long: "embedding", value_name: Some("mode"), description: "Embed images as class probabilities or pooled features, defaults to probabilities" };
+const LAYER: OptionSpec = OptionSpec { short: None, long: "layer", value_name: Some("name"), description: "Layer features are read from, defaults to the last one before the classifier" };
+const POOLING: OptionSpec = OptionSpec { short: None, long: "pooling", value_name: Some("mode"), description: "How features are pooled, avg, max or gem, defaults to avg" };
+const AUGMENT: OptionSpec = OptionSpec { short: None, long: "augment", value_name: Some("views"), description: "Also embed flip, crops or scales views of each image, separated by commas" };
+const AUGMENT_REDUCE: OptionSpec = OptionSpec { short: None, long: "augment-reduce", value_name: Some("mode"), description: "How the embeddings of the views are combined, mean or max, defaults to mean" };
+const PRECISION: OptionSpec = OptionSpec { short: None, long: "precision", value_name: Some("mode"), description: "Compute in fp32, fp16 or bf16, or int8 to quantize only the classifier head of ResNet and DenseNet, defaults to fp32" };
+const TORCHSCRIPT: OptionSpec = OptionSpec { short: None, long: "torchscript", value_name: Some("path"), description: "TorchScript (.pt) model to embed images with instead of --model" };
+const RESIZE: OptionSpec = OptionSpec { short: None, long: "resize", value_name: Some("mode"), description: "Fit images to the input with crop, letterbox or stretch, defaults to crop" };
+const INPUT_SIZE: OptionSpec = OptionSpec { short: None, long: "input-size", value_name: Some("px"), description: "Image size the model expects, defaults to that of the model or 224" };
+const NORMALIZATION: OptionSpec = OptionSpec { short: None, long: "normalization", value_name: Some("mode"), description: "Pixel scaling, imagenet, zero-one or raw, defaults to imagenet" };
+const MEAN: OptionSpec = OptionSpec { short: None, long: "mean", value_name: Some("r,g,b"), description: "Channel means to normalize with instead, given with --std" };
+const STD: OptionSpec = OptionSpec { short: None, long: "std", value_name: Some("r,g,b"), description: "Channel standard deviations to normalize with instead, given with --mean" };
+const ACTIVATION: OptionSpec = OptionSpec { short: None, long: "activation", value_name: Some("mode"), description: "Applied to the TorchScript output, none, softmax or l2, defaults to none" };
+const EXPORT: OptionSpec = OptionSpec { short: None, long: "export", value_name: Some("path"), description: "Write the embeddings to a .npy, .npz, .safetensors or .csv file" };
+const IMPORT: OptionSpec = OptionSpec { short: None, long: "import", value_name: Some("path"), description: "Cluster the embeddings in a .npy, .npz, .safetensors or .csv file instead of embedding images" };
+const IMAGE_LIST: OptionSpec = OptionSpec { short: None, long: "image-list", value_name: Some("path"), description: "Text file with the image path of each imported embedding, one per line" };
+const NO_NAMES: OptionSpec = OptionSpec { short: Some('n'), long: "no-names", value_name: None, description: "Do not generate class names" };
+const DRY_RUN: OptionSpec = OptionSpec { short: Some('d'), long: "dry-run", value_name: None, description: "Print the planned moves without changing any files" };
+const OUTPUT: OptionSpec = OptionSpec { short: Some('o'), long: "output", value_name: Some("dir"), description: "Build the class directories in this directory instead" };
+const PLACEMENT: OptionSpec = OptionSpec { short: Some('p'), long: "placement", value_name: Some("strategy"), description: "One of move, copy, symlink or hardlink, defaults to move (copy with --output)" };
+const DIR_CONFLICT: OptionSpec = OptionSpec { short: None, long: "dir-conflict", value_name: Some("policy"), description: "When a class directory exists, merge or suffix, defaults to merge" };
+const FILE_CONFLICT: OptionSpec = OptionSpec { short: None, long: "file-conflict", value_name: Some("policy"), description: "When a file exists, skip, rename or identical, defaults to rename" };
+
+/// Every command, in the order they are listed in the usage text.
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        command: Command::Sort,
+        name: "sort",
+        about: "Sort the images in a directory into class directories (default command)",
+        positionals: &[TARGET_DIR, CLASS_COUNT],
+        options: &[MODEL, WEIGHTS, EMBEDDING, LAYER, POOLING, AUGMENT, AUGMENT_REDUCE, PRECISION, TORCHSCRIPT, ACTIVATION, RESIZE, INPUT_SIZE, NORMALIZATION, MEAN, STD, DEVICE, THREADS, BATCH_SIZE, WORKERS, QUEUE_DEPTH, NO_CACHE, IMPORT, IMAGE_LIST, MAX_CLASSES, SIMILARITY, NEIGHBOURS, NO_NAMES, DRY_RUN, OUTPUT, PLACEMENT, DIR_CONFLICT, FILE_CONFLICT, RECURSIVE, MAX_DEPTH, FOLLOW_SYMLINKS, HIDDEN, HELP_OPTION]
+    },
+    CommandSpec {
+        command: Command::Embed,
+        name: "embed",
+        about: "Generate an embedding for every image without sorting",
+        positionals: &[TARGET_DIR],
+        options: &[MODEL, WEIGHTS, EMBEDDING, LAYER, POOLING, AUGMENT, AUGMENT_REDUCE, PRECISION, TORCHSCRIPT, ACTIVATION, RESIZE, INPUT_SIZE, NORMALIZATION, MEAN, STD, DEVICE, THREADS, BATCH_SIZE, WORKERS, QUEUE_DEPTH, NO_CACHE, EXPORT, RECURSIVE, MAX_DEPTH, FOLLOW_SYMLINKS, HIDDEN, HELP_OPTION]
+    },
+    CommandSpec {
+        command: Command::Cluster,
+        name: "cluster",
+        about: "Print how the images would be grouped without naming or sorting them",
+        positionals: &[TARGET_DIR, CLASS_COUNT],
+        options: &[MODEL, WEIGHTS, EMBEDDING, LAYER, POOLING, AUGMENT, AUGMENT_REDUCE, PRECISION, TORCHSCRIPT, ACTIVATION, RESIZE, INPUT_SIZE, NORMALIZATION, MEAN, STD, DEVICE, THREADS, BATCH_SIZE, WORKERS, QUEUE_DEPTH, NO_CACHE, IMPORT, IMAGE_LIST, MAX_CLASSES, SIMILARITY, NEIGHBOURS, RECURSIVE, MAX_DEPTH, FOLLOW_SYMLINKS, HIDDEN, HELP_OPTION]
+    },
+    CommandSpec {
+        command: Command::Name,
+        name: "name",
+        about: "Print the named classes the images would be sorted into without sorting them",
+        positionals: &[TARGET_DIR, CLASS_COUNT],
+        options: &[MODEL, WEIGHTS, EMBEDDING, LAYER, POOLING, AUGMENT, AUGMENT_REDUCE, PRECISION, TORCHSCRIPT, ACTIVATION, RESIZE, INPUT_SIZE, NORMALIZATION, MEAN, STD, DEVICE, THREADS, BATCH_SIZE, WORKERS, QUEUE_DEPTH, NO_CACHE, IMPORT, IMAGE_LIST, MAX_CLASSES, SIMILARITY, NEIGHBOURS, RECURSIVE, MAX_DEPTH, FOLLOW_SYMLINKS, HIDDEN, HELP_OPTION]
+    },
+    CommandSpec {
+        command: Command::Undo,
+        name: "undo",
+        about: "Reverse the changes made by a previous sort",
+        positionals: &[TARGET_DIR, RUN_ID],
+        options: &[HELP_OPTION]
+    },
+    CommandSpec {
+        command: Command::Cache,
+        name: "cache",
+        about: "Prune or clear the image embeddings cached for a directory",
+        positionals: &[TARGET_DIR, CACHE_ACTION],
+        options: &[HELP_OPTION]
+    }
+];
+
+impl Command {
+
+    /// Looks up a command by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        COMMANDS
+            .iter()
+            .find(|spec| { spec.name == name })
+            .map(|spec| { spec.command })
+    }
+
+    /// Returns the definition of this command.
+    pub fn spec(&self) -> &'static CommandSpec {
+        COMMANDS
+            .iter()
+            .find(|spec| { spec.command == *self })
+            .unwrap()
+    }
+}
+
+impl OptionSpec {
+
+    /// Checks if a command line token names this option.
+    pub fn matches(&self, token: &str) -> bool {
+        match (token.strip_prefix("--"), token.strip_prefix('-')) {
+            (Some(long), _) => long == self.long,
+            (None, Some(short)) => short.len() == 1 && self.short.is_some_and(|c| { short.starts_with(c) }),
+            _ => false
+        }
+    }
+}
+
+/// The result of parsing the arguments of a command against its `CommandSpec`.
+#[derive(PartialEq, Debug)]
+pub struct ParsedArgsModel {
+    command: Command,
+    positionals: Vec<String>,
+    options: HashMap<&'static str, Option<String>>
+}
+
+impl ParsedArgsModel {
+
+    /// Parses the arguments that follow a command name.
+    ///
+    /// Options may appear in any order, before or after positional arguments.
+    /// Option values can be given as `--option value`, `--option=value` or `-o value`.
+    /// Everything after `--` is treated as a positional argument.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command being parsed.
+    /// * `args` - The arguments following the command name.
+    ///
+    /// # Returns
+    ///
+    /// Returns the parsed arguments, or an `InvalidUsageError` for unknown options, missing values
+    /// and missing or unexpected positional arguments. Missing positional arguments are not
+    /// reported when help is requested.
+    pub fn parse(command: Command, args: &[String]) -> Result<Self, InvalidUsageError> {
+        let spec = command.spec();
+        let mut positionals = vec![];
+        let mut options = HashMap::new();
+        let mut tokens = args.iter();
+        let mut only_positionals = false;
+
+        while let Some(token) = tokens.next() {
+            if only_positionals || !token.starts_with('-') || token == "-" {
+                positionals.push(token.clone());
+                continue;
+            }
+
+            if token == "--" {
+                only_positionals = true;
+                continue;
+            }
+
+            // Split inline values given as `--option=value`
+            let (name, inline_value) = match token.split_once('=') {
+                Some((name, value)) if token.starts_with("--") => (name, Some(value.to_string())),
+                _ => (token.as_str(), None)
+            };
+
+            let option = spec.options
+                .iter()
+                .find(|option| { option.matches(name) })
+                .ok_or_else(|| { InvalidUsageError::new(command, format!("Unknown option {}", name)) })?;
+
+            let value = match (option.value_name, inline_value) {
+                (None, None) => None,
+                (None, Some(_)) => {
+                    return Err(InvalidUsageError::new(command, format!("Option --{} does not take a value", option.long)));
+                },
+                (Some(_), Some(value)) => Some(value),
+                (Some(value_name), None) => match tokens.next() {
+                    Some(value) => Some(value.clone()),
+                    None => {
+                        return Err(InvalidUsageError::new(command, format!("Option --{} requires a <{}>", option.long, value_name)));
+                    }
+                }
+            };
+
+            options.insert(option.long, value);
+        }
+
+        let parsed = Self {
+            command,
+            positionals,
+            options
+        };
+
+        if parsed.flag(HELP_OPTION.long) {
+            return Ok(parsed);
+        }
+
+        if parsed.positionals.len() > spec.positionals.len() {
+            return Err(InvalidUsageError::new(command, format!("Unexpected argument {}", parsed.positionals[spec.positionals.len()])));
+        }
+
+        if let Some(missing) = spec.positionals.iter().skip(parsed.positionals.len()).find(|positional| { positional.required }) {
+            return Err(InvalidUsageError::new(command, format!("Missing argument <{}>", missing.name)));
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
+    /// Returns the positional argument at `index`, if it was given.
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        self.positionals.get(index).map(|positional| { positional.as_str() })
+    }
+
+    /// Returns `true` if the option with the given long name was given.
+    pub fn flag(&self, long: &str) -> bool {
+        self.options.contains_key(long)
+    }
+
+    /// Returns the value of the option with the given long name, if it was given.
+    pub fn value(&self, long: &str) -> Option<&str> {
+        self.options.get(long).and_then(|value| { value.as_deref() })
+    }
+}
+
+/// Splits the full command line into the command and the arguments that follow it.
+///
+/// If the first argument is not a command name the `sort` command is assumed,
+/// so `tensort <target_dir> <class_count>` keeps working.
+pub fn split_command(args: &[String]) -> (Command, &[String]) {
+    let rest = args.get(1..).unwrap_or_default();
+
+    match rest.first().and_then(|name| { Command::from_name(name) }) {
+        Some(command) => (command, &rest[1..]),
+        None => (Command::Sort, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| { arg.to_string() }).collect()
+    }
+
+    #[test]
+    fn split_command_works() {
+        let args = to_args(&["tensort", "cluster", "dir", "3"]);
+        assert_eq!(split_command(&args), (Command::Cluster, &args[2..]));
+
+        let args = to_args(&["tensort", "dir", "3"]);
+        assert_eq!(split_command(&args), (Command::Sort, &args[1..]));
+
+        assert_eq!(split_command(&[]), (Command::Sort, &[][..]));
+    }
+
+    #[test]
+    fn parse_accepts_options_in_any_order() {
+        let args = to_args(&["-n", "dir", "--placement=copy", "3", "--output", "out", "-r"]);
+        let parsed = ParsedArgsModel::parse(Command::Sort, &args).unwrap();
+
+        assert_eq!(parsed.positional(0), Some("dir"));
+        assert_eq!(parsed.positional(1), Some("3"));
+        assert!(parsed.flag("no-names"));
+        assert!(parsed.flag("recursive"));
+        assert!(!parsed.flag("dry-run"));
+        assert_eq!(parsed.value("placement"), Some("copy"));
+        assert_eq!(parsed.value("output"), Some("out"));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_input() {
+        let unknown = ParsedArgsModel::parse(Command::Sort, &to_args(&["dir", "3", "--frobnicate"]));
+        assert!(unknown.unwrap_err().message.contains("Unknown option"));
+
+        // Options only apply to the commands that define them
+        assert!(ParsedArgsModel::parse(Command::Embed, &to_args(&["dir", "--dry-run"])).is_err());
+
+        assert!(ParsedArgsModel::parse(Command::Sort, &to_args(&["dir", "3", "--output"])).is_err());
+        assert!(ParsedArgsModel::parse(Command::Sort, &to_args(&["dir", "3", "-n=yes"])).is_err());
+        assert!(ParsedArgsModel::parse(Command::Sort, &to_args(&["-n"])).is_err());
+        assert!(ParsedArgsModel::parse(Command::Sort, &to_args(&["dir", "3", "extra"])).is_err());
+    }
+
+    #[test]
+    fn parse_with_help_skips_positional_checks() {
+        let parsed = ParsedArgsModel::parse(Command::Sort, &to_args(&["--help"])).unwrap();
+
+        assert!(parsed.flag("help"));
+    }
+}
\ No newline at end of file
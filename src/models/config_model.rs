@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use toml::{Spanned, Value};
+
+/// Prefix of the environment variables settings can be given in, such as `TENSORT_CLASS_COUNT`.
+pub const ENV_PREFIX: &str = "TENSORT_";
+
+/// Settings that can be given in a config file or the environment, named after their command line option.
+pub const SETTINGS: &[&str] = &[
+    "model",
+    "weights",
+    "embedding",
+    "layer",
+    "pooling",
+    "augment",
+    "augment-reduce",
+    "precision",
+    "torchscript",
+    "activation",
+    "resize",
+    "input-size",
+    "normalization",
+    "mean",
+    "std",
+    "device",
+    "threads",
+    "batch-size",
+    "workers",
+    "queue-depth",
+    "no-cache",
+    "class-count",
+    "max-classes",
+    "similarity",
+    "neighbours",
+    "no-names",
+    "output",
+    "placement",
+    "dir-conflict",
+    "file-conflict",
+    "recursive",
+    "max-depth",
+    "follow-symlinks",
+    "hidden"
+];
+
+/// Settings holding a path, which a config file gives relative to its own directory.
+const PATH_SETTINGS: &[&str] = &["weights", "torchscript", "output"];
+
+/// Where the effective value of a setting came from.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub enum ConfigSource {
+    #[default]
+    Default,
+    File(PathBuf),
+    Environment(String),
+    CommandLine
+}
+
+/// A setting value along with where it came from.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConfigValue {
+    pub value: String,
+    pub source: ConfigSource
+}
+
+/// Settings collected from config files and the environment.
+///
+/// Layers are merged in order, so a value set by a later layer overrides earlier ones.
+/// Command line options are not stored here, they are applied on top when the arguments are parsed.
+#[derive(PartialEq, Debug, Default)]
+pub struct ConfigModel {
+    values: HashMap<&'static str, ConfigValue>
+}
+
+impl ConfigModel {
+
+    /// Creates a configuration without any settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a setting, overriding any value from an earlier layer.
+    ///
+    /// Returns an error message if `key` is not a known setting.
+    pub fn set(&mut self, key: &str, value: String, source: ConfigSource) -> Result<(), String> {
+        let key = SETTINGS
+            .iter()
+            .find(|setting| { **setting == key })
+            .ok_or_else(|| { format!("Unknown setting {}", key) })?;
+
+        self.values.insert(key, ConfigValue { value, source });
+        Ok(())
+    }
+
+    /// Merges the settings of a config file on top of the current settings.
+    ///
+    /// Keys are written in snake case at the top level of the file, such as `class_count = 5`.
+    /// Lists such as `mean = [0.485, 0.456, 0.406]` are joined with commas, the way the command line takes them.
+    /// Relative paths are resolved against the directory of the config file.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The contents of the config file.
+    /// * `path` - Where the config file was read from.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error message naming the offending line if the file is not valid TOML, holds a table, or contains an unknown setting.
+    pub fn merge_toml(&mut self, text: &str, path: PathBuf) -> Result<(), String> {
+        let line_of = |offset: usize| { text[..offset].matches('\n').count() + 1 };
+
+        let mut entries: Vec<(String, Spanned<Value>)> = toml::from_str::<HashMap<String, Spanned<Value>>>(text)
+            .map_err(|err| {
+                let line_number = err.span().map_or(1, |span| { line_of(span.start) });
+                let message = match err.message().trim() {
+                    "" => String::from("expected a value"),
+                    message => message.replace('\n', ", ")
+                };
+
+                format!("line {}: {}", line_number, message)
+            })?
+            .into_iter()
+            .collect();
+
+        // Settings are applied in the order they are written, so errors name the first offending line
+        entries.sort_by_key(|(_, value)| { value.span().start });
+
+        for (key, value) in entries {
+            let line_number = line_of(value.span().start);
+            let key = key.replace('_', "-");
+
+            let value = format_toml_value(value.get_ref())
+                .ok_or_else(|| { format!("line {}: {} must be a string, number, boolean or list of them", line_number, key) })?;
+
+            let value = match PATH_SETTINGS.contains(&key.as_str()) {
+                true => resolve_relative_path(&path, value),
+                false => value
+            };
+
+            self.set(&key, value, ConfigSource::File(path.clone()))
+                .map_err(|message| { format!("line {}: {}", line_number, message) })?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges settings given as `TENSORT_*` environment variables on top of the current settings.
+    ///
+    /// Variables without the prefix are ignored, and so are prefixed variables that are not settings,
+    /// since the environment is shared with every other program.
+    ///
+    /// # Returns
+    ///
+    /// Returns the names of the prefixed variables that were ignored.
+    pub fn merge_env(&mut self, vars: impl Iterator<Item = (String, String)>) -> Vec<String> {
+        let mut ignored = vec![];
+
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                let key = key.to_lowercase().replace('_', "-");
+
+                if self.set(&key, value, ConfigSource::Environment(name.clone())).is_err() {
+                    ignored.push(name);
+                }
+            }
+        }
+
+        ignored
+    }
+
+    /// Returns the value of a setting, if any layer set it.
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.get(key)
+    }
+}
+
+/// Formats a TOML value the way the same setting is given on the command line.
+///
+/// # Returns
+///
+/// Returns the formatted value, or `None` for tables, dates and nested lists, which no setting takes.
+fn format_toml_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::Boolean(value) => Some(value.to_string()),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| { if value.is_array() { None } else { format_toml_value(value) } })
+            .collect::<Option<Vec<String>>>()
+            .map(|values| { values.join(",") }),
+        Value::Table(_) | Value::Datetime(_) => None
+    }
+}
+
+/// Resolves a path given in the config file at `config_path` against the directory of that file.
+fn resolve_relative_path(config_path: &Path, value: String) -> String {
+    let resolved = match config_path.parent() {
+        Some(config_dir) if Path::new(&value).is_relative() => config_dir.join(&value),
+        _ => return value
+    };
+
+    resolved
+        .to_str()
+        .map_or(value, |resolved| { resolved.to_string() })
+}
+
+/// Parses a boolean setting value, accepting `true`/`false`, `1`/`0` and `yes`/`no`.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" => Some(false),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_toml_reads_supported_values() {
+        let mut config = ConfigModel::new();
+        let path = PathBuf::from(".tensort.toml");
+        let text = "# tensort settings\n\
+            class_count = 5 # five classes\n\
+            placement = \"copy\"\n\
+            dir_conflict = 'suffix # here'\n\
+            mean = [0.5, 0.25, 1.0]\n\
+            augment = [\"flip\", \"crops\"]\n\
+            \n\
+            recursive = true\n";
+
+        config.merge_toml(text, path.clone()).unwrap();
+
+        assert_eq!(config.get("class-count").unwrap().value, "5");
+        assert_eq!(config.get("placement").unwrap().value, "copy");
+        assert_eq!(config.get("dir-conflict").unwrap().value, "suffix # here");
+        assert_eq!(config.get("mean").unwrap().value, "0.5,0.25,1");
+        assert_eq!(config.get("augment").unwrap().value, "flip,crops");
+        assert_eq!(config.get("recursive").unwrap().source, ConfigSource::File(path));
+        assert!(config.get("hidden").is_none());
+    }
+
+    #[test]
+    fn merge_toml_resolves_paths_against_config_dir() {
+        let mut config = ConfigModel::new();
+        let text = "weights = \"models/resnet34.ot\"\noutput = \"/srv/sorted\"\nlayer = \"layer4\"\n";
+
+        config.merge_toml(text, PathBuf::from("/home/user/.config/tensort/config.toml")).unwrap();
+
+        assert_eq!(config.get("weights").unwrap().value, "/home/user/.config/tensort/models/resnet34.ot");
+        assert_eq!(config.get("output").unwrap().value, "/srv/sorted");
+        assert_eq!(config.get("layer").unwrap().value, "layer4");
+
+        // The project config is read from the working directory, which relative paths are already resolved against
+        config.merge_toml("output = \"sorted\"", PathBuf::from(".tensort.toml")).unwrap();
+        assert_eq!(config.get("output").unwrap().value, "sorted");
+    }
+
+    #[test]
+    fn merge_toml_rejects_malformed_files() {
+        let path = PathBuf::from(".tensort.toml");
+
+        assert!(ConfigModel::new().merge_toml("class_count", path.clone()).is_err());
+        assert!(ConfigModel::new().merge_toml("placement = copy", path.clone()).is_err());
+        assert!(ConfigModel::new().merge_toml("placement = \"copy", path.clone()).is_err());
+        assert!(ConfigModel::new().merge_toml("placement = \"copy\" move", path.clone()).is_err());
+
+        let table = ConfigModel::new().merge_toml("[sort]\nclass_count = 5", path.clone());
+        assert_eq!(table, Err(String::from("line 1: sort must be a string, number, boolean or list of them")));
+
+        let missing_value = ConfigModel::new().merge_toml("\nclass_count = ", path.clone());
+        assert_eq!(missing_value, Err(String::from("line 2: expected a value")));
+
+        let unknown = ConfigModel::new().merge_toml("placement = \"copy\"\nclass_cuont = 5", path);
+        assert_eq!(unknown, Err(String::from("line 2: Unknown setting class-cuont")));
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let mut config = ConfigModel::new();
+
+        config.merge_toml("class_count = 5\nplacement = \"copy\"", PathBuf::from("config.toml")).unwrap();
+        let ignored = config.merge_env(vec![
+            (String::from("TENSORT_CLASS_COUNT"), String::from("7")),
+            (String::from("HOME"), String::from("/home/user"))
+        ].into_iter());
+
+        assert!(ignored.is_empty());
+
+        let class_count = config.get("class-count").unwrap();
+        assert_eq!(class_count.value, "7");
+        assert_eq!(class_count.source, ConfigSource::Environment(String::from("TENSORT_CLASS_COUNT")));
+        assert_eq!(config.get("placement").unwrap().value, "copy");
+
+        // Variables that only share the prefix are skipped without touching the settings
+        let ignored = config.merge_env(vec![
+            (String::from("TENSORT_FROBNICATE"), String::from("1")),
+            (String::from("TENSORT_PLACEMENT"), String::from("symlink"))
+        ].into_iter());
+
+        assert_eq!(ignored, vec![String::from("TENSORT_FROBNICATE")]);
+        assert_eq!(config.get("placement").unwrap().value, "symlink");
+        assert_eq!(config.get("frobnicate"), None);
+    }
+}
\ No newline at end of file
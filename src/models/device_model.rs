@@ -0,0 +1,74 @@
+use tch::Device;
+
+/// Which device the neural network runs on.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum DeviceSelection {
+    /// The first CUDA device if one is available, otherwise the CPU.
+    #[default]
+    Auto,
+    Cpu,
+    /// The CUDA device with the given index.
+    Cuda(usize)
+}
+
+impl DeviceSelection {
+
+    /// Looks up a device by its command line name, `auto`, `cpu`, `cuda` or `cuda:N`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "cpu" => Some(Self::Cpu),
+            "cuda" => Some(Self::Cuda(0)),
+            _ => name
+                .strip_prefix("cuda:")
+                .and_then(|index| { index.parse::<usize>().ok() })
+                .map(Self::Cuda)
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Auto => String::from("auto"),
+            Self::Cpu => String::from("cpu"),
+            Self::Cuda(index) => format!("cuda:{}", index)
+        }
+    }
+
+    /// Works out the device to run on, given the number of CUDA devices available.
+    ///
+    /// # Returns
+    ///
+    /// Returns the device, or `None` if the selected CUDA device does not exist.
+    pub fn resolve(&self, cuda_device_count: usize) -> Option<Device> {
+        match self {
+            Self::Auto if cuda_device_count > 0 => Some(Device::Cuda(0)),
+            Self::Auto | Self::Cpu => Some(Device::Cpu),
+            Self::Cuda(index) if *index < cuda_device_count => Some(Device::Cuda(*index)),
+            Self::Cuda(_) => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_works() {
+        assert_eq!(DeviceSelection::from_name("cpu"), Some(DeviceSelection::Cpu));
+        assert_eq!(DeviceSelection::from_name("cuda"), Some(DeviceSelection::Cuda(0)));
+        assert_eq!(DeviceSelection::from_name("cuda:2"), Some(DeviceSelection::Cuda(2)));
+        assert_eq!(DeviceSelection::from_name("cuda:two"), None);
+        assert_eq!(DeviceSelection::from_name("tpu"), None);
+        assert_eq!(DeviceSelection::from_name(&DeviceSelection::Cuda(1).name()), Some(DeviceSelection::Cuda(1)));
+    }
+
+    #[test]
+    fn resolve_works() {
+        assert_eq!(DeviceSelection::Auto.resolve(0), Some(Device::Cpu));
+        assert_eq!(DeviceSelection::Auto.resolve(2), Some(Device::Cuda(0)));
+        assert_eq!(DeviceSelection::Cpu.resolve(2), Some(Device::Cpu));
+        assert_eq!(DeviceSelection::Cuda(1).resolve(2), Some(Device::Cuda(1)));
+        assert_eq!(DeviceSelection::Cuda(2).resolve(2), None);
+    }
+}
\ No newline at end of file
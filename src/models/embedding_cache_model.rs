@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tch::{Kind, Tensor};
+
+/// Bytes every cache file starts with, changed whenever the layout of its entries changes.
+pub const CACHE_MAGIC: &[u8; 8] = b"TSCACHE1";
+
+/// Length of the identity, checksum and value count that precede the values of an entry.
+const ENTRY_HEADER_LEN: usize = 20;
+
+/// What the `cache` command does to the cache of a directory.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CacheAction {
+    /// Remove the embeddings of images that are no longer in the directory.
+    Prune,
+    /// Remove every embedding.
+    Clear
+}
+
+impl CacheAction {
+
+    /// Looks up a cache action by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "prune" => Some(Self::Prune),
+            "clear" => Some(Self::Clear),
+            _ => None
+        }
+    }
+}
+
+/// An embedding as stored in a cache file.
+///
+/// # Fields
+///
+/// - `identity`: Identifies the model that computed the embedding, including its weights and preprocessing.
+/// - `checksum`: The checksum of the contents of the image file, so the embedding follows the image when it is moved or renamed.
+/// - `embedding`: The values of the embedding.
+#[derive(PartialEq, Debug, Clone)]
+pub struct CacheEntry {
+    pub identity: u64,
+    pub checksum: u64,
+    pub embedding: Vec<f32>
+}
+
+impl CacheEntry {
+
+    /// Serializes the entry as its identity, checksum, number of values and then every value, all little endian.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ENTRY_HEADER_LEN + 4 * self.embedding.len());
+
+        bytes.extend(self.identity.to_le_bytes());
+        bytes.extend(self.checksum.to_le_bytes());
+        bytes.extend((self.embedding.len() as u32).to_le_bytes());
+
+        for value in &self.embedding {
+            bytes.extend(value.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Parses the entry at the start of `bytes`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the entry along with the number of bytes it takes up, or `None` if `bytes` ends before the entry does.
+    pub fn parse(bytes: &[u8]) -> Option<(Self, usize)> {
+        let header = bytes.get(..ENTRY_HEADER_LEN)?;
+        let identity = u64::from_le_bytes(header[0..8].try_into().ok()?);
+        let checksum = u64::from_le_bytes(header[8..16].try_into().ok()?);
+        let value_count = u32::from_le_bytes(header[16..20].try_into().ok()?) as usize;
+        let len = ENTRY_HEADER_LEN + 4 * value_count;
+
+        let embedding = bytes
+            .get(ENTRY_HEADER_LEN..len)?
+            .chunks_exact(4)
+            .map(|value| { f32::from_le_bytes([value[0], value[1], value[2], value[3]]) })
+            .collect();
+
+        Some((Self { identity, checksum, embedding }, len))
+    }
+}
+
+/// Parses every entry of a cache file.
+///
+/// Entries are appended as they are computed, so a run that was interrupted may have left the last entry partly written.
+/// Such an entry is left out.
+///
+/// # Returns
+///
+/// Returns the entries along with the number of bytes up to the end of the last complete one,
+/// or `None` if `bytes` is not a cache file.
+pub fn parse_cache(bytes: &[u8]) -> Option<(Vec<CacheEntry>, usize)> {
+    if !bytes.starts_with(CACHE_MAGIC) {
+        return None;
+    }
+
+    let mut len = CACHE_MAGIC.len();
+    let mut entries = vec![];
+
+    while let Some((entry, entry_len)) = CacheEntry::parse(&bytes[len..]) {
+        entries.push(entry);
+        len += entry_len;
+    }
+
+    Some((entries, len))
+}
+
+/// The cached embeddings of the model in use, along with how many images were looked up in them.
+///
+/// # Fields
+///
+/// - `identity`: Identifies the model in use, entries computed by any other model are ignored.
+/// - `embeddings`: The cached embedding of each image checksum.
+/// - `path`: The cache file new entries are appended to, or `None` if they are only kept for this run.
+/// - `hits`: The number of images whose embedding was cached.
+/// - `misses`: The number of images whose embedding had to be computed.
+#[derive(PartialEq, Debug)]
+pub struct EmbeddingCacheModel {
+    identity: u64,
+    embeddings: HashMap<u64, Vec<f32>>,
+    path: Option<PathBuf>,
+    hits: usize,
+    misses: usize
+}
+
+impl EmbeddingCacheModel {
+
+    /// Creates a cache of the entries computed by the model identified by `identity`.
+    /// New entries are appended to the cache file at `path`, if one is given.
+    pub fn new(identity: u64, entries: Vec<CacheEntry>, path: Option<PathBuf>) -> Self {
+        let embeddings = entries
+            .into_iter()
+            .filter(|entry| { entry.identity == identity })
+            .map(|entry| { (entry.checksum, entry.embedding) })
+            .collect();
+
+        Self {
+            identity,
+            embeddings,
+            path,
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    /// Returns the number of embeddings cached for the model in use.
+    pub fn entry_count(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Looks up the embedding of an image by the checksum of its contents, counting it as a hit or a miss.
+    /// An image whose checksum is `None`, because it could not be read, is always a miss.
+    pub fn lookup(&mut self, checksum: Option<u64>) -> Option<Tensor> {
+        let embedding = checksum.and_then(|checksum| { self.embeddings.get(&checksum) });
+
+        match embedding {
+            Some(embedding) => {
+                self.hits += 1;
+                Some(Tensor::from_slice(embedding))
+            },
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Adds the embedding of an image.
+    ///
+    /// # Returns
+    ///
+    /// Returns the entry to append to the cache file, or `None` if the image was already cached.
+    pub fn insert(&mut self, checksum: u64, embedding: &Tensor) -> Option<CacheEntry> {
+        if self.embeddings.contains_key(&checksum) {
+            return None;
+        }
+
+        let embedding = Vec::<f32>::try_from(embedding.flatten(0, -1).to_kind(Kind::Float)).ok()?;
+        self.embeddings.insert(checksum, embedding.clone());
+
+        Some(CacheEntry {
+            identity: self.identity,
+            checksum,
+            embedding
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_and_skip_partial_entry() {
+        let entries = vec![
+            CacheEntry { identity: 1, checksum: 2, embedding: vec![0.5, -1.0] },
+            CacheEntry { identity: 3, checksum: 4, embedding: vec![] }
+        ];
+
+        let mut bytes = CACHE_MAGIC.to_vec();
+        entries.iter().for_each(|entry| { bytes.extend(entry.serialize()) });
+        let len = bytes.len();
+
+        assert_eq!(parse_cache(&bytes), Some((entries.clone(), len)));
+
+        // A run interrupted while writing leaves half an entry behind
+        bytes.extend(&entries[0].serialize()[..10]);
+        assert_eq!(parse_cache(&bytes), Some((entries, len)));
+        assert_eq!(parse_cache(b"not a cache"), None);
+    }
+
+    #[test]
+    fn lookup_counts_hits_and_misses() {
+        let entries = vec![
+            CacheEntry { identity: 1, checksum: 7, embedding: vec![1.0, 2.0] },
+            CacheEntry { identity: 2, checksum: 8, embedding: vec![3.0, 4.0] }
+        ];
+        let mut cache = EmbeddingCacheModel::new(1, entries, None);
+
+        // Only entries of the model in use are found
+        assert_eq!(cache.entry_count(), 1);
+        assert!(cache.lookup(Some(7)).is_some());
+        assert!(cache.lookup(Some(8)).is_none());
+        assert!(cache.lookup(None).is_none());
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+        let entry = cache.insert(8, &Tensor::from_slice(&[5.0f32, 6.0])).unwrap();
+
+        assert_eq!(entry, CacheEntry { identity: 1, checksum: 8, embedding: vec![5.0, 6.0] });
+        assert!(cache.insert(8, &Tensor::from_slice(&[5.0f32, 6.0])).is_none());
+        assert_eq!(Vec::<f32>::try_from(cache.lookup(Some(8)).unwrap()).unwrap(), vec![5.0, 6.0]);
+    }
+}
\ No newline at end of file
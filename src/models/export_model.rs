@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+/// A file format embeddings can be exported to.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    /// A NumPy array of the embeddings, with the image paths and metadata in a JSON file next to it.
+    Npy,
+    /// A NumPy archive holding the `embeddings`, the image `paths` and the `metadata` as JSON.
+    Npz,
+    /// A safetensors file holding the `embeddings`, with the image paths and metadata in its header metadata.
+    SafeTensors,
+    /// A CSV file with the path of an image followed by its embedding on each row, with the metadata in a JSON file next to it.
+    Csv
+}
+
+impl ExportFormat {
+
+    /// Looks up an export format by its file extension, ignoring case.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "npy" => Some(Self::Npy),
+            "npz" => Some(Self::Npz),
+            "safetensors" => Some(Self::SafeTensors),
+            "csv" => Some(Self::Csv),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Npy => "npy",
+            Self::Npz => "npz",
+            Self::SafeTensors => "safetensors",
+            Self::Csv => "csv"
+        }
+    }
+
+    /// Returns `true` if the paths and metadata are written to a JSON file next to the export.
+    pub fn has_sidecar(&self) -> bool {
+        matches!(self, Self::Npy | Self::Csv)
+    }
+}
+
+/// Where and how embeddings are exported.
+///
+/// # Fields
+///
+/// - `path`: The file the embeddings are written to.
+/// - `format`: The format of the file, chosen by its extension.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ExportModel {
+    path: PathBuf,
+    format: ExportFormat
+}
+
+impl ExportModel {
+
+    /// Creates an export to `path`, or `None` if its extension is not that of a supported format.
+    pub fn new(path: &Path) -> Option<Self> {
+        let format = path
+            .extension()
+            .and_then(|extension| { extension.to_str() })
+            .and_then(ExportFormat::from_extension)?;
+
+        Some(Self {
+            path: path.to_path_buf(),
+            format
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Returns the JSON file the paths and metadata are written to, for formats that cannot hold them.
+    pub fn sidecar_path(&self) -> Option<PathBuf> {
+        self.format.has_sidecar().then(|| { self.path.with_extension("json") })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_chosen_by_extension() {
+        let export = ExportModel::new(Path::new("out/vectors.NPZ")).unwrap();
+
+        assert_eq!(export.format(), ExportFormat::Npz);
+        assert_eq!(export.sidecar_path(), None);
+        assert_eq!(ExportModel::new(Path::new("vectors.csv")).unwrap().sidecar_path(), Some(PathBuf::from("vectors.json")));
+        assert_eq!(ExportModel::new(Path::new("vectors.parquet")), None);
+        assert_eq!(ExportModel::new(Path::new("vectors")), None);
+    }
+}
\ No newline at end of file
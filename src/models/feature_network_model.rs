@@ -0,0 +1,419 @@
+use std::panic::{self, AssertUnwindSafe};
+use tch::{nn, Kind, Scalar, TchError, Tensor};
+use tch::nn::{Conv2D, Linear, ModuleT, Path, SequentialT};
+
+/// Exponent used by generalized mean pooling. `1` is average pooling and larger values approach max pooling.
+pub const GEM_POWER: f64 = 3.0;
+
+/// How a feature map is reduced to a single feature vector.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Pooling {
+    /// The mean of each channel.
+    #[default]
+    Average,
+    /// The largest value of each channel.
+    Max,
+    /// The generalized mean of each channel, which favours strong activations without discarding the rest.
+    Gem
+}
+
+impl Pooling {
+
+    /// Looks up a pooling by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "avg" => Some(Self::Average),
+            "max" => Some(Self::Max),
+            "gem" => Some(Self::Gem),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Average => "avg",
+            Self::Max => "max",
+            Self::Gem => "gem"
+        }
+    }
+
+    /// Pools a batch of `N x C x H x W` feature maps into `N x C` feature vectors.
+    pub fn apply(&self, features: &Tensor) -> Result<Tensor, TchError> {
+        let pooled = match self {
+            Self::Average => features.f_adaptive_avg_pool2d([1, 1])?,
+            Self::Max => features.f_adaptive_max_pool2d([1, 1])?.0,
+            Self::Gem => features
+                .f_clamp_min(1e-6)?
+                .f_pow_tensor_scalar(GEM_POWER)?
+                .f_adaptive_avg_pool2d([1, 1])?
+                .f_pow_tensor_scalar(1.0 / GEM_POWER)?
+        };
+
+        pooled.f_flatten(1, -1)
+    }
+}
+
+/// A linear layer with its weights quantized to 8-bit integers, for dynamic quantized inference on the CPU.
+///
+/// The weights are quantized once with a single scale and zero point, while inputs stay `Float32`
+/// and are quantized on the fly by the fbgemm kernel.
+#[derive(Debug)]
+pub struct QuantizedLinear {
+    weight: Tensor,
+    packed_weight: Tensor,
+    col_offsets: Tensor,
+    scale: f64,
+    zero_point: i64,
+    bias: Tensor
+}
+
+impl QuantizedLinear {
+
+    /// Quantizes the weights of a linear layer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the quantized layer, or a `TchError` if libtorch was built without the fbgemm kernels.
+    pub fn new(linear: &Linear) -> Result<Self, TchError> {
+        let quantized = linear.ws
+            .detach()
+            .to_kind(Kind::Float)
+            .f_quantize_per_tensor_dynamic(Kind::QInt8, false)?;
+        let scale = quantized.f_q_scale()?;
+        let zero_point = quantized.f_q_zero_point()?;
+        let weight = quantized.f_int_repr()?;
+        let (out_dim, in_dim) = weight.size2()?;
+
+        // The kernel expects the sum of each row of weights, less the zero point once for every value in the row
+        let col_offsets = (weight.f_sum_dim_intlist(1, false, Kind::Int)? - zero_point * in_dim).to_kind(Kind::Int);
+        let bias = match &linear.bs {
+            Some(bias) => bias.detach().to_kind(Kind::Float),
+            None => Tensor::zeros([out_dim], (Kind::Float, weight.device()))
+        };
+
+        Ok(Self {
+            packed_weight: weight.f_fbgemm_pack_quantized_matrix()?,
+            weight,
+            col_offsets,
+            scale,
+            zero_point,
+            bias
+        })
+    }
+
+    /// Runs a batch of `Float32` or reduced precision inputs through the layer, returning `Float32` outputs.
+    pub fn forward(&self, xs: &Tensor) -> Result<Tensor, TchError> {
+        xs
+            .f_to_kind(Kind::Float)?
+            .f_fbgemm_linear_int8_weight_fp32_activation(
+                &self.weight,
+                &self.packed_weight,
+                &self.col_offsets,
+                Scalar::float(self.scale),
+                Scalar::int(self.zero_point),
+                &self.bias
+            )
+    }
+}
+
+/// A network split into named stages, so the feature map of any stage can be read,
+/// along with the classifier head that turns pooled features of the last stage into class logits.
+///
+/// Run as a whole, the network classifies images like the network in `tch::vision` it mirrors.
+#[derive(Debug)]
+pub struct FeatureNetwork {
+    stages: Vec<(&'static str, Box<dyn ModuleT>)>,
+    head: Linear,
+    quantized_head: Option<QuantizedLinear>
+}
+
+impl FeatureNetwork {
+
+    /// Returns the name of the last stage, whose features the classifier head expects.
+    pub fn last_layer(&self) -> &'static str {
+        self.stages.last().map(|(name, _)| { *name }).unwrap_or_default()
+    }
+
+    /// Runs a batch of images through every stage up to and including `layer`, returning its feature map.
+    pub fn forward_until(&self, images: &Tensor, layer: &str) -> Result<Tensor, TchError> {
+        let mut features = images.shallow_clone();
+
+        for (name, stage) in &self.stages {
+            features = forward_module(stage.as_ref(), &features)?;
+
+            if *name == layer {
+                break;
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Turns a batch of pooled features of the last stage into class logits.
+    pub fn classify(&self, features: &Tensor) -> Result<Tensor, TchError> {
+        match &self.quantized_head {
+            Some(head) => head.forward(features),
+            None => features.f_linear(&self.head.ws, self.head.bs.as_ref())
+        }
+    }
+
+    /// Quantizes the classifier head to int8, the only linear layer of the network.
+    pub fn quantize_head(&mut self) -> Result<(), TchError> {
+        self.quantized_head = Some(QuantizedLinear::new(&self.head)?);
+
+        Ok(())
+    }
+}
+
+impl ModuleT for FeatureNetwork {
+    fn forward_t(&self, images: &Tensor, _train: bool) -> Tensor {
+        self.forward_until(images, self.last_layer())
+            .and_then(|features| { Pooling::Average.apply(&features) })
+            .and_then(|features| { self.classify(&features) })
+            .unwrap()
+    }
+}
+
+/// Runs a batch through a module in inference mode.
+///
+/// Modules only have an infallible `forward_t`, which panics when libtorch fails, for example when a layer has no kernel
+/// for a reduced precision on the device. The panic is caught and returned as the `TchError` libtorch raised.
+///
+/// # Arguments
+///
+/// * `module` - The module to run.
+/// * `xs` - The batch to run through it.
+///
+/// # Returns
+///
+/// Returns the output of the module, or a `TchError` if it failed.
+pub fn forward_module(module: &dyn ModuleT, xs: &Tensor) -> Result<Tensor, TchError> {
+    panic::catch_unwind(AssertUnwindSafe(|| { module.forward_t(xs, false) })).map_err(|payload| {
+        let message = payload.downcast_ref::<String>()
+            .cloned()
+            .or_else(|| { payload.downcast_ref::<&str>().map(|message| { message.to_string() }) })
+            .unwrap_or_else(|| { String::from("The network failed") });
+
+        TchError::Torch(message)
+    })
+}
+
+// The builders below mirror the networks in `tch::vision`, using the same variable names so that the same weights load into them
+
+fn conv2d(p: Path, c_in: i64, c_out: i64, ksize: i64, padding: i64, stride: i64) -> Conv2D {
+    let config = nn::ConvConfig { stride, padding, bias: false, ..Default::default() };
+    nn::conv2d(p, c_in, c_out, ksize, config)
+}
+
+fn resnet_downsample(p: Path, c_in: i64, c_out: i64, stride: i64) -> SequentialT {
+    if stride == 1 && c_in == c_out {
+        return nn::seq_t();
+    }
+
+    nn::seq_t()
+        .add(conv2d(&p / "0", c_in, c_out, 1, 0, stride))
+        .add(nn::batch_norm2d(&p / "1", c_out, Default::default()))
+}
+
+fn resnet_block(p: Path, c_in: i64, c_out: i64, stride: i64, bottleneck: bool) -> nn::FuncT<'static> {
+    let downsample = resnet_downsample(&p / "downsample", c_in, if bottleneck { 4 * c_out } else { c_out }, stride);
+
+    if !bottleneck {
+        let conv1 = conv2d(&p / "conv1", c_in, c_out, 3, 1, stride);
+        let bn1 = nn::batch_norm2d(&p / "bn1", c_out, Default::default());
+        let conv2 = conv2d(&p / "conv2", c_out, c_out, 3, 1, 1);
+        let bn2 = nn::batch_norm2d(&p / "bn2", c_out, Default::default());
+
+        return nn::func_t(move |xs, train| {
+            let ys = xs.apply(&conv1).apply_t(&bn1, train).relu().apply(&conv2).apply_t(&bn2, train);
+            (xs.apply_t(&downsample, train) + ys).relu()
+        });
+    }
+
+    let conv1 = conv2d(&p / "conv1", c_in, c_out, 1, 0, 1);
+    let bn1 = nn::batch_norm2d(&p / "bn1", c_out, Default::default());
+    let conv2 = conv2d(&p / "conv2", c_out, c_out, 3, 1, stride);
+    let bn2 = nn::batch_norm2d(&p / "bn2", c_out, Default::default());
+    let conv3 = conv2d(&p / "conv3", c_out, 4 * c_out, 1, 0, 1);
+    let bn3 = nn::batch_norm2d(&p / "bn3", 4 * c_out, Default::default());
+
+    nn::func_t(move |xs, train| {
+        let ys = xs
+            .apply(&conv1)
+            .apply_t(&bn1, train)
+            .relu()
+            .apply(&conv2)
+            .apply_t(&bn2, train)
+            .relu()
+            .apply(&conv3)
+            .apply_t(&bn3, train);
+
+        (xs.apply_t(&downsample, train) + ys).relu()
+    })
+}
+
+/// Builds a ResNet split into its four residual layers, `layer1` to `layer4`. The first layer includes the stem.
+///
+/// # Arguments
+///
+/// * `p` - The variable store path to build the network under.
+/// * `class_count` - The number of classes the head outputs.
+/// * `block_counts` - The number of blocks in each layer.
+/// * `bottleneck` - Whether the layers use bottleneck blocks, as in ResNet-50 and deeper.
+pub fn resnet(p: &Path, class_count: i64, block_counts: [i64; 4], bottleneck: bool) -> FeatureNetwork {
+    let expansion = if bottleneck { 4 } else { 1 };
+    let mut stages: Vec<(&'static str, Box<dyn ModuleT>)> = vec![];
+    let mut stage = nn::seq_t()
+        .add(conv2d(p / "conv1", 3, 64, 7, 3, 2))
+        .add(nn::batch_norm2d(p / "bn1", 64, Default::default()))
+        .add_fn(|xs| { xs.relu().max_pool2d([3, 3], [2, 2], [1, 1], [1, 1], false) });
+    let mut c_in = 64;
+
+    for (i, (name, c_out)) in [("layer1", 64), ("layer2", 128), ("layer3", 256), ("layer4", 512)].into_iter().enumerate() {
+        let stride = if i == 0 { 1 } else { 2 };
+
+        for j in 0..block_counts[i] {
+            stage = stage.add(resnet_block(p / name / j.to_string(), c_in, c_out, if j == 0 { stride } else { 1 }, bottleneck));
+            c_in = expansion * c_out;
+        }
+
+        stages.push((name, Box::new(stage)));
+        stage = nn::seq_t();
+    }
+
+    FeatureNetwork {
+        stages,
+        head: nn::linear(p / "fc", c_in, class_count, Default::default()),
+        quantized_head: None
+    }
+}
+
+fn dense_layer(p: Path, c_in: i64, bn_size: i64, growth: i64) -> nn::FuncT<'static> {
+    let c_inter = bn_size * growth;
+    let bn1 = nn::batch_norm2d(&p / "norm1", c_in, Default::default());
+    let conv1 = conv2d(&p / "conv1", c_in, c_inter, 1, 0, 1);
+    let bn2 = nn::batch_norm2d(&p / "norm2", c_inter, Default::default());
+    let conv2 = conv2d(&p / "conv2", c_inter, growth, 3, 1, 1);
+
+    nn::func_t(move |xs, train| {
+        let ys = xs.apply_t(&bn1, train).relu().apply(&conv1).apply_t(&bn2, train).relu().apply(&conv2);
+        Tensor::cat(&[xs, &ys], 1)
+    })
+}
+
+/// Builds a DenseNet split into its four dense blocks, `denseblock1` to `denseblock4`.
+///
+/// The first block includes the stem, every later block the transition in front of it, and the last block the final normalization.
+///
+/// # Arguments
+///
+/// * `p` - The variable store path to build the network under.
+/// * `class_count` - The number of classes the head outputs.
+/// * `c_in` - The number of channels after the stem.
+/// * `growth` - The number of channels each dense layer adds.
+/// * `layer_counts` - The number of dense layers in each block.
+pub fn densenet(p: &Path, class_count: i64, c_in: i64, growth: i64, layer_counts: [i64; 4]) -> FeatureNetwork {
+    let bn_size = 4;
+    let fp = p / "features";
+    let mut stages: Vec<(&'static str, Box<dyn ModuleT>)> = vec![];
+    let mut stage = nn::seq_t()
+        .add(conv2d(&fp / "conv0", 3, c_in, 7, 3, 2))
+        .add(nn::batch_norm2d(&fp / "norm0", c_in, Default::default()))
+        .add_fn(|xs| { xs.relu().max_pool2d([3, 3], [2, 2], [1, 1], [1, 1], false) });
+    let mut feature_count = c_in;
+
+    for (i, name) in ["denseblock1", "denseblock2", "denseblock3", "denseblock4"].into_iter().enumerate() {
+        if i > 0 {
+            let transition = &fp / format!("transition{}", i);
+
+            stage = stage
+                .add(nn::batch_norm2d(&transition / "norm", feature_count, Default::default()))
+                .add_fn(|xs| { xs.relu() })
+                .add(conv2d(&transition / "conv", feature_count, feature_count / 2, 1, 0, 1))
+                .add_fn(|xs| { xs.avg_pool2d_default(2) });
+
+            feature_count /= 2;
+        }
+
+        for j in 0..layer_counts[i] {
+            stage = stage.add(dense_layer(&fp / name / format!("denselayer{}", j + 1), feature_count + j * growth, bn_size, growth));
+        }
+
+        feature_count += layer_counts[i] * growth;
+
+        if i == 3 {
+            stage = stage
+                .add(nn::batch_norm2d(&fp / "norm5", feature_count, Default::default()))
+                .add_fn(|xs| { xs.relu() });
+        }
+
+        stages.push((name, Box::new(stage)));
+        stage = nn::seq_t();
+    }
+
+    FeatureNetwork {
+        stages,
+        head: nn::linear(p / "classifier", feature_count, class_count, Default::default()),
+        quantized_head: None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tch::{Device, Kind};
+    use tch::nn::VarStore;
+    use super::*;
+
+    #[test]
+    fn pooling_reduces_feature_maps() {
+        let features = Tensor::from_slice(&[1.0f32, 2.0, 3.0, 6.0]).view([1, 1, 2, 2]);
+
+        let average = Pooling::Average.apply(&features).unwrap();
+        let max = Pooling::Max.apply(&features).unwrap();
+        let gem = Pooling::Gem.apply(&features).unwrap();
+
+        assert_eq!(average.size(), [1, 1]);
+        assert_eq!(average.double_value(&[0, 0]), 3.0);
+        assert_eq!(max.double_value(&[0, 0]), 6.0);
+
+        // Generalized mean pooling lies between the two
+        assert!(gem.double_value(&[0, 0]) > 3.0 && gem.double_value(&[0, 0]) < 6.0);
+        assert_eq!(Pooling::from_name(Pooling::Gem.name()), Some(Pooling::Gem));
+    }
+
+    #[test]
+    fn resnet_stages_output_features() {
+        let varstore = VarStore::new(Device::Cpu);
+        let network = resnet(&varstore.root(), 10, [2, 2, 2, 2], false);
+        let images = Tensor::zeros([1, 3, 64, 64], (Kind::Float, Device::Cpu));
+
+        assert_eq!(network.last_layer(), "layer4");
+        assert_eq!(network.forward_until(&images, "layer2").unwrap().size(), [1, 128, 8, 8]);
+
+        let features = Pooling::Average.apply(&network.forward_until(&images, "layer4").unwrap()).unwrap();
+        assert_eq!(network.classify(&features).unwrap().size(), [1, 10]);
+    }
+
+    #[test]
+    fn quantized_head_stays_close_to_float_head() {
+        let varstore = VarStore::new(Device::Cpu);
+        let mut network = resnet(&varstore.root(), 10, [1, 1, 1, 1], false);
+        let features = Tensor::randn([4, 512], (Kind::Float, Device::Cpu));
+        let logits = network.classify(&features).unwrap();
+
+        network.quantize_head().unwrap();
+        let quantized = network.classify(&features).unwrap();
+
+        assert_eq!(quantized.size(), [4, 10]);
+        assert!((logits - quantized).abs().max().double_value(&[]) < 0.1);
+    }
+
+    #[test]
+    fn forward_module_returns_torch_errors() {
+        let module = nn::func_t(|xs, _| { xs.view([3]) });
+        let xs = Tensor::zeros([4], (Kind::Float, Device::Cpu));
+
+        assert!(matches!(forward_module(&module, &xs), Err(TchError::Torch(_))));
+        assert_eq!(forward_module(&module, &Tensor::zeros([3], (Kind::Float, Device::Cpu))).unwrap().size(), [3]);
+    }
+}
\ No newline at end of file
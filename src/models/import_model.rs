@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+use crate::models::export_model::ExportFormat;
+
+/// Embeddings computed elsewhere that are clustered instead of embedding the images of the target directory.
+///
+/// # Fields
+///
+/// - `path`: The file the embeddings are read from.
+/// - `format`: The format of the file, chosen by its extension. Every format embeddings are exported to can be imported.
+/// - `image_list`: A text file listing the image of each embedding, one path per line, or `None` to use the paths stored with the embeddings.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ImportModel {
+    path: PathBuf,
+    format: ExportFormat,
+    image_list: Option<PathBuf>
+}
+
+impl ImportModel {
+
+    /// Creates an import of `path`, or `None` if its extension is not that of a supported format.
+    pub fn new(path: &Path, image_list: Option<&Path>) -> Option<Self> {
+        let format = path
+            .extension()
+            .and_then(|extension| { extension.to_str() })
+            .and_then(ExportFormat::from_extension)?;
+
+        Some(Self {
+            path: path.to_path_buf(),
+            format,
+            image_list: image_list.map(Path::to_path_buf)
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Returns the file listing the image of each embedding, or `None` if the paths are stored with the embeddings.
+    pub fn image_list(&self) -> Option<&Path> {
+        self.image_list.as_deref()
+    }
+
+    /// Returns the JSON file the paths are read from when the format cannot hold them, as written by an export.
+    pub fn sidecar_path(&self) -> Option<PathBuf> {
+        (self.format == ExportFormat::Npy).then(|| { self.path.with_extension("json") })
+    }
+}
\ No newline at end of file
@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+use crate::models::sort_plan_model::PlacementStrategy;
+
+const JOURNAL_HEADER: &str = "tensort-journal 1";
+
+/// Records a single file that was placed during a run along with
+/// the state of the destination right after it was placed.
+///
+/// If `replaced` is set, an identical file already existed at `dest` before the run.
+#[derive(PartialEq, Debug, Clone)]
+pub struct JournalEntry {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+    pub size: u64,
+    pub modified: u128,
+    pub replaced: bool
+}
+
+/// Records every change made to the filesystem by a single run so that it can be undone.
+///
+/// # Format
+///
+/// Journals are stored as plain text, one record per line:
+///
+/// ```text
+/// tensort-journal 1
+/// run <run_id>
+/// placement <strategy>
+/// dir <created_dir>
+/// file <size> <modified> <src> <dest>
+/// replaced <size> <modified> <src> <dest>
+/// ```
+///
+/// Fields are separated by tabs and paths are escaped so that they always fit on a single line.
+/// Records are appended as changes are made, so a record is only complete once its line ends.
+#[derive(PartialEq, Debug, Clone)]
+pub struct JournalModel {
+    run_id: String,
+    placement: PlacementStrategy,
+    created_dirs: Vec<PathBuf>,
+    entries: Vec<JournalEntry>
+}
+
+impl JournalModel {
+
+    /// Creates an empty journal for a new run.
+    pub fn new(run_id: String, placement: PlacementStrategy) -> Self {
+        Self {
+            run_id,
+            placement,
+            created_dirs: vec![],
+            entries: vec![]
+        }
+    }
+
+    /// Records a directory that was created during the run.
+    pub fn add_created_dir(&mut self, dir: PathBuf) {
+        self.created_dirs.push(dir);
+    }
+
+    /// Records a file that was placed during the run.
+    pub fn add_entry(&mut self, entry: JournalEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Forgets the most recently created directory, once it no longer needs undoing.
+    pub fn pop_created_dir(&mut self) -> Option<PathBuf> {
+        self.created_dirs.pop()
+    }
+
+    /// Forgets the most recently placed file, once it no longer needs undoing.
+    pub fn pop_entry(&mut self) -> Option<JournalEntry> {
+        self.entries.pop()
+    }
+
+    /// Returns whether there is nothing left in the journal to undo.
+    pub fn is_empty(&self) -> bool {
+        self.created_dirs.is_empty() && self.entries.is_empty()
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    pub fn placement(&self) -> PlacementStrategy {
+        self.placement
+    }
+
+    pub fn created_dirs(&self) -> &[PathBuf] {
+        &self.created_dirs
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Serializes the records that identify the run, which start every journal.
+    pub fn serialize_header(&self) -> String {
+        format!("{}\nrun\t{}\nplacement\t{}\n", JOURNAL_HEADER, escape(&self.run_id), self.placement.name())
+    }
+
+    /// Serializes the record of a created directory.
+    ///
+    /// Returns `None` if the path is not valid UTF-8.
+    pub fn serialize_created_dir(dir: &Path) -> Option<String> {
+        Some(format!("dir\t{}\n", escape(dir.to_str()?)))
+    }
+
+    /// Serializes the record of a placed file.
+    ///
+    /// Returns `None` if either path is not valid UTF-8.
+    pub fn serialize_entry(entry: &JournalEntry) -> Option<String> {
+        Some(format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            if entry.replaced { "replaced" } else { "file" },
+            entry.size,
+            entry.modified,
+            escape(entry.src.to_str()?),
+            escape(entry.dest.to_str()?)
+        ))
+    }
+
+    /// Serializes the journal into its plain text format.
+    ///
+    /// Returns `None` if any recorded path is not valid UTF-8.
+    pub fn serialize(&self) -> Option<String> {
+        let mut text = self.serialize_header();
+
+        for dir in &self.created_dirs {
+            text.push_str(&Self::serialize_created_dir(dir)?);
+        }
+
+        for entry in &self.entries {
+            text.push_str(&Self::serialize_entry(entry)?);
+        }
+
+        Some(text)
+    }
+
+    /// Parses a journal from its plain text format.
+    ///
+    /// Returns `None` if the text is not a well formed journal.
+    pub fn parse(text: &str) -> Option<Self> {
+
+        // A run that was interrupted while appending a record leaves it without its line ending
+        let complete = match text.rfind('\n') {
+            Some(end) => &text[..=end],
+            None => text
+        };
+        let mut lines = complete.lines();
+
+        if lines.next()? != JOURNAL_HEADER {
+            return None;
+        }
+
+        let run_id = unescape(lines.next()?.strip_prefix("run\t")?)?;
+        let placement = PlacementStrategy::from_name(lines.next()?.strip_prefix("placement\t")?)?;
+        let mut journal = Self::new(run_id, placement);
+
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            match fields.as_slice() {
+                ["dir", dir] => journal.add_created_dir(PathBuf::from(unescape(dir)?)),
+                [kind @ ("file" | "replaced"), size, modified, src, dest] => journal.add_entry(JournalEntry {
+                    src: PathBuf::from(unescape(src)?),
+                    dest: PathBuf::from(unescape(dest)?),
+                    size: size.parse().ok()?,
+                    modified: modified.parse().ok()?,
+                    replaced: *kind == "replaced"
+                }),
+                _ => return None
+            }
+        }
+
+        Some(journal)
+    }
+}
+
+/// Escapes backslashes, tabs and newlines so a value fits in a single journal field.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// Reverses `escape`, returning `None` on an invalid escape sequence.
+fn unescape(value: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            't' => unescaped.push('\t'),
+            'n' => unescaped.push('\n'),
+            _ => return None
+        }
+    }
+
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_and_parse_round_trip() {
+        let mut journal = JournalModel::new(String::from("1718000000000"), PlacementStrategy::Copy);
+
+        journal.add_created_dir(PathBuf::from("/images/Class 1"));
+        journal.add_entry(JournalEntry {
+            src: PathBuf::from("/images/odd\tname\\.jpg"),
+            dest: PathBuf::from("/images/Class 1/odd\tname\\.jpg"),
+            size: 42,
+            modified: 1_718_000_000_000_000_000,
+            replaced: false
+        });
+        journal.add_entry(JournalEntry {
+            src: PathBuf::from("/images/b.jpg"),
+            dest: PathBuf::from("/images/Class 1/b.jpg"),
+            size: 7,
+            modified: 0,
+            replaced: true
+        });
+
+        let text = journal.serialize().unwrap();
+        let parsed = JournalModel::parse(&text).unwrap();
+
+        assert_eq!(parsed, journal);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_journal() {
+        assert!(JournalModel::parse("").is_none());
+        assert!(JournalModel::parse("not a journal\nrun\t1\nplacement\tmove\n").is_none());
+        assert!(JournalModel::parse("tensort-journal 1\nrun\t1\nplacement\tmove\nfile\tx\t0\ta\tb\n").is_none());
+    }
+
+    #[test]
+    fn parse_ignores_unfinished_record() {
+        let mut journal = JournalModel::new(String::from("1"), PlacementStrategy::Move);
+        let mut text = journal.serialize_header();
+
+        journal.add_created_dir(PathBuf::from("/images/Class 1"));
+        text.push_str(&JournalModel::serialize_created_dir(Path::new("/images/Class 1")).unwrap());
+
+        assert_eq!(JournalModel::parse(&text).unwrap(), journal);
+        assert_eq!(JournalModel::parse(&(text + "file\t4\t1")).unwrap(), journal);
+    }
+}
\ No newline at end of file
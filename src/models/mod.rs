@@ -1,2 +1,20 @@
 pub mod cnn_model;
-pub mod arguments_model;
\ No newline at end of file
+pub mod arguments_model;
+pub mod sort_plan_model;
+pub mod journal_model;
+pub mod traversal_model;
+pub mod cli_model;
+pub mod config_model;
+pub mod class_estimate_model;
+pub mod architecture_model;
+pub mod torchscript_model;
+pub mod feature_network_model;
+pub mod device_model;
+pub mod preprocessing_model;
+pub mod pipeline_model;
+pub mod augmentation_model;
+pub mod precision_model;
+pub mod embedding_cache_model;
+pub mod export_model;
+pub mod import_model;
+pub mod ann_index_model;
\ No newline at end of file
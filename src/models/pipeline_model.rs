@@ -0,0 +1,62 @@
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Number of images passed through the model at once, unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 16;
+
+/// Number of prepared images waiting for the model, unless configured otherwise.
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+
+/// Controls how images are decoded and fed to the model.
+///
+/// Worker threads decode and prepare images ahead of the model, which takes them from a bounded queue
+/// in the order the images were found, so the results do not depend on the number of workers.
+///
+/// # Fields
+///
+/// - `batch_size`: How many images are passed through the model at once.
+/// - `workers`: How many threads decode images. `0` decodes every batch on the thread running the model.
+/// - `queue_depth`: How many prepared images may wait for the model before the workers pause.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PipelineModel {
+    batch_size: usize,
+    workers: usize,
+    queue_depth: usize
+}
+
+impl Default for PipelineModel {
+    fn default() -> Self {
+        Self::new(DEFAULT_BATCH_SIZE, default_worker_count(), DEFAULT_QUEUE_DEPTH)
+    }
+}
+
+impl PipelineModel {
+
+    /// Creates a new set of pipeline options.
+    pub fn new(batch_size: usize, workers: usize, queue_depth: usize) -> Self {
+        Self {
+            batch_size,
+            workers,
+            queue_depth
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+}
+
+/// Returns the number of decoding threads used unless configured otherwise, one for each core.
+pub fn default_worker_count() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
\ No newline at end of file
@@ -0,0 +1,93 @@
+use tch::Kind;
+
+/// Number of images embedded at both precisions to measure how much reduced precision changes the embeddings.
+pub const DRIFT_SAMPLE_SIZE: usize = 16;
+
+/// The precision the network computes in.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Precision {
+    /// Single precision, the precision the weights are stored in.
+    #[default]
+    Float32,
+    /// IEEE half precision, fast on GPUs.
+    Float16,
+    /// Brain floating point, half precision with the range of `Float32`, fast on recent CPUs.
+    BFloat16,
+    /// Dynamic quantization of the classifier head to 8-bit integers, on CPUs only.
+    /// Everything else computes in `Float32`.
+    Int8
+}
+
+impl Precision {
+
+    /// Looks up a precision by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fp32" => Some(Self::Float32),
+            "fp16" => Some(Self::Float16),
+            "bf16" => Some(Self::BFloat16),
+            "int8" => Some(Self::Int8),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Float32 => "fp32",
+            Self::Float16 => "fp16",
+            Self::BFloat16 => "bf16",
+            Self::Int8 => "int8"
+        }
+    }
+
+    /// Describes what the precision applies to, since `Int8` only quantizes the classifier head.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Int8 => "int8 classifier head, fp32 convolutions",
+            _ => self.name()
+        }
+    }
+
+    /// Returns the tensor kind values are passed between layers in.
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Float32 | Self::Int8 => Kind::Float,
+            Self::Float16 => Kind::Half,
+            Self::BFloat16 => Kind::BFloat16
+        }
+    }
+}
+
+/// How much the embeddings of a sample of images changed when the network was converted to a reduced precision.
+///
+/// # Fields
+///
+/// - `precision`: The precision the network was converted to.
+/// - `sample_size`: The number of images compared.
+/// - `mean_cosine_similarity`: The mean cosine similarity between the embedding of each image at both precisions, `1` if they point the same way.
+/// - `max_difference`: The largest absolute difference between any value of the embeddings at both precisions.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PrecisionDrift {
+    pub precision: Precision,
+    pub sample_size: usize,
+    pub mean_cosine_similarity: f64,
+    pub max_difference: f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for precision in [Precision::Float32, Precision::Float16, Precision::BFloat16, Precision::Int8] {
+            assert_eq!(Precision::from_name(precision.name()), Some(precision));
+        }
+
+        assert_eq!(Precision::from_name("int4"), None);
+        assert_eq!(Precision::BFloat16.kind(), Kind::BFloat16);
+        assert_eq!(Precision::Int8.kind(), Kind::Float);
+        assert_eq!(Precision::Float16.description(), "fp16");
+        assert_ne!(Precision::Int8.description(), "int8");
+    }
+}
\ No newline at end of file
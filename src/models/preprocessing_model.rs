@@ -0,0 +1,242 @@
+use std::path::Path;
+use tch::{Kind, TchError, Tensor};
+use tch::vision::{image, imagenet};
+
+/// Image size used when neither the model nor the configuration gives one.
+pub const DEFAULT_INPUT_SIZE: i64 = 224;
+
+/// How an image is fitted into the square input of the network.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ResizeStrategy {
+    /// Resize until the image covers the input, then cut off whatever sticks out on either side.
+    #[default]
+    CenterCrop,
+    /// Resize until the whole image fits the input, then pad the remaining space with black.
+    Letterbox,
+    /// Resize the width and height independently, which distorts images that are not square.
+    Stretch
+}
+
+impl ResizeStrategy {
+
+    /// Looks up a resize strategy by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "crop" => Some(Self::CenterCrop),
+            "letterbox" => Some(Self::Letterbox),
+            "stretch" => Some(Self::Stretch),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CenterCrop => "crop",
+            Self::Letterbox => "letterbox",
+            Self::Stretch => "stretch"
+        }
+    }
+
+    /// Fits a `C x H x W` image of `0..255` values into a `C x size x size` image.
+    pub fn apply(&self, image: &Tensor, size: i64) -> Result<Tensor, TchError> {
+        match self {
+            Self::CenterCrop => image::resize_preserve_aspect_ratio(image, size, size),
+            Self::Stretch => image::resize(image, size, size),
+            Self::Letterbox => {
+                let (height, width) = (image.size()[1], image.size()[2]);
+                let scale = size as f64 / height.max(width) as f64;
+                let fitted_height = ((height as f64 * scale).round() as i64).clamp(1, size);
+                let fitted_width = ((width as f64 * scale).round() as i64).clamp(1, size);
+                let (top, left) = ((size - fitted_height) / 2, (size - fitted_width) / 2);
+
+                // Padding is given for the last dimension first
+                Ok(image::resize(image, fitted_width, fitted_height)?.constant_pad_nd([
+                    left,
+                    size - fitted_width - left,
+                    top,
+                    size - fitted_height - top
+                ]))
+            }
+        }
+    }
+}
+
+/// How pixel values are scaled before images are passed to the network.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Normalization {
+    /// Scale to `0..1`, then subtract the ImageNet mean and divide by the ImageNet standard deviation.
+    #[default]
+    ImageNet,
+    /// Scale to `0..1`.
+    ZeroOne,
+    /// Keep the raw `0..255` values.
+    Raw,
+    /// Scale to `0..1`, then subtract the given mean and divide by the given standard deviation of each RGB channel.
+    MeanStd {
+        mean: [f64; 3],
+        std: [f64; 3]
+    }
+}
+
+impl Normalization {
+
+    /// Looks up a normalization by its command line name. Normalizations with a custom mean and standard deviation have no name to look up.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "imagenet" => Some(Self::ImageNet),
+            "zero-one" => Some(Self::ZeroOne),
+            "raw" => Some(Self::Raw),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ImageNet => "imagenet",
+            Self::ZeroOne => "zero-one",
+            Self::Raw => "raw",
+            Self::MeanStd { .. } => "mean-std"
+        }
+    }
+
+    /// Applies the normalization to a `C x H x W` image of `0..255` values.
+    pub fn apply(&self, image: &Tensor) -> Result<Tensor, TchError> {
+        match self {
+            Self::ImageNet => imagenet::normalize(image),
+            Self::ZeroOne => Ok(image.to_kind(Kind::Float) / 255.0),
+            Self::Raw => Ok(image.to_kind(Kind::Float)),
+            Self::MeanStd { mean, std } => {
+                let mean = Tensor::from_slice(mean).to_kind(Kind::Float).view([3, 1, 1]);
+                let std = Tensor::from_slice(std).to_kind(Kind::Float).view([3, 1, 1]);
+
+                Ok((image.to_kind(Kind::Float) / 255.0 - mean) / std)
+            }
+        }
+    }
+}
+
+/// Parses one value for each RGB channel, separated by commas, such as `0.5,0.5,0.5`.
+pub fn parse_channel_values(values: &str) -> Option<[f64; 3]> {
+    let values: Vec<f64> = values
+        .split(',')
+        .map(|value| { value.trim().parse::<f64>().ok().filter(|value| { value.is_finite() }) })
+        .collect::<Option<_>>()?;
+
+    values.try_into().ok()
+}
+
+/// Controls how an image file is turned into an input of the network.
+///
+/// It holds no tensors, so images can be prepared on other threads than the one running the network.
+///
+/// # Fields
+///
+/// - `resize`: How images that are not square are fitted into the input.
+/// - `input_size`: The width and height of the input.
+/// - `normalization`: How pixel values are scaled.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PreprocessingModel {
+    resize: ResizeStrategy,
+    input_size: i64,
+    normalization: Normalization
+}
+
+impl PreprocessingModel {
+
+    /// Creates a new set of preprocessing options.
+    pub fn new(resize: ResizeStrategy, input_size: i64, normalization: Normalization) -> Self {
+        Self {
+            resize,
+            input_size,
+            normalization
+        }
+    }
+
+    pub fn resize(&self) -> ResizeStrategy {
+        self.resize
+    }
+
+    pub fn input_size(&self) -> i64 {
+        self.input_size
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    /// Reads an image and prepares it as an input of the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `image_path` - The path to the image file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the prepared `C x H x W` image tensor, or an error if the image cannot be read.
+    pub fn load_image(&self, image_path: &Path) -> Result<Tensor, TchError> {
+        let image = self.resize.apply(&image::load(image_path)?, self.input_size)?;
+
+        self.normalization.apply(&image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tch::Device;
+    use tempfile::tempdir;
+    use super::*;
+
+    #[test]
+    fn load_image_resizes_and_normalizes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("image.png");
+
+        image::save(&Tensor::full([3, 8, 8], 51, (Kind::Uint8, Device::Cpu)), &path).unwrap();
+
+        let image = PreprocessingModel::new(ResizeStrategy::CenterCrop, 4, Normalization::ZeroOne).load_image(&path).unwrap();
+
+        assert_eq!(image.size(), [3, 4, 4]);
+        assert!((image.double_value(&[0, 0, 0]) - 0.2).abs() < 1e-6);
+
+        let normalization = Normalization::MeanStd { mean: [0.2, 0.1, 0.0], std: [0.5, 0.5, 0.1] };
+        let image = PreprocessingModel::new(ResizeStrategy::Stretch, 4, normalization).load_image(&path).unwrap();
+
+        assert!(image.double_value(&[0, 0, 0]).abs() < 1e-6);
+        assert!((image.double_value(&[1, 0, 0]) - 0.2).abs() < 1e-6);
+        assert!((image.double_value(&[2, 0, 0]) - 2.0).abs() < 1e-5);
+
+        // Files that are not images fail to load
+        fs::write(&path, [69u8, 23]).unwrap();
+        assert!(PreprocessingModel::new(ResizeStrategy::CenterCrop, 4, Normalization::Raw).load_image(&path).is_err());
+    }
+
+    #[test]
+    fn resize_strategies_keep_or_change_aspect_ratio() {
+        let image = Tensor::full([3, 4, 8], 200, (Kind::Uint8, Device::Cpu));
+
+        // A wide image is cropped at the sides, stretched, or padded above and below
+        let cropped = ResizeStrategy::CenterCrop.apply(&image, 4).unwrap();
+        let stretched = ResizeStrategy::Stretch.apply(&image, 4).unwrap();
+        let letterboxed = ResizeStrategy::Letterbox.apply(&image, 4).unwrap();
+
+        assert_eq!(cropped.size(), [3, 4, 4]);
+        assert_eq!(stretched.size(), [3, 4, 4]);
+        assert_eq!(letterboxed.size(), [3, 4, 4]);
+        assert_eq!(cropped.int64_value(&[0, 0, 0]), 200);
+        assert_eq!(letterboxed.int64_value(&[0, 0, 0]), 0);
+        assert_eq!(letterboxed.int64_value(&[0, 1, 0]), 200);
+        assert_eq!(letterboxed.int64_value(&[0, 3, 3]), 0);
+    }
+
+    #[test]
+    fn names_and_channel_values_parse() {
+        for resize in [ResizeStrategy::CenterCrop, ResizeStrategy::Letterbox, ResizeStrategy::Stretch] {
+            assert_eq!(ResizeStrategy::from_name(resize.name()), Some(resize));
+        }
+
+        assert_eq!(parse_channel_values("0.5, 0.25,1"), Some([0.5, 0.25, 1.0]));
+        assert_eq!(parse_channel_values("0.5,0.5"), None);
+        assert_eq!(parse_channel_values("0.5,0.5,nan"), None);
+    }
+}
\ No newline at end of file
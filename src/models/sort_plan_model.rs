@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+
+/// Determines how a file is placed into its class directory.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum PlacementStrategy {
+    #[default]
+    Move,
+    Copy,
+    Symlink,
+    Hardlink
+}
+
+impl PlacementStrategy {
+
+    /// Parses a placement strategy from its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "move" => Some(Self::Move),
+            "copy" => Some(Self::Copy),
+            "symlink" => Some(Self::Symlink),
+            "hardlink" => Some(Self::Hardlink),
+            _ => None
+        }
+    }
+
+    /// Returns the command line name of this placement strategy.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Move => "move",
+            Self::Copy => "copy",
+            Self::Symlink => "symlink",
+            Self::Hardlink => "hardlink"
+        }
+    }
+}
+
+/// Determines what happens when a class directory already exists.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum DirConflictPolicy {
+    /// Place files into the existing directory.
+    #[default]
+    Merge,
+    /// Create a new directory with a numbered suffix instead.
+    Suffix
+}
+
+impl DirConflictPolicy {
+
+    /// Parses a directory conflict policy from its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "merge" => Some(Self::Merge),
+            "suffix" => Some(Self::Suffix),
+            _ => None
+        }
+    }
+
+    /// Returns the command line name of this directory conflict policy.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Merge => "merge",
+            Self::Suffix => "suffix"
+        }
+    }
+}
+
+/// Determines what happens when a destination file already exists.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum FileConflictPolicy {
+    /// Leave the file where it is.
+    Skip,
+    /// Place the file under a new name with a numbered suffix.
+    #[default]
+    Rename,
+    /// Overwrite the destination, but only if both files have identical contents.
+    Identical
+}
+
+impl FileConflictPolicy {
+
+    /// Parses a file conflict policy from its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "skip" => Some(Self::Skip),
+            "rename" => Some(Self::Rename),
+            "identical" => Some(Self::Identical),
+            _ => None
+        }
+    }
+
+    /// Returns the command line name of this file conflict policy.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Skip => "skip",
+            Self::Rename => "rename",
+            Self::Identical => "identical"
+        }
+    }
+}
+
+/// Groups the policies used to resolve conflicts with existing directories and files.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct ConflictPolicyModel {
+    dirs: DirConflictPolicy,
+    files: FileConflictPolicy
+}
+
+impl ConflictPolicyModel {
+
+    /// Creates a new set of conflict policies.
+    pub fn new(dirs: DirConflictPolicy, files: FileConflictPolicy) -> Self {
+        Self {
+            dirs,
+            files
+        }
+    }
+
+    pub fn dirs(&self) -> DirConflictPolicy {
+        self.dirs
+    }
+
+    pub fn files(&self) -> FileConflictPolicy {
+        self.files
+    }
+}
+
+/// Represents a single file relocation derived from the sorting results.
+///
+/// If `replace` is set, `dest` already exists with the same contents as `src` and is overwritten.
+#[derive(PartialEq, Debug, Clone)]
+pub struct PlannedMove {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+    pub replace: bool
+}
+
+/// Describes how applying a plan ended.
+#[derive(PartialEq, Debug)]
+pub enum SortOutcome {
+    /// Every step was applied.
+    Committed { placed: usize },
+    /// A step failed and every change that was already applied was reverted.
+    RolledBack { cause: String },
+    /// A step failed and reverting the changes that were already applied failed as well.
+    Failed { cause: String, rollback_cause: String }
+}
+
+impl SortOutcome {
+
+    /// Returns `true` if every step of the plan was applied.
+    pub fn is_committed(&self) -> bool {
+        matches!(self, Self::Committed { .. })
+    }
+}
+
+/// Describes every change that sorting will make to the filesystem.
+///
+/// A plan is computed entirely in memory so it can be inspected (for example during a dry run)
+/// before any directory is created or any file is moved.
+#[derive(PartialEq, Debug, Default)]
+pub struct SortPlanModel {
+    class_dirs: Vec<PathBuf>,
+    moves: Vec<PlannedMove>,
+    skipped: Vec<PathBuf>
+}
+
+impl SortPlanModel {
+
+    /// Creates an empty plan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a class directory that needs to be created.
+    pub fn add_class_dir(&mut self, class_dir: PathBuf) {
+        self.class_dirs.push(class_dir);
+    }
+
+    /// Records a file that needs to be moved from `src` to `dest`, replacing an identical file if `replace` is set.
+    pub fn add_move(&mut self, src: PathBuf, dest: PathBuf, replace: bool) {
+        self.moves.push(PlannedMove { src, dest, replace });
+    }
+
+    /// Records a file that is left in place because its destination is already taken.
+    pub fn add_skipped(&mut self, src: PathBuf) {
+        self.skipped.push(src);
+    }
+
+    pub fn class_dirs(&self) -> &[PathBuf] {
+        &self.class_dirs
+    }
+
+    pub fn moves(&self) -> &[PlannedMove] {
+        &self.moves
+    }
+
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DirConflictPolicy, FileConflictPolicy, PlacementStrategy};
+
+    #[test]
+    fn placement_strategy_names_round_trip() {
+        for strategy in [PlacementStrategy::Move, PlacementStrategy::Copy, PlacementStrategy::Symlink, PlacementStrategy::Hardlink] {
+            assert_eq!(PlacementStrategy::from_name(strategy.name()), Some(strategy));
+        }
+
+        assert_eq!(PlacementStrategy::from_name("COPY"), Some(PlacementStrategy::Copy));
+        assert_eq!(PlacementStrategy::from_name("teleport"), None);
+    }
+
+    #[test]
+    fn conflict_policy_names_round_trip() {
+        for policy in [DirConflictPolicy::Merge, DirConflictPolicy::Suffix] {
+            assert_eq!(DirConflictPolicy::from_name(policy.name()), Some(policy));
+        }
+
+        for policy in [FileConflictPolicy::Skip, FileConflictPolicy::Rename, FileConflictPolicy::Identical] {
+            assert_eq!(FileConflictPolicy::from_name(policy.name()), Some(policy));
+        }
+
+        assert_eq!(DirConflictPolicy::from_name("skip"), None);
+        assert_eq!(FileConflictPolicy::from_name("merge"), None);
+    }
+}
\ No newline at end of file
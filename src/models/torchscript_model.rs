@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+use tch::{CModule, Device, IValue, Kind, TchError, Tensor};
+use crate::models::cnn_model::Embeddable;
+use crate::models::preprocessing_model::PreprocessingModel;
+
+/// How the output of a TorchScript model is turned into an embedding.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum OutputActivation {
+    /// Use the output as is.
+    #[default]
+    None,
+    /// Apply a softmax, for models that output class logits.
+    Softmax,
+    /// Scale the output to unit length.
+    L2
+}
+
+impl OutputActivation {
+
+    /// Looks up an output activation by its command line name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "softmax" => Some(Self::Softmax),
+            "l2" => Some(Self::L2),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Softmax => "softmax",
+            Self::L2 => "l2"
+        }
+    }
+
+    /// Applies the activation to each row of a batch of outputs.
+    pub fn apply(&self, output: &Tensor) -> Result<Tensor, TchError> {
+        match self {
+            Self::None => Ok(output.shallow_clone()),
+            Self::Softmax => output.f_softmax(-1, Kind::Float),
+            Self::L2 => output.f_div(&output.f_norm_scalaropt_dim(2, [-1], true)?.f_clamp_min(1e-12)?)
+        }
+    }
+}
+
+/// Generates embeddings with an exported TorchScript (`.pt`) model.
+///
+/// The model is called with a batch of `N x 3 x input_size x input_size` images, prepared as configured in its `PreprocessingModel`. If it returns
+/// several values (a tuple or list), the first tensor is used. Each output is flattened before the activation is applied.
+pub struct TorchScriptModel {
+    device: Device,
+    module: CModule,
+    path: PathBuf,
+    preprocessing: PreprocessingModel,
+    activation: OutputActivation
+}
+
+impl TorchScriptModel {
+
+    /// Loads a TorchScript model.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the TorchScript file.
+    /// * `preprocessing` - How images are prepared for the model.
+    /// * `activation` - How the output is turned into an embedding.
+    /// * `device` - The device the model runs on.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the loaded TorchScriptModel instance, or an error if the file is not a valid TorchScript module.
+    pub fn new(path: &Path, preprocessing: PreprocessingModel, activation: OutputActivation, device: Device) -> Result<Self, TchError> {
+        let mut module = CModule::load_on_device(path, device)?;
+
+        module.set_eval();
+
+        Ok(Self {
+            device,
+            module,
+            path: path.to_path_buf(),
+            preprocessing,
+            activation
+        })
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn activation(&self) -> OutputActivation {
+        self.activation
+    }
+
+    /// Returns the number of CPU threads used within each operation.
+    pub fn thread_count(&self) -> i32 {
+        tch::get_num_threads()
+    }
+}
+
+/// Finds the first tensor in the value returned by a TorchScript model.
+fn first_tensor(output: IValue) -> Option<Tensor> {
+    match output {
+        IValue::Tensor(tensor) => Some(tensor),
+        IValue::TensorList(tensors) => tensors.into_iter().next(),
+        IValue::Tuple(values) | IValue::GenericList(values) => values
+            .into_iter()
+            .find_map(first_tensor),
+        _ => None
+    }
+}
+
+impl Embeddable for TorchScriptModel {
+    fn preprocessing(&self) -> PreprocessingModel {
+        self.preprocessing
+    }
+
+    fn identity(&self) -> String {
+        format!("torchscript {:?} {:?}", self.activation, self.preprocessing)
+    }
+
+    fn embed_images(&self, images: &Tensor) -> Result<Tensor, TchError> {
+        tch::no_grad(|| {
+            let output = self.module.forward_is(&[IValue::Tensor(images.f_to_device(self.device)?)])?;
+            let output = first_tensor(output)
+                .ok_or_else(|| { TchError::Kind(String::from("TorchScript model did not return a tensor")) })?
+                .f_flatten(1, -1)?
+                .f_to_kind(Kind::Float)?;
+
+            self.activation.apply(&output)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::tempdir;
+    use crate::models::preprocessing_model::{Normalization, ResizeStrategy, DEFAULT_INPUT_SIZE};
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for normalization in [Normalization::ImageNet, Normalization::ZeroOne, Normalization::Raw] {
+            assert_eq!(Normalization::from_name(normalization.name()), Some(normalization));
+        }
+
+        for activation in [OutputActivation::None, OutputActivation::Softmax, OutputActivation::L2] {
+            assert_eq!(OutputActivation::from_name(activation.name()), Some(activation));
+        }
+
+        assert_eq!(Normalization::from_name("imagenet21k"), None);
+    }
+
+    #[test]
+    fn l2_activation_scales_to_unit_length() {
+        let output = Tensor::from_slice(&[3.0f32, 4.0, 0.0, 2.0]).view([2, 2]);
+        let embeddings = OutputActivation::L2.apply(&output).unwrap();
+
+        assert!((embeddings.get(0).norm().double_value(&[]) - 1.0).abs() < 1e-6);
+        assert!((embeddings.get(1).norm().double_value(&[]) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn invalid_file_returns_error() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model.pt");
+
+        fs::write(&path, [69u8, 23]).unwrap();
+
+        let preprocessing = PreprocessingModel::new(ResizeStrategy::CenterCrop, DEFAULT_INPUT_SIZE, Normalization::ImageNet);
+        assert!(TorchScriptModel::new(&path, preprocessing, OutputActivation::None, Device::Cpu).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,42 @@
+/// Controls how the target directory is searched for images.
+///
+/// # Fields
+///
+/// - `max_depth`: How many levels of subdirectories are searched. `0` only searches the target directory itself.
+/// - `follow_symlinks`: Whether symbolic links to directories are descended into. Directories are never visited twice.
+/// - `include_hidden`: Whether files and directories whose names start with a `.` are included.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct TraversalModel {
+    max_depth: usize,
+    follow_symlinks: bool,
+    include_hidden: bool
+}
+
+impl TraversalModel {
+
+    /// Creates a new set of traversal options.
+    pub fn new(max_depth: usize, follow_symlinks: bool, include_hidden: bool) -> Self {
+        Self {
+            max_depth,
+            follow_symlinks,
+            include_hidden
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn follow_symlinks(&self) -> bool {
+        self.follow_symlinks
+    }
+
+    pub fn include_hidden(&self) -> bool {
+        self.include_hidden
+    }
+
+    /// Returns `true` if subdirectories are searched at all.
+    pub fn is_recursive(&self) -> bool {
+        self.max_depth > 0
+    }
+}
\ No newline at end of file
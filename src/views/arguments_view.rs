@@ -1,19 +1,119 @@
 use std::fmt::{Display, Formatter, Result};
+use crate::models::ann_index_model::SimilarityMode;
 use crate::models::arguments_model::ArgumentsModel;
+use crate::models::cli_model::Command;
+use crate::models::config_model::ConfigSource;
+
+/// Describes where the settings behind a single line came from, leaving out
+/// defaults unless every setting was left at its default.
+fn describe_sources(args: &ArgumentsModel, keys: &[&str]) -> String {
+    let mut sources: Vec<String> = vec![];
+
+    for key in keys {
+        let source = args.source(key);
+
+        if *source != ConfigSource::Default && !sources.contains(&source.to_string()) {
+            sources.push(source.to_string());
+        }
+    }
+
+    if sources.is_empty() {
+        return ConfigSource::Default.to_string();
+    }
+
+    sources.join(", ")
+}
 
 impl Display for ArgumentsModel
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(
-            f,
-            "Running tensort with options:\n\
-            <target_dir>        : {}\n\
-            <class_count>       : {}\n\
-            <no_class_names>    : {}
-            ",
-            self.target_dir().to_str().unwrap(),
-            self.class_count(),
-            self.should_not_gen_names()
-        )
+        writeln!(f, "Running tensort {} with options:", self.command().spec().name)?;
+        writeln!(f, "<target_dir>        : {}", self.target_dir().display())?;
+
+        // Only show the options that apply to the command being run
+        if self.command() == Command::Sort {
+            writeln!(f, "<output_dir>        : {} ({})", self.output_dir().display(), describe_sources(self, &["output"]))?;
+        }
+
+        // Imported embeddings replace the model and everything that feeds images into it
+        if let Some(import) = self.import() {
+            let image_list = import.image_list()
+                .map(|image_list| { image_list.display().to_string() })
+                .unwrap_or(String::from("stored with the embeddings"));
+
+            writeln!(f, "<import>            : {} ({}), image paths {}", import.path().display(), import.format().name(), image_list)?;
+        }
+        else {
+            // A TorchScript model replaces the built-in architecture and its weights
+            if let Some(torchscript) = self.torchscript() {
+                writeln!(f, "<model>             : TorchScript {} ({})", torchscript.display(), describe_sources(self, &["torchscript"]))?;
+                writeln!(f, "<activation>        : {} ({})", self.activation().name(), describe_sources(self, &["activation"]))?;
+            }
+            else {
+                writeln!(f, "<model>             : {} ({})", self.architecture().name, describe_sources(self, &["model"]))?;
+
+                match self.weights() {
+                    Some(weights) => writeln!(f, "<weights>           : {} ({})", weights.display(), describe_sources(self, &["weights"]))?,
+                    None => writeln!(f, "<weights>           : default locations")?
+                }
+
+                writeln!(f, "<embedding>         : {} ({})", self.embedding(), describe_sources(self, &["embedding", "layer", "pooling"]))?;
+                writeln!(f, "<augmentation>      : {} ({})", self.augmentation(), describe_sources(self, &["augment", "augment-reduce"]))?;
+                writeln!(f, "<precision>         : {} ({})", self.precision().description(), describe_sources(self, &["precision"]))?;
+            }
+
+            writeln!(f, "<preprocessing>     : {} ({})", self.preprocessing(), describe_sources(self, &["resize", "input-size", "normalization", "mean", "std"]))?;
+
+            let threads = self.threads().map(|threads| { threads.to_string() }).unwrap_or(String::from("every core"));
+            writeln!(f, "<device>            : {}, {} ({})", self.device().name(), threads, describe_sources(self, &["device", "threads"]))?;
+            writeln!(f, "<batch_size>        : {} ({})", self.pipeline().batch_size(), describe_sources(self, &["batch-size"]))?;
+            writeln!(f, "<pipeline>          : {} workers, {} queued images ({})", self.pipeline().workers(), self.pipeline().queue_depth(), describe_sources(self, &["workers", "queue-depth"]))?;
+
+            // A dry run reads the cache without storing anything in it
+            let cache = match (self.use_cache(), self.stores_cache()) {
+                (true, true) => "enabled",
+                (true, false) => "read only",
+                (false, _) => "disabled"
+            };
+
+            writeln!(f, "<cache>             : {} ({})", cache, describe_sources(self, &["no-cache"]))?;
+        }
+
+        if self.command() == Command::Embed {
+            match self.export() {
+                Some(export) => writeln!(f, "<export>            : {} ({})", export.path().display(), export.format().name())?,
+                None => writeln!(f, "<export>            : none")?
+            }
+        }
+
+        if self.command() != Command::Embed {
+            match self.class_count() {
+                Some(class_count) => writeln!(f, "<class_count>       : {} ({})", class_count, describe_sources(self, &["class-count"]))?,
+                None => writeln!(f, "<class_count>       : estimated, up to {} ({})", self.max_class_count(), describe_sources(self, &["max-classes"]))?
+            }
+
+            match self.similarity() {
+                SimilarityMode::Exact => writeln!(f, "<similarity>        : exact ({})", describe_sources(self, &["similarity"]))?,
+                similarity => writeln!(f, "<similarity>        : {}, {} neighbours ({})", similarity.name(), self.neighbour_count(), describe_sources(self, &["similarity", "neighbours"]))?
+            }
+        }
+
+        if self.command() == Command::Sort {
+            writeln!(f, "<no_class_names>    : {} ({})", self.should_not_gen_names(), describe_sources(self, &["no-names"]))?;
+            writeln!(f, "<dry_run>           : {}", self.dry_run())?;
+            writeln!(f, "<placement>         : {} ({})", self.placement(), describe_sources(self, &["placement"]))?;
+            writeln!(f, "<conflicts>         : {} ({})", self.conflicts(), describe_sources(self, &["dir-conflict", "file-conflict"]))?;
+        }
+
+        if self.import().is_none() {
+            writeln!(
+                f,
+                "<traversal>         : {} ({})",
+                self.traversal(),
+                describe_sources(self, &["recursive", "max-depth", "follow-symlinks", "hidden"])
+            )?;
+        }
+
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+use std::fmt::{Display, Formatter, Result};
+use crate::models::embedding_cache_model::EmbeddingCacheModel;
+
+impl Display for EmbeddingCacheModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "Embedding cache: {} hits, {} misses ({} embeddings cached for this model)",
+            self.hits(),
+            self.misses(),
+            self.entry_count()
+        )
+    }
+}
\ No newline at end of file
@@ -0,0 +1,25 @@
+use std::fmt::{Display, Formatter, Result};
+use crate::models::class_estimate_model::ClassEstimateModel;
+
+impl Display for ClassEstimateModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.scores().is_empty() {
+            return writeln!(f, "Too few images to compare class counts, using {} class(es).", self.chosen());
+        }
+
+        writeln!(f, "Silhouette score for each class count:")?;
+
+        for (class_count, score) in self.scores() {
+            writeln!(
+                f,
+                "\tk = {:<3} : {:>6.3}{}",
+                class_count,
+                score,
+                if *class_count == self.chosen() { " <= chosen" } else { "" }
+            )?;
+        }
+
+        writeln!(f, "Estimated class count: {}", self.chosen())
+    }
+}
\ No newline at end of file
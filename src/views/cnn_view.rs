@@ -1,13 +1,155 @@
 use std::fmt::{Display, Formatter, Result};
-use crate::models::cnn_model::CNNModel;
+use crate::errors::{DeviceUnavailableError, PrecisionError, WeightsError};
+use crate::models::augmentation_model::AugmentationModel;
+use crate::models::cnn_model::{CNNModel, EmbeddingMode, WeightsSource};
+use crate::models::precision_model::PrecisionDrift;
+use crate::models::preprocessing_model::{Normalization, PreprocessingModel};
+use crate::models::torchscript_model::TorchScriptModel;
 
 impl Display for CNNModel
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
             f,
-            "Neural network running on device: {:?}",
-            self.device()
+            "Neural network {} ({}, {}, {}) running on device: {:?} with {} CPU threads",
+            self.architecture().name,
+            self.mode(),
+            self.augmentation(),
+            self.precision().description(),
+            self.device(),
+            self.thread_count()
         )
     }
+}
+
+impl Display for EmbeddingMode
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Probabilities => write!(f, "class probabilities"),
+            Self::Features { layer, pooling } => write!(f, "features of {}, {} pooled", layer.unwrap_or("the last layer"), pooling.name())
+        }
+    }
+}
+
+impl Display for AugmentationModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if !self.is_enabled() {
+            return write!(f, "no augmentation");
+        }
+
+        let views: Vec<&str> = self.views()
+            .iter()
+            .map(|view| { view.name() })
+            .collect();
+
+        write!(f, "{} of {} views ({})", self.reduction().name(), self.view_count(), views.join(", "))
+    }
+}
+
+impl Display for PreprocessingModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{} to {}px, ", self.resize().name(), self.input_size())?;
+
+        match self.normalization() {
+            Normalization::MeanStd { mean, std } => write!(f, "mean {:?} and std {:?} normalization", mean, std),
+            normalization => write!(f, "{} normalization", normalization.name())
+        }
+    }
+}
+
+impl Display for TorchScriptModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "TorchScript model {} ({} activation) running on device: {:?} with {} CPU threads",
+            self.path().display(),
+            self.activation().name(),
+            self.device(),
+            self.thread_count()
+        )
+    }
+}
+
+impl Display for WeightsError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::NotFound { architecture, searched } => {
+                writeln!(f, "No {} weights found, looked in:", architecture)?;
+
+                for path in searched {
+                    writeln!(f, "\t=> {}", path.display())?;
+                }
+
+                write!(f, "Pass --weights <path> or set weights in a config file. The default resnet34 weights can also be embedded with the embedded-weights feature.")
+            },
+            Self::Missing(path) => write!(f, "Model weights file {} does not exist", path.display()),
+            Self::Invalid { origin, architecture, cause } => write!(
+                f,
+                "Could not load model weights from {}, they are not a valid weights file or do not match the {} architecture.\n\
+                Cause: {}",
+                origin,
+                architecture,
+                cause
+            ),
+            Self::InvalidTorchScript { path, cause } => write!(
+                f,
+                "Could not load TorchScript model {}, it is not a valid TorchScript file.\n\
+                Cause: {}",
+                path.display(),
+                cause
+            )
+        }
+    }
+}
+
+impl Display for DeviceUnavailableError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.cuda_device_count {
+            0 => write!(f, "Device {} is not available, no CUDA devices were found. Use --device cpu instead", self.selection.name()),
+            count => write!(f, "Device {} is not available, only cuda:0 to cuda:{} were found", self.selection.name(), count - 1)
+        }
+    }
+}
+
+impl Display for PrecisionDrift
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "Embeddings of {} sample images at {} compared to fp32: mean cosine similarity {:.5}, largest difference {:.5}",
+            self.sample_size,
+            self.precision.name(),
+            self.mean_cosine_similarity,
+            self.max_difference
+        )
+    }
+}
+
+impl Display for PrecisionError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "The network cannot run at {} precision on this device. Use --precision fp32 instead.\n\
+            Cause: {}",
+            self.precision.name(),
+            self.cause
+        )
+    }
+}
+
+impl Display for WeightsSource
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Embedded => write!(f, "weights embedded in the binary")
+        }
+    }
 }
\ No newline at end of file
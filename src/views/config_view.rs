@@ -0,0 +1,14 @@
+use std::fmt::{Display, Formatter, Result};
+use crate::models::config_model::ConfigSource;
+
+impl Display for ConfigSource
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Environment(name) => write!(f, "{}", name),
+            Self::CommandLine => write!(f, "command line")
+        }
+    }
+}
\ No newline at end of file
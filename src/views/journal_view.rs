@@ -0,0 +1,18 @@
+use std::fmt::{Display, Formatter, Result};
+use crate::models::journal_model::JournalModel;
+
+impl Display for JournalModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "Run {} ({}):\n\
+            \t=> {} files placed\n\
+            \t=> {} directories created",
+            self.run_id(),
+            self.placement(),
+            self.entries().len(),
+            self.created_dirs().len()
+        )
+    }
+}
\ No newline at end of file
@@ -1,4 +1,10 @@
 pub mod usage_view;
 pub mod arguments_view;
 pub mod cnn_view;
-pub mod results_view;
\ No newline at end of file
+pub mod results_view;
+pub mod sort_plan_view;
+pub mod journal_view;
+pub mod traversal_view;
+pub mod config_view;
+pub mod class_estimate_view;
+pub mod cache_view;
\ No newline at end of file
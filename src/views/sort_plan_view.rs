@@ -0,0 +1,90 @@
+use std::fmt::{Display, Formatter, Result};
+use crate::models::sort_plan_model::{ConflictPolicyModel, DirConflictPolicy, FileConflictPolicy, PlacementStrategy, SortOutcome, SortPlanModel};
+
+impl Display for PlacementStrategy
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for DirConflictPolicy
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for FileConflictPolicy
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for ConflictPolicyModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{} directories, {} files", self.dirs(), self.files())
+    }
+}
+
+impl Display for SortOutcome
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Committed { placed } => write!(f, "Sort committed, {} files placed.", placed),
+            Self::RolledBack { cause } => write!(f, "Sort rolled back, no changes were kept.\nCause: {}", cause),
+            Self::Failed { cause, rollback_cause } => write!(
+                f,
+                "Sort failed and could not be rolled back, the directory may be partially sorted.\n\
+                Cause: {}\n\
+                Rollback error: {}\n\
+                The applied changes were recorded and can be undone with the undo command.",
+                cause,
+                rollback_cause
+            )
+        }
+    }
+}
+
+impl Display for SortPlanModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        writeln!(f, "Planned directories:")?;
+
+        if self.class_dirs().is_empty() {
+            writeln!(f, "\t=> No directories to create")?;
+        }
+
+        for class_dir in self.class_dirs() {
+            writeln!(f, "\t+ {}", class_dir.display())?;
+        }
+
+        writeln!(f, "\nPlanned moves:")?;
+
+        if self.moves().is_empty() {
+            writeln!(f, "\t=> No files to move")?;
+        }
+
+        for planned_move in self.moves() {
+            writeln!(
+                f,
+                "\t{} => {}{}",
+                planned_move.src.display(),
+                planned_move.dest.display(),
+                if planned_move.replace { " (replaces identical file)" } else { "" }
+            )?;
+        }
+
+        if !self.skipped().is_empty() {
+            writeln!(f, "\nSkipped because the destination already exists:")?;
+
+            for path in self.skipped() {
+                writeln!(f, "\t=> {}", path.display())?;
+            }
+        }
+
+        Ok(())
+    }
+}
\ No newline at end of file
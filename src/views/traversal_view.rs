@@ -0,0 +1,23 @@
+use std::fmt::{Display, Formatter, Result};
+use crate::models::traversal_model::TraversalModel;
+
+impl Display for TraversalModel
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.max_depth() {
+            _ if !self.is_recursive() => write!(f, "top level only")?,
+            usize::MAX => write!(f, "recursive")?,
+            max_depth => write!(f, "recursive (max depth {})", max_depth)?
+        }
+
+        if self.follow_symlinks() {
+            write!(f, ", following symlinks")?;
+        }
+
+        if self.include_hidden() {
+            write!(f, ", including hidden files")?;
+        }
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -1,22 +1,116 @@
 use std::fmt::{Display, Formatter, Result};
 use crate::errors::InvalidUsageError;
+use crate::models::architecture_model::ARCHITECTURES;
+use crate::models::cli_model::{Command, CommandSpec, OptionSpec, COMMANDS, HELP_OPTION, VERSION_OPTION};
 
-impl Display for InvalidUsageError {
+/// Usage text generated from the command definitions in `cli_model`.
+struct UsageFormatter(Option<Command>);
+
+/// Formats the usage of a single command, or the overall usage if no command is given.
+pub fn format_usage(command: Option<Command>) -> impl Display {
+    UsageFormatter(command)
+}
+
+/// Formats the left hand column of an option, such as `-o, --output <dir>`.
+fn format_option(option: &OptionSpec) -> String {
+    let short = option.short
+        .map(|short| { format!("-{}, ", short) })
+        .unwrap_or_default();
+
+    let value = option.value_name
+        .map(|value_name| { format!(" <{}>", value_name) })
+        .unwrap_or_default();
+
+    format!("{}--{}{}", short, option.long, value)
+}
+
+/// Writes two aligned columns under a heading.
+fn write_section(f: &mut Formatter<'_>, heading: &str, rows: &[(String, &str)]) -> Result {
+    let width = rows
+        .iter()
+        .map(|(left, _)| { left.len() })
+        .max()
+        .unwrap_or(0);
+
+    writeln!(f, "\n{}:", heading)?;
+
+    for (left, right) in rows {
+        writeln!(f, "  {:width$}  {}", left, right, width = width)?;
+    }
+
+    Ok(())
+}
+
+fn write_command_usage(f: &mut Formatter<'_>, spec: &CommandSpec) -> Result {
+    let positionals: Vec<String> = spec.positionals
+        .iter()
+        .map(|positional| {
+            if positional.required { format!("<{}>", positional.name) } else { format!("[{}]", positional.name) }
+        })
+        .collect();
+
+    writeln!(f, "{}\n", spec.about)?;
+    writeln!(f, "Usage: tensort {} {} [options]", spec.name, positionals.join(" "))?;
+
+    let positional_rows: Vec<(String, &str)> = spec.positionals
+        .iter()
+        .map(|positional| { (format!("<{}>", positional.name), positional.description) })
+        .collect();
+
+    let option_rows: Vec<(String, &str)> = spec.options
+        .iter()
+        .map(|option| { (format_option(option), option.description) })
+        .collect();
+
+    write_section(f, "Arguments", &positional_rows)?;
+    write_section(f, "Options", &option_rows)?;
+
+    // List the selectable architectures for commands that embed images
+    if spec.options.iter().any(|option| { option.long == "model" }) {
+        let architecture_rows: Vec<(String, &str)> = ARCHITECTURES
+            .iter()
+            .map(|architecture| { (format!("{} ({}px)", architecture.name, architecture.input_size), architecture.description) })
+            .collect();
+
+        write_section(f, "Models", &architecture_rows)?;
+    }
+
+    Ok(())
+}
+
+fn write_global_usage(f: &mut Formatter<'_>) -> Result {
+    writeln!(f, "Usage: tensort <command> [arguments] [options]")?;
+    writeln!(f, "       tensort <target_dir> <class_count> [options]  (same as tensort sort)")?;
+
+    let command_rows: Vec<(String, &str)> = COMMANDS
+        .iter()
+        .map(|spec| { (String::from(spec.name), spec.about) })
+        .collect();
+
+    let option_rows: Vec<(String, &str)> = [HELP_OPTION, VERSION_OPTION]
+        .iter()
+        .map(|option| { (format_option(option), option.description) })
+        .collect();
+
+    write_section(f, "Commands", &command_rows)?;
+    write_section(f, "Options", &option_rows)?;
+
+    write!(f, "\nRun tensort <command> --help for the arguments of a command.\n\nExample:\n  tensort sort /path/to/images_dir 5 -n")
+}
+
+impl Display for UsageFormatter
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.0 {
+            Some(command) => write_command_usage(f, command.spec()),
+            None => write_global_usage(f)
+        }
+    }
+}
+
+impl Display for InvalidUsageError
+{
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(
-            f,
-            "Error: {}\n\
-            \n\
-            Usage: tensort <target_dir> <class_count> [-n | --no-names]\n\
-             \n\
-             Arguments:\n\
-             <target_dir>    : Path to the target directory\n\
-             <class_count>   : Number of classes\n\
-             -n, --no-names  : Do not generate class names (optional)\n\
-             \n\
-             Example:\n\
-             tensort /path/to/images_dir 5 -n",
-             self.0
-        )
+        write!(f, "Error: {}\n\n{}", self.message, format_usage(self.command))
     }
 }
\ No newline at end of file